@@ -1,9 +1,25 @@
 use cgmath::{InnerSpace, Matrix4, PerspectiveFov, Point3, Rad, Vector3};
 
+use crate::physics::scene_query::{raycast, BoxCollider};
 use std::f32::consts::PI;
 
 const HALF_PI: f32 = PI / 2.0;
 
+// the feet are this far under the eye while walking
+pub const EYE_HEIGHT: f32 = 2.0;
+// how far from the ground the feet still stand on it
+pub const GROUND_CONTACT: f32 = 0.1;
+
+// the swimmer is a sphere under the eye, a bit lighter than the water so the eye floats above the
+// surface
+pub const SWIMMER_RADIUS: f32 = 0.5;
+const SWIMMER_DEPTH: f32 = 0.6;
+// relative to the water
+const SWIMMER_DENSITY: f32 = 0.9;
+// more than the gravity, so the swimmer can get out of the water
+const SWIM_ACCELERATION: f32 = 12.0;
+const SWIM_DRAG: f32 = 2.0;
+
 pub struct Camera {
     pub position: Point3<f32>,
     pub front: Vector3<f32>,
@@ -13,6 +29,13 @@ pub struct Camera {
     pub speed: f32,
     pub sensitivity: f32,
     pub fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    // the camera falls and stands on the colliders when it isn't flying
+    pub flying: bool,
+    pub swimming: bool,
+    // of the fall while walking and of the swimmer in the water
+    pub vertical_speed: f32,
 }
 
 impl Camera {
@@ -26,6 +49,11 @@ impl Camera {
             speed: 4.0,
             sensitivity: 0.005,
             fov: 0.8,
+            near_plane: 0.1,
+            far_plane: 1000.0,
+            flying: true,
+            swimming: false,
+            vertical_speed: 0.0,
         }
     }
 
@@ -37,8 +65,8 @@ impl Camera {
         PerspectiveFov {
             fovy: Rad(self.fov),
             aspect: aspect_ratio,
-            far: 1000.0,
-            near: 0.1,
+            far: self.far_plane,
+            near: self.near_plane,
         }
         .into()
     }
@@ -71,12 +99,48 @@ impl Camera {
         };
     }
 
+    pub fn look_at(&mut self, target: Point3<f32>) {
+        let direction = (target - self.position).normalize();
+        self.yaw = direction.z.atan2(direction.x);
+        self.pitch = direction.y.asin();
+        self.front = direction;
+    }
+
+    // third person camera used while driving, it lags a bit behind the target
+    pub fn follow(
+        &mut self,
+        target: Point3<f32>,
+        target_front: Vector3<f32>,
+        delta_time: std::time::Duration,
+    ) {
+        let flat_front = Vector3::new(target_front.x, 0.0, target_front.z);
+        let flat_front = if flat_front.magnitude2() > 1e-4 {
+            flat_front.normalize()
+        } else {
+            self.front
+        };
+
+        let desired_position = target - flat_front * 7.0 + self.up * 2.5;
+        let blend = 1.0 - (-4.0 * delta_time.as_secs_f32()).exp();
+        self.position += (desired_position - self.position) * blend;
+        self.look_at(target + self.up);
+    }
+
     pub fn handle_keys(&mut self, keys_pressed: [bool; 4], delta_time: std::time::Duration) {
         // todo: handle keys better
-        let delta_speed = self.speed * delta_time.as_secs_f32();
+        let mut delta_speed = self.speed * delta_time.as_secs_f32();
+        if self.swimming {
+            delta_speed *= 0.4;
+        }
+        // walking stays on the ground whatever way the camera looks
+        let front = if self.flying || self.swimming {
+            self.front
+        } else {
+            Vector3::new(self.front.x, 0.0, self.front.z).normalize()
+        };
         if keys_pressed[0] {
             // w
-            self.position += self.front * delta_speed;
+            self.position += front * delta_speed;
         }
         if keys_pressed[1] {
             // a
@@ -84,11 +148,80 @@ impl Camera {
         }
         if keys_pressed[2] {
             // s
-            self.position -= self.front * delta_speed;
+            self.position -= front * delta_speed;
         }
         if keys_pressed[3] {
             // d
             self.position += self.front.cross(self.up).normalize() * delta_speed;
         }
     }
+
+    pub fn get_swimmer_center(&self) -> Point3<f32> {
+        self.position - Vector3::new(0.0, SWIMMER_DEPTH, 0.0)
+    }
+
+    // the buoyancy of the part of the swimmer under water, the drag and the swim keys (up and
+    // down) move the camera vertically
+    pub fn handle_swimming(
+        &mut self,
+        submerged_fraction: f32,
+        swim_keys: [bool; 2],
+        gravity: Vector3<f32>,
+        delta_time: std::time::Duration,
+    ) {
+        if !self.swimming {
+            // out of the water it keeps falling while walking
+            if self.flying {
+                self.vertical_speed = 0.0;
+            }
+            return;
+        }
+
+        let mut acceleration = gravity.y * (1.0 - submerged_fraction / SWIMMER_DENSITY)
+            - self.vertical_speed * SWIM_DRAG;
+        if swim_keys[0] {
+            acceleration += SWIM_ACCELERATION;
+        }
+        if swim_keys[1] {
+            acceleration -= SWIM_ACCELERATION;
+        }
+
+        let delta_time = delta_time.as_secs_f32();
+        self.vertical_speed += acceleration * delta_time;
+        self.position.y += self.vertical_speed * delta_time;
+    }
+
+    pub fn get_feet(&self) -> Point3<f32> {
+        self.position - Vector3::new(0.0, EYE_HEIGHT, 0.0)
+    }
+
+    // falls until the ray from the eye to the feet hits one of the colliders, then the feet stay
+    // on top of it
+    pub fn handle_walking(
+        &mut self,
+        colliders: &[BoxCollider],
+        gravity: Vector3<f32>,
+        delta_time: std::time::Duration,
+    ) {
+        if self.flying || self.swimming {
+            return;
+        }
+
+        let delta_time = delta_time.as_secs_f32();
+        self.vertical_speed += gravity.y * delta_time;
+        let fall = (-self.vertical_speed * delta_time).max(0.0);
+        let ground = raycast(
+            colliders,
+            self.position,
+            Vector3::new(0.0, -1.0, 0.0),
+            EYE_HEIGHT + GROUND_CONTACT + fall,
+        );
+        match ground {
+            Some(hit) if self.vertical_speed <= 0.0 => {
+                self.position.y = hit.point.y + EYE_HEIGHT;
+                self.vertical_speed = 0.0;
+            }
+            _ => self.position.y += self.vertical_speed * delta_time,
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use cgmath::{Matrix4, Vector3};
+use glium::{Display, Surface, VertexBuffer};
+
+use crate::containers::container::ObjectContainer;
+use crate::physics::{
+    rigid_body::RigidBody,
+    scene_query::{Aabb, BoxCollider, MeshCollider},
+    sph::{SphSettings, SphSimulation},
+};
+use crate::shaders::{common::ParticleInstance, programs, sphere_shader::SphereShader};
+
+pub struct FluidContainer {
+    pub shader: SphereShader,
+    pub simulation: SphSimulation,
+    pub color: Vector3<f32>,
+    instance_buffer: VertexBuffer<ParticleInstance>,
+}
+
+pub struct FluidContainerDrawData<'a, 'b> {
+    pub projection_view: &'a Matrix4<f32>,
+    pub light_direction: &'b Vector3<f32>,
+}
+
+impl FluidContainer {
+    pub fn new(
+        display: &Display,
+        settings: SphSettings,
+        bounds: Aabb,
+        color: Vector3<f32>,
+        max_particles: usize,
+    ) -> Self {
+        Self {
+            shader: SphereShader::new(display, 8, 6),
+            simulation: SphSimulation::new(settings, bounds),
+            color,
+            instance_buffer: VertexBuffer::empty_dynamic(display, max_particles).unwrap(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+        bodies: &mut [&mut RigidBody],
+    ) {
+        self.simulation
+            .update(delta_time, colliders, mesh_colliders, bodies);
+
+        let particle_count = self
+            .simulation
+            .particle_count()
+            .min(self.instance_buffer.len());
+        let instances: Vec<ParticleInstance> = self.simulation.positions[..particle_count]
+            .iter()
+            .map(|position| ParticleInstance {
+                offset: [position.x, position.y, position.z],
+            })
+            .collect();
+        self.instance_buffer
+            .slice(0..particle_count)
+            .unwrap()
+            .write(&instances);
+    }
+}
+
+impl ObjectContainer<&programs::FluidParticleProgram, FluidContainerDrawData<'_, '_>>
+    for FluidContainer
+{
+    fn draw(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::FluidParticleProgram,
+        params: &glium::DrawParameters,
+        data: FluidContainerDrawData,
+    ) {
+        let particle_count = self
+            .simulation
+            .particle_count()
+            .min(self.instance_buffer.len());
+        if particle_count == 0 {
+            return;
+        }
+
+        let uniforms = programs::FluidParticleProgram::get_uniforms(
+            data.projection_view,
+            self.simulation.settings.particle_spacing * 0.5,
+            &self.color,
+            data.light_direction,
+        );
+
+        target
+            .draw(
+                (
+                    &self.shader.vertex_buffer,
+                    self.instance_buffer
+                        .slice(0..particle_count)
+                        .unwrap()
+                        .per_instance()
+                        .unwrap(),
+                ),
+                &self.shader.index_buffer,
+                &program.0,
+                &uniforms,
+                params,
+            )
+            .unwrap();
+    }
+}
@@ -1,3 +1,6 @@
 pub mod simple_containers;
 pub mod container;
-pub mod renderable_3d_object_container;
\ No newline at end of file
+pub mod renderable_3d_object_container;
+pub mod trajectory_preview_container;
+pub mod fluid_container;
+pub mod water_container;
\ No newline at end of file
@@ -1,11 +1,18 @@
 use crate::objects::renderable_3d_object::HasRenderable3dObject;
+use crate::physics::scene_query::MeshCollider;
 use crate::shaders::renderable_3d_object_shader::Renderable3dObjectShader;
-use cgmath::{Matrix4, Point3, Vector3};
-use glium::Surface;
+use cgmath::{Matrix4, Point3, Transform};
+use glium::{texture::Cubemap, Surface};
 
 use crate::shaders::{
-    common::{DirectionalLight, PointLight, SpotLight},
+    common::{ObjectMaterial, PbrMaterial, PointLight},
+    fog::Fog,
+    ibl::ImageBasedLighting,
+    lights::LightBuffer,
     programs,
+    reflection_probes::{EnvironmentMap, ReflectionProbes},
+    shadow_map::ShadowMaps,
+    ssao::Ssao,
 };
 
 pub struct Renderable3dObjectContainer<Obj: HasRenderable3dObject> {
@@ -13,12 +20,27 @@ pub struct Renderable3dObjectContainer<Obj: HasRenderable3dObject> {
     pub objects: Vec<Obj>,
 }
 
-pub struct Renderable3dObjectContainerDrawData<'a, 'b, 'c, 'd> {
+// the objects use the program of their material
+pub struct Renderable3dObjectContainerPrograms<'a, 'b> {
+    pub textured_object: &'a programs::SimpleTexturedObjectProgram,
+    pub pbr_object: &'b programs::PbrObjectProgram,
+}
+
+pub struct Renderable3dObjectContainerGBufferPrograms<'a, 'b> {
+    pub gbuffer: &'a programs::GBufferProgram,
+    pub pbr_gbuffer: &'b programs::PbrGBufferProgram,
+}
+
+pub struct Renderable3dObjectContainerDrawData<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h> {
     pub projection_view: &'a Matrix4<f32>,
     pub camera_pos: Point3<f32>,
-    pub spot_light: &'b SpotLight,
-    pub point_lights: &'c [&'d PointLight; 4],
-    pub directional_light_intensity: f32,
+    pub lights: &'b LightBuffer,
+    pub shadow_maps: &'c ShadowMaps,
+    pub ssao: &'d Ssao,
+    pub ibl: &'e ImageBasedLighting,
+    pub fog: &'f Fog,
+    pub environment: &'g Cubemap,
+    pub reflection_probes: Option<&'h ReflectionProbes>,
 }
 
 impl<Obj: HasRenderable3dObject> Renderable3dObjectContainer<Obj> {
@@ -33,42 +55,139 @@ impl<Obj: HasRenderable3dObject> Renderable3dObjectContainer<Obj> {
         }
     }
 
+    pub fn new_pbr(display: &glium::Display, model_path: &str, material: PbrMaterial) -> Self {
+        Self {
+            shader: Renderable3dObjectShader::new_pbr(display, model_path, material),
+            objects: Vec::new(),
+        }
+    }
+
+    // the light of an emissive material in the middle of every object
+    pub fn get_emissive_lights(&self) -> Vec<PointLight> {
+        match self.shader.material.get_emissive_light() {
+            Some(light) => self
+                .objects
+                .iter()
+                .map(|object| {
+                    let model_matrix = object.get_object().model_matrix;
+                    light.get_point_light(model_matrix.transform_point(self.shader.center))
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // the mesh of every object where it is now, they have to be made again after the objects move
+    pub fn get_mesh_colliders(&self) -> Vec<MeshCollider> {
+        self.objects
+            .iter()
+            .map(|object| {
+                let model_matrix = object.get_object().model_matrix;
+                MeshCollider::new(
+                    self.shader
+                        .triangles
+                        .iter()
+                        .map(|triangle| {
+                            let mut points = *triangle;
+                            for point in points.iter_mut() {
+                                *point = model_matrix.transform_point(*point);
+                            }
+                            points
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_objects(
         &self,
         target: &mut glium::framebuffer::SimpleFrameBuffer,
-        program: &programs::SimpleTexturedObjectProgram,
+        programs: Renderable3dObjectContainerPrograms,
         params: &glium::DrawParameters,
         projection_view: &Matrix4<f32>,
         camera_pos: Point3<f32>,
-        spot_light: &SpotLight,
-        lights: &[&PointLight; 4],
-        directional_light_intensity: f32,
+        lights: &LightBuffer,
+        shadow_maps: &ShadowMaps,
+        ssao: &Ssao,
+        ibl: &ImageBasedLighting,
+        fog: &Fog,
+        environment: &Cubemap,
+        reflection_probes: Option<&ReflectionProbes>,
     ) {
         for object in self.objects.iter() {
             let model_matrix = object.get_object().model_matrix;
             let matrix = projection_view * model_matrix;
 
-            let directional_light = {
-                let ambient = directional_light_intensity / 3.0;
-                let diffuse = directional_light_intensity;
-                let specular = directional_light_intensity * 0.4 + 0.4;
-                DirectionalLight {
-                    ambient: Vector3::new(ambient, ambient, ambient),
-                    diffuse: Vector3::new(diffuse, diffuse, diffuse),
-                    specular: Vector3::new(specular, specular, specular),
-                    direction: Vector3::new(-0.2, -1.0, -0.3),
+            match &self.shader.material {
+                ObjectMaterial::Phong(material) => {
+                    let position = object.get_object().position;
+                    let environment = reflection_probes.map_or(
+                        EnvironmentMap::new(environment),
+                        |probes| probes.get_environment_map(position, environment),
+                    );
+
+                    let uniforms = programs::SimpleTexturedObjectProgram::get_uniforms(
+                        &matrix,
+                        &model_matrix,
+                        material,
+                        lights,
+                        &camera_pos,
+                        shadow_maps,
+                        ssao,
+                        ibl,
+                        fog,
+                        environment,
+                    );
+
+                    target
+                        .draw(
+                            &self.shader.vertex_buffer,
+                            &self.shader.index_buffer,
+                            &programs.textured_object.0,
+                            &uniforms,
+                            params,
+                        )
+                        .unwrap();
                 }
-            };
-
-            let uniforms = programs::SimpleTexturedObjectProgram::get_uniforms(
-                &matrix,
-                &model_matrix,
-                &self.shader.material,
-                &directional_light,
-                spot_light,
-                &lights,
-                &camera_pos,
-            );
+                ObjectMaterial::Pbr(material) => {
+                    let uniforms = programs::PbrObjectProgram::get_uniforms(
+                        &matrix,
+                        &model_matrix,
+                        material,
+                        lights,
+                        &camera_pos,
+                        shadow_maps,
+                        ssao,
+                        ibl,
+                        fog,
+                    );
+
+                    target
+                        .draw(
+                            &self.shader.vertex_buffer,
+                            &self.shader.index_buffer,
+                            &programs.pbr_object.0,
+                            &uniforms,
+                            params,
+                        )
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn draw_objects_depth(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::ShadowDepthProgram,
+        params: &glium::DrawParameters,
+        light_space_matrix: &Matrix4<f32>,
+    ) {
+        for object in self.objects.iter() {
+            let matrix = light_space_matrix * object.get_object().model_matrix;
+            let uniforms = programs::ShadowDepthProgram::get_uniforms(&matrix);
 
             target
                 .draw(
@@ -81,22 +200,76 @@ impl<Obj: HasRenderable3dObject> Renderable3dObjectContainer<Obj> {
                 .unwrap();
         }
     }
+
+    pub fn draw_objects_gbuffer(
+        &self,
+        target: &mut glium::framebuffer::MultiOutputFrameBuffer,
+        programs: Renderable3dObjectContainerGBufferPrograms,
+        params: &glium::DrawParameters,
+        projection_view: &Matrix4<f32>,
+        camera_pos: Point3<f32>,
+    ) {
+        for object in self.objects.iter() {
+            let model_matrix = object.get_object().model_matrix;
+            let matrix = projection_view * model_matrix;
+
+            match &self.shader.material {
+                ObjectMaterial::Phong(material) => {
+                    let uniforms = programs::GBufferProgram::get_uniforms(
+                        &matrix,
+                        &model_matrix,
+                        material,
+                        &camera_pos,
+                    );
+
+                    target
+                        .draw(
+                            &self.shader.vertex_buffer,
+                            &self.shader.index_buffer,
+                            &programs.gbuffer.0,
+                            &uniforms,
+                            params,
+                        )
+                        .unwrap();
+                }
+                ObjectMaterial::Pbr(material) => {
+                    let uniforms =
+                        programs::PbrGBufferProgram::get_uniforms(&matrix, &model_matrix, material);
+
+                    target
+                        .draw(
+                            &self.shader.vertex_buffer,
+                            &self.shader.index_buffer,
+                            &programs.pbr_gbuffer.0,
+                            &uniforms,
+                            params,
+                        )
+                        .unwrap();
+                }
+            }
+        }
+    }
+
     pub fn draw(
         &self,
         target: &mut glium::framebuffer::SimpleFrameBuffer,
-        program: &programs::SimpleTexturedObjectProgram,
+        programs: Renderable3dObjectContainerPrograms,
         params: &glium::DrawParameters,
         data: Renderable3dObjectContainerDrawData,
     ) {
         self.draw_objects(
             target,
-            program,
+            programs,
             params,
             data.projection_view,
             data.camera_pos,
-            data.spot_light,
-            data.point_lights,
-            data.directional_light_intensity,
+            data.lights,
+            data.shadow_maps,
+            data.ssao,
+            data.ibl,
+            data.fog,
+            data.environment,
+            data.reflection_probes,
         );
     }
 }
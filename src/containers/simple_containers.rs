@@ -1,25 +1,69 @@
 use cgmath::{Matrix4, Point3, Vector3};
-use glium::Surface;
+use glium::{texture::Cubemap, Surface};
 
 use crate::containers::container::ObjectContainer;
-use crate::objects::simple_objects::{Cube, SimpleLightCube};
+use crate::objects::cart::Cart;
+use crate::objects::light_behaviour::LightTarget;
+use crate::objects::renderable_3d_object::Renderable3dObject;
+use crate::objects::simple_objects::{Cube, DynamicCube, KinematicCube, SimpleLightCube};
+use crate::physics::{
+    rigid_body::RigidBody,
+    scene_query::BoxCollider,
+    water::{apply_water_forces, SplashEvent, WaterVolume},
+};
 use crate::shaders::{
-    common::{DirectionalLight, SpotLight},
+    common::PointLight,
+    fog::Fog,
+    ibl::ImageBasedLighting,
+    lights::LightBuffer,
     programs,
+    reflection_probes::{EnvironmentMap, ReflectionProbes},
+    shadow_map::ShadowMaps,
     simple_shaders::CubeShader,
+    ssao::Ssao,
 };
 
+const LIGHT_CUBE_EMISSION: f32 = 4.0;
+
+// stays the same while other lights are added and removed, unlike the index of the light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightId(usize);
+
 pub struct CubeContainer {
     pub shader: CubeShader,
     pub cubes: Vec<Cube>,
-    pub light_cubes: [SimpleLightCube; 4],
+    // static cubes with the tiled wall material
+    pub wall_shader: CubeShader,
+    pub walls: Vec<Cube>,
+    // static cubes that show the skybox, see EnvironmentMapping
+    pub chrome_shader: CubeShader,
+    pub chrome_cubes: Vec<Cube>,
+    pub glass_shader: CubeShader,
+    pub glass_cubes: Vec<Cube>,
+    // static cubes with a glowing frame, each one also has a point light
+    pub neon_shader: CubeShader,
+    pub neon_cubes: Vec<Cube>,
+    pub kinematic_cubes: Vec<KinematicCube>,
+    pub dynamic_cubes: Vec<DynamicCube>,
+    pub carts: Vec<Cart>,
+    // the id of each light cube is at the same index in light_ids
+    light_cubes: Vec<SimpleLightCube>,
+    light_ids: Vec<LightId>,
+    next_light_id: usize,
 }
 
-pub struct CubeContainerDrawData<'a, 'b> {
+pub struct CubeContainerDrawData<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h> {
     pub projection_view: &'a Matrix4<f32>,
     pub camera_pos: Point3<f32>,
-    pub spot_light: &'b SpotLight,
-    pub t: f32,
+    pub lights: &'b LightBuffer,
+    pub shadow_maps: &'c ShadowMaps,
+    pub ssao: &'d Ssao,
+    pub ibl: &'e ImageBasedLighting,
+    pub fog: &'f Fog,
+    // the skybox, for the environment mapped materials that aren't close to a probe
+    pub environment: &'g Cubemap,
+    // none while the probes themselves are drawn
+    pub reflection_probes: Option<&'h ReflectionProbes>,
 }
 
 pub struct CubeContainerPrograms<'a, 'b> {
@@ -28,11 +72,61 @@ pub struct CubeContainerPrograms<'a, 'b> {
 }
 
 impl CubeContainer {
-    pub fn new(display: &glium::Display, lights: [SimpleLightCube; 4]) -> Self {
+    pub fn new(display: &glium::Display) -> Self {
         CubeContainer {
             shader: CubeShader::new(&display),
             cubes: Vec::new(),
-            light_cubes: lights,
+            wall_shader: CubeShader::new_wall(display),
+            walls: Vec::new(),
+            chrome_shader: CubeShader::new_chrome(display),
+            chrome_cubes: Vec::new(),
+            glass_shader: CubeShader::new_glass(display),
+            glass_cubes: Vec::new(),
+            neon_shader: CubeShader::new_neon(display),
+            neon_cubes: Vec::new(),
+            kinematic_cubes: Vec::new(),
+            dynamic_cubes: Vec::new(),
+            carts: Vec::new(),
+            light_cubes: Vec::new(),
+            light_ids: Vec::new(),
+            next_light_id: 0,
+        }
+    }
+
+    pub fn add_light_cube(&mut self, light_cube: SimpleLightCube) -> LightId {
+        let id = LightId(self.next_light_id);
+        self.next_light_id += 1;
+        self.light_cubes.push(light_cube);
+        self.light_ids.push(id);
+        id
+    }
+
+    pub fn remove_light_cube(&mut self, id: LightId) -> Option<SimpleLightCube> {
+        let index = self.light_ids.iter().position(|light_id| *light_id == id)?;
+        self.light_ids.remove(index);
+        Some(self.light_cubes.remove(index))
+    }
+
+    pub fn get_light_cube_mut(&mut self, id: LightId) -> Option<&mut SimpleLightCube> {
+        let index = self.light_ids.iter().position(|light_id| *light_id == id)?;
+        self.light_cubes.get_mut(index)
+    }
+
+    pub fn get_light_cubes(&self) -> impl Iterator<Item = (LightId, &SimpleLightCube)> {
+        self.light_ids.iter().copied().zip(self.light_cubes.iter())
+    }
+
+    // has to go after the objects the lights follow have moved
+    pub fn update_light_cubes(&mut self, delta_time: f32) {
+        let carts = &self.carts;
+        let kinematic_cubes = &self.kinematic_cubes;
+        let get_target_position = |target| match target {
+            LightTarget::Cart(i) => carts.get(i).map(|cart| cart.vehicle.chassis.position),
+            LightTarget::KinematicCube(i) => kinematic_cubes.get(i).map(|cube| cube.body.position),
+        };
+
+        for light_cube in self.light_cubes.iter_mut() {
+            light_cube.update(delta_time, &get_target_position);
         }
     }
 
@@ -52,53 +146,261 @@ impl CubeContainer {
         }
     }
 
+    pub fn update_kinematic_cubes(&mut self, delta_time: f32) {
+        for cube in self.kinematic_cubes.iter_mut() {
+            cube.update(delta_time);
+        }
+    }
+
+    pub fn update_dynamic_cubes(
+        &mut self,
+        delta_time: f32,
+        gravity: Vector3<f32>,
+        water_volumes: &[WaterVolume],
+    ) -> Vec<SplashEvent> {
+        let colliders = self.get_heavy_colliders();
+        let mut splashes = Vec::new();
+
+        for cube in self.dynamic_cubes.iter_mut() {
+            if let Some(splash) = apply_water_forces(water_volumes, &mut cube.body, None, gravity) {
+                splashes.push(splash);
+            }
+            cube.body.integrate(delta_time, gravity);
+            cube.body.resolve_contacts(&colliders);
+            cube.sync_object();
+        }
+
+        // forget about the ones that fell out of the world
+        self.dynamic_cubes
+            .retain(|cube| cube.body.position.y > -200.0);
+
+        splashes
+    }
+
+    pub fn update_carts(&mut self, delta_time: f32, gravity: Vector3<f32>) {
+        let colliders = self.get_solid_colliders();
+
+        for cart in self.carts.iter_mut() {
+            cart.vehicle.update(delta_time, gravity, &colliders);
+            cart.sync_objects();
+        }
+    }
+
+    pub fn get_colliders(&self) -> Vec<BoxCollider> {
+        let mut colliders = self.get_heavy_colliders();
+        colliders.extend(self.dynamic_cubes.iter().map(|cube| cube.body.get_collider()));
+        colliders
+    }
+
+    // everything except the dynamic cubes, they are too light to move any of it
+    pub fn get_heavy_colliders(&self) -> Vec<BoxCollider> {
+        let mut colliders = self.get_solid_colliders();
+        colliders.extend(self.carts.iter().map(|cart| cart.vehicle.chassis.get_collider()));
+        colliders
+    }
+
+    // the bodies of the dynamic cubes, for the things that push them around
+    pub fn get_dynamic_bodies_mut(&mut self) -> Vec<&mut RigidBody> {
+        self.dynamic_cubes
+            .iter_mut()
+            .map(|cube| &mut cube.body)
+            .collect()
+    }
+
+    // colliders that can't be moved by the dynamic cubes
+    fn get_solid_colliders(&self) -> Vec<BoxCollider> {
+        // the cube shape goes from -1.0 to 1.0 on every axis
+        self.cubes
+            .iter()
+            .chain(self.walls.iter())
+            .chain(self.chrome_cubes.iter())
+            .chain(self.glass_cubes.iter())
+            .chain(self.neon_cubes.iter())
+            .map(|cube| {
+                let half_extent = cube.object.scale;
+                BoxCollider::new_static(
+                    cube.object.position,
+                    Vector3::new(half_extent, half_extent, half_extent),
+                )
+            })
+            .chain(
+                self.kinematic_cubes
+                    .iter()
+                    .map(|cube| cube.body.get_collider()),
+            )
+            .collect()
+    }
+
+    // every object drawn with the cube shape and the shader with its material, except for the
+    // light cubes
+    fn get_objects(&self) -> impl Iterator<Item = (&CubeShader, &Renderable3dObject)> {
+        self.cubes
+            .iter()
+            .map(|cube| &cube.object)
+            .chain(self.kinematic_cubes.iter().map(|cube| &cube.object))
+            .chain(self.dynamic_cubes.iter().map(|cube| &cube.object))
+            .chain(self.carts.iter().flat_map(|cart| {
+                std::iter::once(&cart.chassis_object).chain(cart.wheel_objects.iter())
+            }))
+            .map(move |object| (&self.shader, object))
+            .chain(
+                self.walls
+                    .iter()
+                    .map(move |wall| (&self.wall_shader, &wall.object)),
+            )
+            .chain(
+                self.chrome_cubes
+                    .iter()
+                    .map(move |cube| (&self.chrome_shader, &cube.object)),
+            )
+            .chain(
+                self.glass_cubes
+                    .iter()
+                    .map(move |cube| (&self.glass_shader, &cube.object)),
+            )
+            .chain(
+                self.neon_cubes
+                    .iter()
+                    .map(move |cube| (&self.neon_shader, &cube.object)),
+            )
+    }
+
+    // the lights of the emissive materials, in the middle of each cube
+    pub fn get_emissive_lights(&self) -> Vec<PointLight> {
+        self.get_objects()
+            .filter_map(|(shader, object)| {
+                shader
+                    .material
+                    .emissive_light
+                    .as_ref()
+                    .map(|light| light.get_point_light(object.position))
+            })
+            .collect()
+    }
+
     pub fn draw_cubes(
         &self,
         target: &mut glium::framebuffer::SimpleFrameBuffer,
         program: &programs::SimpleTexturedObjectProgram,
         params: &glium::DrawParameters,
-        projection_view: &Matrix4<f32>,
-        camera_pos: Point3<f32>,
-        spot_light: &SpotLight,
-        t: f32,
+        data: &CubeContainerDrawData,
     ) {
-        for cube in self.cubes.iter() {
-            let matrix = projection_view * cube.object.model_matrix;
-
-            let directional_light = {
-                let ambient = t / 3.0;
-                let diffuse = t;
-                let specular = t * 0.4 + 0.4;
-                DirectionalLight {
-                    ambient: Vector3::new(ambient, ambient, ambient),
-                    diffuse: Vector3::new(diffuse, diffuse, diffuse),
-                    specular: Vector3::new(specular, specular, specular),
-                    direction: Vector3::new(-0.2, -1.0, -0.3),
-                }
-            };
+        self.draw_textured_objects(self.get_objects(), target, program, params, data);
+    }
 
-            let lights = [
-                &self.light_cubes[0].light,
-                &self.light_cubes[1].light,
-                &self.light_cubes[2].light,
-                &self.light_cubes[3].light,
-            ];
+    // the deferred lighting doesn't do the environment mapping, so these are drawn again over it
+    pub fn draw_environment_mapped_cubes(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::SimpleTexturedObjectProgram,
+        params: &glium::DrawParameters,
+        data: &CubeContainerDrawData,
+    ) {
+        let objects = self
+            .get_objects()
+            .filter(|(shader, _)| shader.material.environment.is_some());
+        self.draw_textured_objects(objects, target, program, params, data);
+    }
+
+    // the environment mapped cubes would see themselves in the probes, so they are left out
+    pub fn draw_reflection_probe_cubes(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::SimpleTexturedObjectProgram,
+        params: &glium::DrawParameters,
+        data: &CubeContainerDrawData,
+    ) {
+        let objects = self
+            .get_objects()
+            .filter(|(shader, _)| shader.material.environment.is_none());
+        self.draw_textured_objects(objects, target, program, params, data);
+    }
+
+    fn draw_textured_objects<'a>(
+        &self,
+        objects: impl Iterator<Item = (&'a CubeShader, &'a Renderable3dObject)>,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::SimpleTexturedObjectProgram,
+        params: &glium::DrawParameters,
+        data: &CubeContainerDrawData,
+    ) {
+        for (shader, object) in objects {
+            let matrix = data.projection_view * object.model_matrix;
+            let environment = data.reflection_probes.map_or(
+                EnvironmentMap::new(data.environment),
+                |probes| probes.get_environment_map(object.position, data.environment),
+            );
 
             let uniforms = programs::SimpleTexturedObjectProgram::get_uniforms(
                 &matrix,
-                &cube.object.model_matrix,
-                &self.shader.material,
-                &directional_light,
-                spot_light,
-                &lights,
-                &camera_pos,
+                &object.model_matrix,
+                &shader.material,
+                data.lights,
+                &data.camera_pos,
+                data.shadow_maps,
+                data.ssao,
+                data.ibl,
+                data.fog,
+                environment,
             );
 
             // draw cube
             target
                 .draw(
-                    &self.shader.vertex_buffer,
-                    &self.shader.index_buffer,
+                    &shader.vertex_buffer,
+                    shader.index_buffer,
+                    &program.0,
+                    &uniforms,
+                    params,
+                )
+                .unwrap();
+        }
+    }
+
+    pub fn draw_cubes_depth(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::ShadowDepthProgram,
+        params: &glium::DrawParameters,
+        light_space_matrix: &Matrix4<f32>,
+    ) {
+        for (shader, object) in self.get_objects() {
+            let matrix = light_space_matrix * object.model_matrix;
+            let uniforms = programs::ShadowDepthProgram::get_uniforms(&matrix);
+
+            target
+                .draw(
+                    &shader.vertex_buffer,
+                    shader.index_buffer,
+                    &program.0,
+                    &uniforms,
+                    params,
+                )
+                .unwrap();
+        }
+    }
+
+    pub fn draw_cubes_gbuffer(
+        &self,
+        target: &mut glium::framebuffer::MultiOutputFrameBuffer,
+        program: &programs::GBufferProgram,
+        params: &glium::DrawParameters,
+        projection_view: &Matrix4<f32>,
+        camera_pos: Point3<f32>,
+    ) {
+        for (shader, object) in self.get_objects() {
+            let matrix = projection_view * object.model_matrix;
+            let uniforms = programs::GBufferProgram::get_uniforms(
+                &matrix,
+                &object.model_matrix,
+                &shader.material,
+                &camera_pos,
+            );
+
+            target
+                .draw(
+                    &shader.vertex_buffer,
+                    shader.index_buffer,
                     &program.0,
                     &uniforms,
                     params,
@@ -117,10 +419,9 @@ impl CubeContainer {
         for light_cube in self.light_cubes.iter() {
             let matrix = projection_view * light_cube.object.model_matrix;
 
-            let uniforms = programs::SimpleLightObjectProgram::get_uniforms(
-                &matrix,
-                &light_cube.light.diffuse,
-            );
+            // brighter than what they light, so they glow with the bloom
+            let color = light_cube.light.diffuse * LIGHT_CUBE_EMISSION;
+            let uniforms = programs::SimpleLightObjectProgram::get_uniforms(&matrix, &color);
 
             target
                 .draw(
@@ -135,7 +436,7 @@ impl CubeContainer {
     }
 }
 
-impl<'a> ObjectContainer<CubeContainerPrograms<'_, '_>, CubeContainerDrawData<'_, '_>>
+impl<'a> ObjectContainer<CubeContainerPrograms<'_, '_>, CubeContainerDrawData<'_, '_, '_, '_, '_, '_, '_, '_>>
     for CubeContainer
 {
     fn draw(
@@ -145,15 +446,7 @@ impl<'a> ObjectContainer<CubeContainerPrograms<'_, '_>, CubeContainerDrawData<'_
         params: &glium::DrawParameters,
         data: CubeContainerDrawData,
     ) {
-        self.draw_cubes(
-            target,
-            programs.cube,
-            params,
-            data.projection_view,
-            data.camera_pos,
-            data.spot_light,
-            data.t,
-        );
+        self.draw_cubes(target, programs.cube, params, &data);
         self.draw_light_cubes(target, programs.light_cube, params, data.projection_view);
     }
 }
@@ -0,0 +1,139 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use glium::{Display, Surface, VertexBuffer};
+
+use crate::containers::container::ObjectContainer;
+use crate::physics::{
+    scene_query::{BoxCollider, MeshCollider},
+    trajectory::Trajectory,
+};
+use crate::shaders::{common::PositionalVertex, programs};
+
+const TIME_STEP: f32 = 0.05;
+const MAX_STEPS: usize = 120;
+
+pub struct TrajectoryPreviewContainer {
+    pub enabled: bool,
+    pub launch_speed: f32,
+    pub trajectory: Trajectory,
+    pub path_color: Vector3<f32>,
+    pub marker_color: Vector3<f32>,
+    vertex_buffer: VertexBuffer<PositionalVertex>,
+}
+
+impl TrajectoryPreviewContainer {
+    pub fn new(display: &Display, launch_speed: f32) -> Self {
+        Self {
+            enabled: false,
+            launch_speed,
+            trajectory: Trajectory {
+                points: Vec::new(),
+                hit: None,
+            },
+            path_color: Vector3::new(1.0, 1.0, 1.0),
+            marker_color: Vector3::new(1.0, 0.2, 0.1),
+            // one extra slot for the impact marker
+            vertex_buffer: VertexBuffer::empty_dynamic(display, MAX_STEPS + 2).unwrap(),
+        }
+    }
+
+    // get_acceleration is the one of Trajectory::predict
+    pub fn update(
+        &mut self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        get_acceleration: impl Fn(Point3<f32>, Vector3<f32>) -> Vector3<f32>,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        self.trajectory = Trajectory::predict(
+            origin,
+            direction.normalize() * self.launch_speed,
+            get_acceleration,
+            colliders,
+            mesh_colliders,
+            TIME_STEP,
+            MAX_STEPS,
+        );
+
+        let mut vertices: Vec<PositionalVertex> = self
+            .trajectory
+            .points
+            .iter()
+            .map(|point| PositionalVertex {
+                position: [point.x, point.y, point.z],
+            })
+            .collect();
+        if let Some(hit) = self.trajectory.hit {
+            // lift the marker a bit from the surface so it doesn't z-fight
+            let marker = hit.point + hit.normal * 0.02;
+            vertices.push(PositionalVertex {
+                position: [marker.x, marker.y, marker.z],
+            });
+        }
+
+        self.vertex_buffer
+            .slice(0..vertices.len())
+            .unwrap()
+            .write(&vertices);
+    }
+}
+
+impl ObjectContainer<&programs::SimpleLightObjectProgram, &Matrix4<f32>>
+    for TrajectoryPreviewContainer
+{
+    fn draw(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::SimpleLightObjectProgram,
+        params: &glium::DrawParameters,
+        projection_view: &Matrix4<f32>,
+    ) {
+        let points_count = self.trajectory.points.len();
+        if !self.enabled || points_count < 2 {
+            return;
+        }
+
+        // every other step of the path is a dash, the first point is skipped because it is right
+        // in front of the camera
+        let dashes_end = 1 + (points_count - 1) / 2 * 2;
+        if dashes_end > 1 {
+            target
+                .draw(
+                    self.vertex_buffer.slice(1..dashes_end).unwrap(),
+                    glium::index::NoIndices(glium::index::PrimitiveType::LinesList),
+                    &program.0,
+                    &programs::SimpleLightObjectProgram::get_uniforms(
+                        projection_view,
+                        &self.path_color,
+                    ),
+                    params,
+                )
+                .unwrap();
+        }
+
+        if self.trajectory.hit.is_some() {
+            let marker_params = glium::DrawParameters {
+                point_size: Some(14.0),
+                ..params.clone()
+            };
+            target
+                .draw(
+                    self.vertex_buffer
+                        .slice(points_count..points_count + 1)
+                        .unwrap(),
+                    glium::index::NoIndices(glium::index::PrimitiveType::Points),
+                    &program.0,
+                    &programs::SimpleLightObjectProgram::get_uniforms(
+                        projection_view,
+                        &self.marker_color,
+                    ),
+                    &marker_params,
+                )
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use cgmath::{EuclideanSpace, Matrix4};
+use glium::{Display, IndexBuffer, Surface, VertexBuffer};
+
+use crate::containers::container::ObjectContainer;
+use crate::physics::water::WaterVolume;
+use crate::shaders::{common::PositionalVertex, cubemap::CubeMapShader, programs};
+
+pub struct WaterContainer {
+    pub volumes: Vec<WaterVolume>,
+    pub color: [f32; 4],
+    vertex_buffer: VertexBuffer<PositionalVertex>,
+    index_buffer: IndexBuffer<u16>,
+}
+
+impl WaterContainer {
+    pub fn new(display: &Display) -> Self {
+        Self {
+            volumes: Vec::new(),
+            color: [0.1, 0.35, 0.6, 0.45],
+            // same -1.0 to 1.0 box used by the skybox
+            vertex_buffer: CubeMapShader::create_vertex_buffer(display),
+            index_buffer: CubeMapShader::create_index_buffer(display),
+        }
+    }
+}
+
+impl ObjectContainer<&programs::WaterProgram, &Matrix4<f32>> for WaterContainer {
+    fn draw(
+        &self,
+        target: &mut glium::framebuffer::SimpleFrameBuffer,
+        program: &programs::WaterProgram,
+        params: &glium::DrawParameters,
+        projection_view: &Matrix4<f32>,
+    ) {
+        // translucent, so it goes after everything else and doesn't write depth
+        let params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            depth: glium::Depth {
+                write: false,
+                ..params.depth
+            },
+            ..params.clone()
+        };
+
+        for volume in self.volumes.iter() {
+            let center = volume.bounds.min.midpoint(volume.bounds.max);
+            let half_extent = (volume.bounds.max - volume.bounds.min) * 0.5;
+            let model_matrix = Matrix4::from_translation(center.to_vec())
+                * Matrix4::from_nonuniform_scale(half_extent.x, half_extent.y, half_extent.z);
+            let matrix = projection_view * model_matrix;
+
+            target
+                .draw(
+                    &self.vertex_buffer,
+                    &self.index_buffer,
+                    &program.0,
+                    &programs::WaterProgram::get_uniforms(&matrix, &self.color),
+                    &params,
+                )
+                .unwrap();
+        }
+    }
+}
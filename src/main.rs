@@ -4,30 +4,62 @@ extern crate cgmath;
 extern crate image;
 extern crate num_traits;
 
-use crate::containers::renderable_3d_object_container::{Renderable3dObjectContainer, Renderable3dObjectContainerDrawData};
+use crate::containers::renderable_3d_object_container::{
+    Renderable3dObjectContainer, Renderable3dObjectContainerDrawData,
+    Renderable3dObjectContainerGBufferPrograms, Renderable3dObjectContainerPrograms,
+};
 use crate::objects::kakyoin::Kakyoin;
-use cgmath::{Euler, Point3, Rad, Vector3};
+use crate::objects::material_ball::MaterialBall;
+use cgmath::{Euler, InnerSpace, Point3, Rad, Vector3};
+use physics::{
+    kinematic::KinematicPath,
+    scene_query::{Aabb, MeshCollider},
+    sph::SphSettings,
+    water::{HullSamples, WaterVolume},
+};
 use glium::{glutin, Surface};
 use glutin::event::WindowEvent;
+use std::rc::Rc;
 
 mod camera;
 mod common;
 mod containers;
 mod objects;
+mod physics;
 mod shaders;
 
-use camera::Camera;
+use camera::{Camera, GROUND_CONTACT, SWIMMER_RADIUS};
 use containers::{
     container::ObjectContainer,
-    simple_containers::{CubeContainer, CubeContainerDrawData, CubeContainerPrograms},
+    fluid_container::{FluidContainer, FluidContainerDrawData},
+    simple_containers::{CubeContainer, CubeContainerDrawData, CubeContainerPrograms, LightId},
+    trajectory_preview_container::TrajectoryPreviewContainer,
+    water_container::WaterContainer,
 };
-use objects::simple_objects::SimpleLightCube;
+use objects::cart::Cart;
+use objects::light_behaviour::{LightBehaviour, LightTarget};
+use objects::simple_objects::{Cube, DynamicCube, KinematicCube, SimpleLightCube};
 use shaders::{
-    common::{PointLight, SpotLight},
+    common::{
+        load_srgb_texture, load_texture, EnvironmentMapping,
+        EnvironmentMappingMode, PbrMaterial, PointLight, SpotLight,
+    },
+    bloom::{Bloom, BloomPrograms},
+    deferred::{DeferredLightingDrawData, DeferredLightingPrograms, DeferredRenderer},
+    fog::Fog,
+    ibl::{IblPrograms, ImageBasedLighting},
+    lights::LightBuffer,
     programs,
     programs::PostProcessingEffects,
+    reflection_probes::ReflectionProbes,
+    shadow_map::{
+        create_flashlight_cookie, DirectionalShadowMap, PointShadowMaps, ShadowMaps, SpotShadowMaps,
+    },
+    sky::{DayNightCycle, SkyCubemap},
+    ssao::{Ssao, SsaoPrograms},
+    tone_mapping::{ToneMapping, ToneMappingPrograms},
+    volumetric_light::{VolumetricLight, VolumetricLightPrograms},
 };
-use common::ToArray;
 
 struct Mouse {
     delta_x: f32,
@@ -38,9 +70,31 @@ struct Mouse {
 
 struct Programs {
     textured_object: programs::SimpleTexturedObjectProgram,
+    pbr_object: programs::PbrObjectProgram,
     light_object: programs::SimpleLightObjectProgram,
     main_framebuffer: programs::MainFramebufferProgram,
     skybox: programs::SkyBoxProgram,
+    water: programs::WaterProgram,
+    fluid_particle: programs::FluidParticleProgram,
+    shadow_depth: programs::ShadowDepthProgram,
+    gbuffer: programs::GBufferProgram,
+    pbr_gbuffer: programs::PbrGBufferProgram,
+    deferred_lighting: programs::DeferredLightingProgram,
+    deferred_point_light: programs::DeferredPointLightProgram,
+    luminance: programs::LuminanceProgram,
+    downsample: programs::DownsampleProgram,
+    exposure_adaptation: programs::ExposureAdaptationProgram,
+    bloom_bright_pass: programs::BloomBrightPassProgram,
+    bloom_downsample: programs::BloomDownsampleProgram,
+    bloom_upsample: programs::BloomUpsampleProgram,
+    ssao: programs::SsaoProgram,
+    ssao_blur: programs::SsaoBlurProgram,
+    volumetric_light: programs::VolumetricLightProgram,
+    volumetric_light_temporal: programs::VolumetricLightTemporalProgram,
+    volumetric_light_composite: programs::VolumetricLightCompositeProgram,
+    ibl_irradiance: programs::IblIrradianceProgram,
+    ibl_prefilter: programs::IblPrefilterProgram,
+    ibl_brdf: programs::IblBrdfProgram,
 }
 
 
@@ -56,7 +110,8 @@ fn main() {
         glium::Display::new(wb, cb, &event_loop).unwrap()
     };
 
-    let mut cube_container = {
+    // the white lights are toggled with 1-4
+    let (mut cube_container, shadow_lights) = {
         let positions = [
             Point3::new(1.0, 2.0, 3.0),
             Point3::new(4.0, 2.0, 7.0),
@@ -64,71 +119,172 @@ fn main() {
             Point3::new(-5.1, 2.0, -3.1),
         ];
 
-        let light_cubes = [
-            SimpleLightCube::new(
-                Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
-                0.2,
-                PointLight {
-                    position: positions[0],
-                    ambient: Vector3::new(0.002, 0.002, 0.002),
-                    diffuse: Vector3::new(1.0, 1.0, 1.0),
-                    specular: Vector3::new(1.0, 1.0, 1.0),
-                    constant: 1.0,
-                    linear: 0.045,
-                    quadratic: 0.0075,
-                },
-            ),
-            SimpleLightCube::new(
-                Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
-                0.2,
-                PointLight {
-                    position: positions[1],
+        // the four white lights that cast shadows, the third one pulses slowly
+        let mut light_cubes: Vec<SimpleLightCube> = positions
+            .iter()
+            .map(|position| {
+                SimpleLightCube::new(
+                    Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+                    0.2,
+                    PointLight {
+                        position: *position,
 
-                    ambient: Vector3::new(0.002, 0.002, 0.002),
-                    diffuse: Vector3::new(1.0, 1.0, 1.0),
-                    specular: Vector3::new(1.0, 1.0, 1.0),
+                        ambient: Vector3::new(0.002, 0.002, 0.002),
+                        diffuse: Vector3::new(1.0, 1.0, 1.0),
+                        specular: Vector3::new(1.0, 1.0, 1.0),
 
-                    constant: 1.0,
-                    linear: 0.045,
-                    quadratic: 0.0075,
-                },
-            ),
-            SimpleLightCube::new(
-                Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
-                0.2,
-                PointLight {
-                    position: positions[2],
-
-                    ambient: Vector3::new(0.002, 0.002, 0.002),
-                    diffuse: Vector3::new(1.0, 1.0, 1.0),
-                    specular: Vector3::new(1.0, 1.0, 1.0),
+                        constant: 1.0,
+                        linear: 0.045,
+                        quadratic: 0.0075,
+                        casts_shadows: true,
+                    },
+                )
+            })
+            .collect();
+        light_cubes[2].behaviours.push(LightBehaviour::Pulse {
+            frequency: 0.25,
+            amount: 0.6,
+        });
 
-                    constant: 1.0,
-                    linear: 0.045,
-                    quadratic: 0.0075,
-                },
-            ),
-            SimpleLightCube::new(
+        // small coloured lights around the carts, they don't cast shadows
+        let colors = [
+            Vector3::new(1.0, 0.2, 0.1),
+            Vector3::new(1.0, 0.7, 0.1),
+            Vector3::new(0.3, 1.0, 0.2),
+            Vector3::new(0.1, 0.8, 1.0),
+            Vector3::new(0.3, 0.2, 1.0),
+            Vector3::new(1.0, 0.2, 0.8),
+        ];
+        for (i, color) in colors.iter().enumerate() {
+            let mut light_cube = SimpleLightCube::new(
                 Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
-                0.2,
+                0.1,
                 PointLight {
-                    position: positions[3],
+                    position: Point3::new(-27.0 + i as f32 * 6.0, -7.0, 16.0),
 
-                    ambient: Vector3::new(0.002, 0.002, 0.002),
-                    diffuse: Vector3::new(1.0, 1.0, 1.0),
-                    specular: Vector3::new(1.0, 1.0, 1.0),
+                    ambient: Vector3::new(0.0, 0.0, 0.0),
+                    diffuse: color * 2.0,
+                    specular: *color,
 
                     constant: 1.0,
-                    linear: 0.045,
-                    quadratic: 0.0075,
+                    linear: 0.22,
+                    quadratic: 0.2,
+                    casts_shadows: false,
                 },
-            ),
-        ];
-        CubeContainer::new(&display, light_cubes)
+            );
+            // a few of them flicker, change colour, circle around or ride along
+            light_cube.behaviours = match i {
+                0 | 1 => vec![LightBehaviour::Flicker {
+                    speed: 8.0,
+                    amount: 0.5,
+                    seed: i as f32,
+                }],
+                2 => vec![LightBehaviour::ColorCycle { speed: 0.1 }],
+                3 => vec![LightBehaviour::Orbit {
+                    center: light_cube.base_light.position,
+                    radius: 1.5,
+                    speed: 1.0,
+                }],
+                4 => vec![LightBehaviour::Follow {
+                    target: LightTarget::Cart(0),
+                    offset: Vector3::new(0.0, 1.5, 0.0),
+                }],
+                // under the elevator
+                5 => vec![LightBehaviour::Follow {
+                    target: LightTarget::KinematicCube(0),
+                    offset: Vector3::new(0.0, -1.2, 0.0),
+                }],
+                _ => Vec::new(),
+            };
+            light_cubes.push(light_cube);
+        }
+
+        let mut cube_container = CubeContainer::new(&display);
+        let light_ids: Vec<LightId> = light_cubes
+            .into_iter()
+            .map(|light_cube| cube_container.add_light_cube(light_cube))
+            .collect();
+        (cube_container, light_ids[..positions.len()].to_vec())
     };
     cube_container.generate_cubes();
+    // ground for the carts, the top is at y = -8.0
+    cube_container.cubes.push(Cube::from_full(
+        Point3::new(0.0, -58.0, 0.0),
+        Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+        50.0,
+    ));
+    // tiled wall behind the cubes
+    for x in (-10..=10).step_by(2) {
+        for y in [-7.0, -5.0].iter() {
+            cube_container
+                .walls
+                .push(Cube::new(Point3::new(x as f32, *y, -12.0)));
+        }
+    }
+    // next to the material balls
+    cube_container
+        .chrome_cubes
+        .push(Cube::new(Point3::new(1.0, -7.0, -9.0)));
+    cube_container
+        .glass_cubes
+        .push(Cube::new(Point3::new(4.0, -7.0, -9.0)));
+    cube_container
+        .neon_cubes
+        .push(Cube::new(Point3::new(7.0, -7.0, -9.0)));
+    cube_container.kinematic_cubes = vec![
+        // elevator
+        KinematicCube::new(
+            KinematicPath::PingPong {
+                from: Point3::new(-2.0, -4.0, 2.0),
+                to: Point3::new(-2.0, 6.0, 2.0),
+                period: 10.0,
+            },
+            0.9,
+        ),
+        // rotating obstacle
+        KinematicCube::new(
+            KinematicPath::Spin {
+                center: Point3::new(2.0, -4.0, 2.0),
+                axis: Vector3::new(0.0, 1.0, 0.0),
+                angular_speed: 1.0,
+            },
+            0.7,
+        ),
+        // sliding wall
+        KinematicCube::new(
+            KinematicPath::PingPong {
+                from: Point3::new(-5.0, -2.0, -2.0),
+                to: Point3::new(5.0, -2.0, -2.0),
+                period: 6.0,
+            },
+            0.9,
+        ),
+    ];
+    // floating crates
+    cube_container.dynamic_cubes = vec![
+        DynamicCube::new(Point3::new(10.0, 4.0, -2.0), 0.5, 500.0),
+        DynamicCube::new(Point3::new(13.0, 6.0, 0.0), 0.5, 500.0),
+        DynamicCube::new(Point3::new(16.0, 5.0, 2.0), 0.5, 500.0),
+        // on the top cube under the fluid, the fluid washes it off
+        DynamicCube::new(Point3::new(-0.5, 5.5, -0.5), 0.5, 500.0),
+    ];
+    cube_container.carts = vec![
+        Cart::new(Point3::new(-10.0, -7.0, 8.0)),
+        Cart::new(Point3::new(-14.0, -7.0, 8.0)),
+    ];
+    // the lights added with f3, f4 removes the last one
+    let mut added_lights: Vec<LightId> = Vec::new();
     println!("Created cubes");
 
+    let mut water_container = WaterContainer::new(&display);
+    water_container.volumes.push(WaterVolume::new(
+        Aabb {
+            min: Point3::new(8.0, -8.0, -5.0),
+            max: Point3::new(18.0, -2.0, 5.0),
+        },
+        Vector3::new(0.0, 0.0, 0.2),
+    ));
+
     let mut kakyoin_container: Renderable3dObjectContainer<Kakyoin> = 
         Renderable3dObjectContainer::new(
             &display, 
@@ -137,8 +293,69 @@ fn main() {
         );
 
     kakyoin_container.objects.push(Kakyoin::new(Point3::new(5.0, 2.0, 10.0)));
+    // falls into the pool, the points of its buoyancy are found inside the mesh once
+    let kakyoin_hull = HullSamples::new(
+        &MeshCollider::new(kakyoin_container.shader.triangles.clone()),
+        8,
+    );
+    kakyoin_container.objects.push(Kakyoin::new_floating(
+        Point3::new(12.0, 0.0, -3.0),
+        &kakyoin_hull,
+        500.0,
+    ));
+
+    let mut chrome_kakyoin_container: Renderable3dObjectContainer<Kakyoin> =
+        Renderable3dObjectContainer::new(
+            &display,
+            "./assets/objects/kakyoin/kakyoin.obj",
+            &include_bytes!("../assets/objects/kakyoin/Kakyoin.png"),
+        );
+    chrome_kakyoin_container
+        .shader
+        .set_environment_mapping(Some(EnvironmentMapping {
+            mode: EnvironmentMappingMode::Reflective,
+            reflectivity: 0.8,
+            reflectivity_from_specular: false,
+            ior: 1.0,
+            tint: Vector3::new(1.0, 1.0, 1.0),
+        }));
+    chrome_kakyoin_container
+        .objects
+        .push(Kakyoin::new(Point3::new(-5.0, 2.0, 10.0)));
     println!("Loaded kakyoins");
 
+    // gold and painted segments, smooth at the top and rough at the bottom
+    let mut material_ball_container: Renderable3dObjectContainer<MaterialBall> =
+        Renderable3dObjectContainer::new_pbr(
+            &display,
+            "./assets/objects/material_ball/material_ball.obj",
+            PbrMaterial {
+                base_color: load_srgb_texture(
+                    &display,
+                    &include_bytes!("../assets/objects/material_ball/base_color.png"),
+                    image::ImageFormat::Png,
+                ),
+                metallic_roughness: load_texture(
+                    &display,
+                    &include_bytes!("../assets/objects/material_ball/metallic_roughness.png"),
+                    image::ImageFormat::Png,
+                ),
+                ..PbrMaterial::new(&display)
+            },
+        );
+    material_ball_container.objects.push(MaterialBall::new(Point3::new(-6.0, -7.0, -9.0)));
+    material_ball_container.objects.push(MaterialBall::from_full(
+        Point3::new(-3.0, -7.0, -9.0),
+        Euler::new(Rad(0.0), Rad(std::f32::consts::FRAC_PI_4), Rad(0.0)),
+        1.0,
+    ));
+    // on the floor of the fluid, between the cubes
+    material_ball_container.objects.push(MaterialBall::new(Point3::new(-2.0, -5.0, -2.0)));
+
+    let mut trajectory_preview = TrajectoryPreviewContainer::new(&display, 15.0);
+    // of the thrown crates, the preview follows them through the water
+    let thrown_density = 500.0;
+
     let main_framebuffer_shader =
         crate::shaders::main_framebuffer_shader::MainFramebufferShader::new(&display);
 
@@ -157,18 +374,42 @@ fn main() {
 
     let mut programs = Programs {
         textured_object: programs::SimpleTexturedObjectProgram::new(&display),
+        pbr_object: programs::PbrObjectProgram::new(&display),
         light_object: programs::SimpleLightObjectProgram::new(&display),
         main_framebuffer: programs::MainFramebufferProgram::new(
             &display,
             &post_processing_effects[selected_post_processing_effect_i],
         ),
         skybox: programs::SkyBoxProgram::new(&display),
+        water: programs::WaterProgram::new(&display),
+        fluid_particle: programs::FluidParticleProgram::new(&display),
+        shadow_depth: programs::ShadowDepthProgram::new(&display),
+        gbuffer: programs::GBufferProgram::new(&display),
+        pbr_gbuffer: programs::PbrGBufferProgram::new(&display),
+        deferred_lighting: programs::DeferredLightingProgram::new(&display),
+        deferred_point_light: programs::DeferredPointLightProgram::new(&display),
+        luminance: programs::LuminanceProgram::new(&display),
+        downsample: programs::DownsampleProgram::new(&display),
+        exposure_adaptation: programs::ExposureAdaptationProgram::new(&display),
+        bloom_bright_pass: programs::BloomBrightPassProgram::new(&display),
+        bloom_downsample: programs::BloomDownsampleProgram::new(&display),
+        bloom_upsample: programs::BloomUpsampleProgram::new(&display),
+        ssao: programs::SsaoProgram::new(&display),
+        ssao_blur: programs::SsaoBlurProgram::new(&display),
+        volumetric_light: programs::VolumetricLightProgram::new(&display),
+        volumetric_light_temporal: programs::VolumetricLightTemporalProgram::new(&display),
+        volumetric_light_composite: programs::VolumetricLightCompositeProgram::new(&display),
+        ibl_irradiance: programs::IblIrradianceProgram::new(&display),
+        ibl_prefilter: programs::IblPrefilterProgram::new(&display),
+        ibl_brdf: programs::IblBrdfProgram::new(&display),
     };
     println!("Loaded Programs");
 
     // GAME VARIABLES
     let mut camera = Camera::new(Point3::new(0.0, 0.0, 3.0));
 
+    // b takes it off the flashlight and puts it back
+    let flashlight_cookie = Rc::new(create_flashlight_cookie(&display, 256));
     let mut spot_light = SpotLight {
         position: camera.position,
         direction: camera.front,
@@ -178,8 +419,36 @@ fn main() {
         ambient: Vector3::new(0.02, 0.02, 0.02),
         diffuse: Vector3::new(1.0, 1.0, 1.0),
         specular: Vector3::new(1.0, 1.0, 1.0),
+
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+        casts_shadows: true,
+        cookie: Some(flashlight_cookie.clone()),
     };
 
+    // street lamps over the carts, only the first one casts shadows
+    let lamps: Vec<SpotLight> = [Point3::new(-8.0, -2.0, 10.0), Point3::new(-18.0, -2.0, 10.0)]
+        .iter()
+        .enumerate()
+        .map(|(i, position)| SpotLight {
+            position: *position,
+            direction: Vector3::new(0.0, -1.0, -0.2),
+            cut_off: 0.9,
+            outer_cut_off: 0.8,
+
+            ambient: Vector3::new(0.0, 0.0, 0.0),
+            diffuse: Vector3::new(1.0, 0.85, 0.6),
+            specular: Vector3::new(1.0, 0.85, 0.6),
+
+            constant: 1.0,
+            linear: 0.09,
+            quadratic: 0.032,
+            casts_shadows: i == 0,
+            cookie: None,
+        })
+        .collect();
+
     let mut projection_matrix = camera.get_projection_matrix(get_aspect_ratio(&display));
 
     let mut mouse = Mouse {
@@ -190,8 +459,80 @@ fn main() {
     };
 
     let mut pressed_keys = [false; 4];
+    // space and left control, up and down while swimming
+    let mut swim_keys = [false; 2];
+    // index of the cart being driven
+    let mut driven_cart: Option<usize> = None;
 
-    let mut directional_light_intensity: f32 = 0.5;
+    let gravity = physics::default_gravity();
+
+    let fluid_spawn_block = Aabb {
+        min: Point3::new(-3.0, 6.0, -3.0),
+        max: Point3::new(0.0, 9.0, 0.0),
+    };
+    let mut fluid_is_goo = false;
+    let mut fluid_container = FluidContainer::new(
+        &display,
+        SphSettings::water(gravity),
+        Aabb {
+            min: Point3::new(-6.0, -6.0, -6.0),
+            max: Point3::new(6.0, 12.0, 6.0),
+        },
+        Vector3::new(0.1, 0.3, 0.8),
+        4096,
+    );
+    fluid_container.simulation.add_block(fluid_spawn_block);
+    // the material balls don't move, the fluid and the trajectory preview hit them
+    let mesh_colliders = material_ball_container.get_mesh_colliders();
+
+    let mut shadow_maps = ShadowMaps {
+        // resolution and filter radius in texels
+        directional: DirectionalShadowMap::new(&display, 2048, 1),
+        // shadow slots, cubemap resolution and how many of them get rendered every frame
+        point: PointShadowMaps::new(&display, 4, 512, 2),
+        // the slots are shared by the spot lights with shadows or a cookie
+        spot: SpotShadowMaps::new(&display, 2, 1024, 1),
+    };
+    let mut light_buffer = LightBuffer::new(&display);
+    let mut deferred_renderer = DeferredRenderer::new(&display, display.get_framebuffer_dimensions());
+    let mut tone_mapping = ToneMapping::new(&display);
+    let mut bloom = Bloom::new(&display, display.get_framebuffer_dimensions());
+    let mut ssao = Ssao::new(&display, display.get_framebuffer_dimensions());
+    let mut volumetric_light =
+        VolumetricLight::new(&display, display.get_framebuffer_dimensions());
+    // the maps come from the skybox or the sky once the first frame knows the sky mode
+    let mut ibl = ImageBasedLighting::new(
+        &display,
+        &IblPrograms {
+            irradiance: &programs.ibl_irradiance,
+            prefilter: &programs.ibl_prefilter,
+            brdf: &programs.ibl_brdf,
+        },
+    );
+    let mut fog = Fog::default();
+    // one next to the chrome and glass cubes and one in front of the chrome kakyoin, the boxes
+    // follow the ground and the wall around them
+    let mut reflection_probes = ReflectionProbes::new(&display, 256, 2);
+    reflection_probes.add_probe(
+        &display,
+        Point3::new(2.5, -6.0, -7.0),
+        Aabb {
+            min: Point3::new(-12.0, -8.0, -11.0),
+            max: Point3::new(12.0, 6.0, 4.0),
+        },
+    );
+    reflection_probes.add_probe(
+        &display,
+        Point3::new(-5.0, 2.0, 6.0),
+        Aabb {
+            min: Point3::new(-15.0, -8.0, -4.0),
+            max: Point3::new(5.0, 12.0, 18.0),
+        },
+    );
+
+    // starts in the morning
+    let mut day_night = DayNightCycle::new(10.0);
+    let mut sky_cubemap = SkyCubemap::new(&display, 256);
     let mut flashlight = true;
     let mut time = 0.0;
 
@@ -239,14 +580,41 @@ fn main() {
                             // d
                             pressed_keys[3] = was_pressed;
                         }
+                        57 => {
+                            // space
+                            swim_keys[0] = was_pressed;
+                        }
+                        29 => {
+                            // lctrl
+                            swim_keys[1] = was_pressed;
+                        }
+                        15 => {
+                            // tab
+                            if !was_pressed {
+                                camera.flying = !camera.flying;
+                                println!("Flying: {}", camera.flying);
+                            }
+                        }
                         20 => {
                             // t
-                            directional_light_intensity += 0.01;
+                            day_night.sun_power += 0.01;
                         }
                         21 => {
                             // y
-                            if directional_light_intensity > 0.0 {
-                                directional_light_intensity -= 0.01;
+                            if day_night.sun_power > 0.0 {
+                                day_night.sun_power -= 0.01;
+                            }
+                        }
+                        26 => {
+                            // [
+                            if was_pressed {
+                                day_night.add_time(-0.25);
+                            }
+                        }
+                        27 => {
+                            // ]
+                            if was_pressed {
+                                day_night.add_time(0.25);
                             }
                         }
                         44 => {
@@ -297,6 +665,284 @@ fn main() {
                                 }
                             }
                         }
+                        34 => {
+                            // g
+                            if !was_pressed {
+                                trajectory_preview.enabled = !trajectory_preview.enabled;
+                            }
+                        }
+                        19 => {
+                            // r
+                            if !was_pressed {
+                                fluid_container.simulation.clear();
+                                fluid_container.simulation.add_block(fluid_spawn_block);
+                            }
+                        }
+                        47 => {
+                            // v
+                            if !was_pressed {
+                                fluid_is_goo = !fluid_is_goo;
+                                if fluid_is_goo {
+                                    fluid_container.simulation.settings = SphSettings::goo(gravity);
+                                    fluid_container.color = Vector3::new(0.3, 0.8, 0.1);
+                                } else {
+                                    fluid_container.simulation.settings = SphSettings::water(gravity);
+                                    fluid_container.color = Vector3::new(0.1, 0.3, 0.8);
+                                }
+                            }
+                        }
+                        46 => {
+                            // c
+                            if !was_pressed {
+                                // throw a crate along the trajectory preview
+                                let mut cube = DynamicCube::new(
+                                    camera.position + camera.front * 0.5,
+                                    0.5,
+                                    thrown_density,
+                                );
+                                cube.body.linear_velocity =
+                                    camera.front * trajectory_preview.launch_speed;
+                                cube_container.dynamic_cubes.push(cube);
+                            }
+                        }
+                        18 => {
+                            // e
+                            if !was_pressed {
+                                if let Some(i) = driven_cart.take() {
+                                    // leave the cart parked
+                                    let cart = &mut cube_container.carts[i];
+                                    cart.handle_keys([false; 4]);
+                                    cart.vehicle.brake = true;
+                                } else {
+                                    // get in the closest cart, if it's close enough
+                                    driven_cart = cube_container
+                                        .carts
+                                        .iter()
+                                        .enumerate()
+                                        .map(|(i, cart)| {
+                                            (i, (cart.vehicle.chassis.position - camera.position).magnitude())
+                                        })
+                                        .filter(|(_, distance)| *distance < 5.0)
+                                        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                                        .map(|(i, _)| i);
+                                }
+                            }
+                        }
+                        35 => {
+                            // h
+                            if !was_pressed {
+                                let shadow_map = &mut shadow_maps.directional;
+                                shadow_map.filter_size = (shadow_map.filter_size + 1) % 4;
+                                println!("Shadow filter size: {}", shadow_map.filter_size);
+                            }
+                        }
+                        49 => {
+                            // n
+                            if !was_pressed {
+                                let shadow_map = &mut shadow_maps.directional;
+                                let resolution = match shadow_map.resolution {
+                                    1024 => 2048,
+                                    2048 => 4096,
+                                    _ => 1024,
+                                };
+                                shadow_map.set_resolution(&display, resolution);
+                                println!("Shadow map resolution: {}", resolution);
+                            }
+                        }
+                        2..=5 => {
+                            // 1, 2, 3, 4
+                            if !was_pressed {
+                                let id = shadow_lights[input.scancode as usize - 2];
+                                if let Some(light_cube) = cube_container.get_light_cube_mut(id) {
+                                    let light = &mut light_cube.base_light;
+                                    light.casts_shadows = !light.casts_shadows;
+                                }
+                            }
+                        }
+                        48 => {
+                            // b
+                            if !was_pressed {
+                                spot_light.cookie = match spot_light.cookie.take() {
+                                    Some(_) => None,
+                                    None => Some(flashlight_cookie.clone()),
+                                };
+                            }
+                        }
+                        36 => {
+                            // j
+                            if !was_pressed {
+                                shadow_maps.spot.enabled = !shadow_maps.spot.enabled;
+                            }
+                        }
+                        37 => {
+                            // k
+                            if !was_pressed {
+                                let clusters = &mut light_buffer.clusters;
+                                clusters.enabled = !clusters.enabled;
+                                println!("Clustered lighting: {}", clusters.enabled);
+                            }
+                        }
+                        38 => {
+                            // l
+                            if !was_pressed {
+                                deferred_renderer.enabled = !deferred_renderer.enabled;
+                                println!("Deferred shading: {}", deferred_renderer.enabled);
+                            }
+                        }
+                        22 => {
+                            // u
+                            if !was_pressed {
+                                tone_mapping.operator = tone_mapping.operator.next();
+                                println!("Tone mapping: {:?}", tone_mapping.operator);
+                            }
+                        }
+                        23 => {
+                            // i
+                            if !was_pressed {
+                                tone_mapping.auto_exposure = !tone_mapping.auto_exposure;
+                                println!("Auto exposure: {}", tone_mapping.auto_exposure);
+                            }
+                        }
+                        24 => {
+                            // o
+                            if !was_pressed {
+                                bloom.settings.enabled = !bloom.settings.enabled;
+                                println!("Bloom: {}", bloom.settings.enabled);
+                            }
+                        }
+                        25 => {
+                            // p
+                            if !was_pressed {
+                                let settings = &mut bloom.settings;
+                                settings.intensity += 0.05;
+                                if settings.intensity > 0.45 {
+                                    settings.intensity = 0.05;
+                                }
+                                println!("Bloom intensity: {:.2}", settings.intensity);
+                            }
+                        }
+                        16 => {
+                            // q
+                            if !was_pressed {
+                                let settings = &mut bloom.settings;
+                                settings.threshold *= 2.0;
+                                if settings.threshold > 4.0 {
+                                    settings.threshold = 0.5;
+                                }
+                                println!("Bloom threshold: {}", settings.threshold);
+                            }
+                        }
+                        50 => {
+                            // m
+                            if !was_pressed {
+                                let settings = &mut bloom.settings;
+                                settings.radius += 0.5;
+                                if settings.radius > 3.0 {
+                                    settings.radius = 0.5;
+                                }
+                                println!("Bloom radius: {}", settings.radius);
+                            }
+                        }
+                        7 => {
+                            // 6
+                            if !was_pressed {
+                                ssao.enabled = !ssao.enabled;
+                                println!("Ambient occlusion: {}", ssao.enabled);
+                            }
+                        }
+                        8 => {
+                            // 7
+                            if !was_pressed {
+                                ibl.enabled = !ibl.enabled;
+                                println!("Image based lighting: {}", ibl.enabled);
+                            }
+                        }
+                        9 => {
+                            // 8
+                            if !was_pressed {
+                                reflection_probes.round_robin = !reflection_probes.round_robin;
+                                println!(
+                                    "Reflection probes round robin: {}",
+                                    reflection_probes.round_robin
+                                );
+                            }
+                        }
+                        10 => {
+                            // 9
+                            if !was_pressed {
+                                reflection_probes.request_update();
+                            }
+                        }
+                        11 => {
+                            // 0
+                            if !was_pressed {
+                                day_night.paused = !day_night.paused;
+                            }
+                        }
+                        12 => {
+                            // -
+                            if !was_pressed {
+                                day_night.mode = day_night.mode.next();
+                            }
+                        }
+                        59 => {
+                            // f1
+                            if !was_pressed {
+                                fog.enabled = !fog.enabled;
+                                println!("Fog: {}", fog.enabled);
+                            }
+                        }
+                        60 => {
+                            // f2
+                            if !was_pressed {
+                                fog.color_from_sky = !fog.color_from_sky;
+                                println!("Fog colour from the sky: {}", fog.color_from_sky);
+                            }
+                        }
+                        61 => {
+                            // f3
+                            if !was_pressed {
+                                let mut light_cube = SimpleLightCube::new(
+                                    Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+                                    0.1,
+                                    PointLight {
+                                        position: camera.position + camera.front * 3.0,
+
+                                        ambient: Vector3::new(0.0, 0.0, 0.0),
+                                        diffuse: Vector3::new(2.0, 1.2, 0.5),
+                                        specular: Vector3::new(1.0, 0.6, 0.25),
+
+                                        constant: 1.0,
+                                        linear: 0.22,
+                                        quadratic: 0.2,
+                                        casts_shadows: false,
+                                    },
+                                );
+                                light_cube.behaviours.push(LightBehaviour::Flicker {
+                                    speed: 8.0,
+                                    amount: 0.4,
+                                    seed: added_lights.len() as f32,
+                                });
+                                added_lights.push(cube_container.add_light_cube(light_cube));
+                                println!("Added lights: {}", added_lights.len());
+                            }
+                        }
+                        62 => {
+                            // f4
+                            if !was_pressed {
+                                if let Some(id) = added_lights.pop() {
+                                    cube_container.remove_light_cube(id);
+                                }
+                                println!("Added lights: {}", added_lights.len());
+                            }
+                        }
+                        13 => {
+                            // =
+                            if !was_pressed {
+                                volumetric_light.enabled = !volumetric_light.enabled;
+                                println!("Volumetric light: {}", volumetric_light.enabled);
+                            }
+                        }
                         42 => {
                             // lshift
                             // todo: repeated code
@@ -390,45 +1036,389 @@ fn main() {
         };
         kakyoin_container.objects[0].object.update_model();
 
-        camera.handle_mouse_movement(mouse.delta_x, mouse.delta_y);
-        camera.handle_keys(pressed_keys, delta_time);
+        // the current carries the swimmer along with the part of it under water
+        let swimmer = camera.get_swimmer_center();
+        let mut submerged_fraction = 0.0;
+        for volume in water_container.volumes.iter() {
+            let fraction = volume.get_sphere_submerged_fraction(swimmer, SWIMMER_RADIUS);
+            camera.position += volume.current * (fraction * delta_time.as_secs_f32());
+            submerged_fraction += fraction;
+        }
+        camera.swimming = submerged_fraction > 0.0;
+
+        // the keys drive the cart instead of the camera
+        match driven_cart {
+            Some(i) => cube_container.carts[i].handle_keys(pressed_keys),
+            None => {
+                camera.handle_mouse_movement(mouse.delta_x, mouse.delta_y);
+                camera.handle_keys(pressed_keys, delta_time);
+                camera.handle_swimming(submerged_fraction.min(1.0), swim_keys, gravity, delta_time);
+            }
+        }
 
         // update spot_light
         spot_light.position = camera.position;
         spot_light.direction = camera.front;
 
+        cube_container.update_kinematic_cubes(delta_time.as_secs_f32());
+        for cube in cube_container.kinematic_cubes.iter() {
+            // ride along when standing on a moving platform
+            if !camera.flying && cube.body.is_standing_on(camera.get_feet(), GROUND_CONTACT) {
+                camera.position = cube.body.carry_point(camera.position);
+            }
+        }
+
+        // big frame times would make the rigid bodies tunnel through everything
+        let mut splashes = cube_container.update_dynamic_cubes(
+            delta_time.as_secs_f32().min(1.0 / 30.0),
+            gravity,
+            &water_container.volumes,
+        );
+        let heavy_colliders = cube_container.get_heavy_colliders();
+        for kakyoin in kakyoin_container.objects.iter_mut() {
+            splashes.extend(kakyoin.update_body(
+                delta_time.as_secs_f32().min(1.0 / 30.0),
+                gravity,
+                &water_container.volumes,
+                &kakyoin_hull,
+                &heavy_colliders,
+            ));
+        }
+        for splash in splashes.iter() {
+            println!(
+                "Splash at {:?} with speed {}",
+                splash.position, splash.speed
+            );
+        }
+
+        cube_container.update_carts(delta_time.as_secs_f32().min(1.0 / 30.0), gravity);
+        if let Some(i) = driven_cart {
+            let vehicle = &cube_container.carts[i].vehicle;
+            camera.follow(vehicle.chassis.position, vehicle.get_forward(), delta_time);
+        }
+        cube_container.update_light_cubes(delta_time.as_secs_f32());
+
+        let colliders = cube_container.get_colliders();
+        if driven_cart.is_none() {
+            camera.handle_walking(&colliders, gravity, delta_time);
+        }
+
+        trajectory_preview.update(
+            camera.position + camera.front * 0.5,
+            camera.front,
+            |point, velocity| {
+                gravity
+                    + water_container
+                        .volumes
+                        .iter()
+                        .map(|volume| {
+                            volume.get_acceleration(point, velocity, gravity, thrown_density)
+                        })
+                        .sum::<Vector3<f32>>()
+            },
+            &colliders,
+            &mesh_colliders,
+        );
+
+        fluid_container.update(
+            delta_time.as_secs_f32(),
+            &cube_container.get_heavy_colliders(),
+            &mesh_colliders,
+            &mut cube_container.get_dynamic_bodies_mut(),
+        );
+
+        // the sun, or the moon during the night
+        day_night.update(delta_time.as_secs_f32());
+        // the reflections and the ambient light of the sky only change once the sun has moved
+        // enough, the skybox mode uses the ambient light of the skybox from the cache
+        let ibl_programs = IblPrograms {
+            irradiance: &programs.ibl_irradiance,
+            prefilter: &programs.ibl_prefilter,
+            brdf: &programs.ibl_brdf,
+        };
+        if sky_cubemap.needs_update(&day_night) {
+            sky_cubemap.update(&display, &programs.skybox, &skybox_shader, &day_night, &ibl);
+            if day_night.mode.uses_procedural_sky() {
+                ibl.use_sky(&display, &ibl_programs, &sky_cubemap.cubemap);
+            }
+        }
+        if !day_night.mode.uses_procedural_sky() {
+            ibl.use_skybox(
+                &display,
+                &ibl_programs,
+                &skybox_shader.cubemap,
+                skybox_shader.cache_key,
+                "./cache/skybox_ibl.bin",
+            );
+            ibl.sky_brightness = day_night.get_sky_brightness();
+        }
+        fog.brightness = day_night.get_sky_brightness();
+
+        // lights of this frame, the flashlight is left out while it's turned off
+        let sun = day_night.get_light();
+        let directional_lights = [&sun];
+        // the emissive objects also light what's around them
+        let emissive_lights: Vec<PointLight> = cube_container
+            .get_emissive_lights()
+            .into_iter()
+            .chain(kakyoin_container.get_emissive_lights())
+            .chain(chrome_kakyoin_container.get_emissive_lights())
+            .chain(material_ball_container.get_emissive_lights())
+            .collect();
+        let point_lights: Vec<&PointLight> = cube_container
+            .get_light_cubes()
+            .map(|(_, light_cube)| &light_cube.light)
+            .chain(emissive_lights.iter())
+            .collect();
+        let mut spot_lights: Vec<&SpotLight> = lamps.iter().collect();
+        if flashlight {
+            spot_lights.insert(0, &spot_light);
+        }
+        shadow_maps.assign_lights(&directional_lights, &point_lights, &spot_lights);
+
+        // sun shadow depth pass
+        let shadow_map = &mut shadow_maps.directional;
+        if let Some(light_index) = shadow_map.light_index {
+            let light_direction = directional_lights[light_index].direction;
+            shadow_map.fit_to_camera(&camera, get_aspect_ratio(&display), light_direction);
+
+            let mut shadow_target = shadow_map.get_framebuffer(&display);
+            shadow_target.clear_depth(1.0);
+            let shadow_params = shadow_map.get_draw_parameters();
+
+            cube_container.draw_cubes_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                &shadow_map.light_space_matrix,
+            );
+            kakyoin_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                &shadow_map.light_space_matrix,
+            );
+            chrome_kakyoin_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                &shadow_map.light_space_matrix,
+            );
+            material_ball_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                &shadow_map.light_space_matrix,
+            );
+        }
+
+        // point light shadow cubemaps, only a few of them are rendered every frame
+        let point_shadow_maps = &mut shadow_maps.point;
+        for (slot, light_index) in point_shadow_maps.get_lights_to_update() {
+            let light_position = point_lights[light_index].position;
+            let shadow_params = point_shadow_maps.get_draw_parameters();
+
+            for (layer, matrix) in point_shadow_maps.get_face_matrices(light_position).iter() {
+                let mut shadow_target = point_shadow_maps.get_framebuffer(&display, slot, *layer);
+                shadow_target.clear_depth(1.0);
+
+                cube_container.draw_cubes_depth(
+                    &mut shadow_target,
+                    &programs.shadow_depth,
+                    &shadow_params,
+                    matrix,
+                );
+                kakyoin_container.draw_objects_depth(
+                    &mut shadow_target,
+                    &programs.shadow_depth,
+                    &shadow_params,
+                    matrix,
+                );
+                chrome_kakyoin_container.draw_objects_depth(
+                    &mut shadow_target,
+                    &programs.shadow_depth,
+                    &shadow_params,
+                    matrix,
+                );
+                material_ball_container.draw_objects_depth(
+                    &mut shadow_target,
+                    &programs.shadow_depth,
+                    &shadow_params,
+                    matrix,
+                );
+            }
+        }
+
+        // spot light shadows, the matrices were updated with the slots
+        let spot_shadow_maps = &shadow_maps.spot;
+        for slot in spot_shadow_maps.get_shadow_slots() {
+            let mut shadow_target = spot_shadow_maps.get_framebuffer(&display, slot);
+            shadow_target.clear_depth(1.0);
+            let shadow_params = spot_shadow_maps.get_draw_parameters();
+            let light_space_matrix = &spot_shadow_maps.light_space_matrices[slot];
+
+            cube_container.draw_cubes_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                light_space_matrix,
+            );
+            kakyoin_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                light_space_matrix,
+            );
+            chrome_kakyoin_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                light_space_matrix,
+            );
+            material_ball_container.draw_objects_depth(
+                &mut shadow_target,
+                &programs.shadow_depth,
+                &shadow_params,
+                light_space_matrix,
+            );
+        }
+
+        light_buffer.update(&directional_lights, &point_lights, &spot_lights, &shadow_maps);
+        light_buffer
+            .clusters
+            .update(&point_lights, &camera, get_aspect_ratio(&display));
+
+        // the probes see the scene from somewhere else, so they can't use the screen space
+        // ambient occlusion or the light clusters of the camera
+        let probe_faces = reflection_probes.get_faces_to_update();
+        if !probe_faces.is_empty() {
+            let ssao_enabled = ssao.enabled;
+            let clustered_lighting = light_buffer.clusters.enabled;
+            ssao.enabled = false;
+            light_buffer.clusters.enabled = false;
+
+            let probe_params = glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::draw_parameters::DepthTest::IfLessOrEqual,
+                    write: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            for (probe_index, layer) in probe_faces {
+                let probe_position = reflection_probes.probes[probe_index].position;
+                let probe_projection_view = reflection_probes.get_face_matrix(probe_index, layer);
+                let mut probe_target =
+                    reflection_probes.get_framebuffer(&display, probe_index, layer);
+                probe_target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+
+                // without the environment mapped objects, they would reflect themselves
+                cube_container.draw_reflection_probe_cubes(
+                    &mut probe_target,
+                    &programs.textured_object,
+                    &probe_params,
+                    &CubeContainerDrawData {
+                        projection_view: &probe_projection_view,
+                        camera_pos: probe_position,
+                        lights: &light_buffer,
+                        shadow_maps: &shadow_maps,
+                        ssao: &ssao,
+                        ibl: &ibl,
+                        fog: &fog,
+                        environment: &sky_cubemap.cubemap,
+                        reflection_probes: None,
+                    },
+                );
+                cube_container.draw_light_cubes(
+                    &mut probe_target,
+                    &programs.light_object,
+                    &probe_params,
+                    &probe_projection_view,
+                );
+                kakyoin_container.draw(
+                    &mut probe_target,
+                    Renderable3dObjectContainerPrograms {
+                        textured_object: &programs.textured_object,
+                        pbr_object: &programs.pbr_object,
+                    },
+                    &probe_params,
+                    Renderable3dObjectContainerDrawData {
+                        projection_view: &probe_projection_view,
+                        camera_pos: probe_position,
+                        lights: &light_buffer,
+                        shadow_maps: &shadow_maps,
+                        ssao: &ssao,
+                        ibl: &ibl,
+                        fog: &fog,
+                        environment: &sky_cubemap.cubemap,
+                        reflection_probes: None,
+                    },
+                );
+                material_ball_container.draw(
+                    &mut probe_target,
+                    Renderable3dObjectContainerPrograms {
+                        textured_object: &programs.textured_object,
+                        pbr_object: &programs.pbr_object,
+                    },
+                    &probe_params,
+                    Renderable3dObjectContainerDrawData {
+                        projection_view: &probe_projection_view,
+                        camera_pos: probe_position,
+                        lights: &light_buffer,
+                        shadow_maps: &shadow_maps,
+                        ssao: &ssao,
+                        ibl: &ibl,
+                        fog: &fog,
+                        environment: &sky_cubemap.cubemap,
+                        reflection_probes: None,
+                    },
+                );
+
+                let matrix = probe_projection_view
+                    * crate::objects::renderable_3d_object::create_model_matrix(
+                        probe_position,
+                        Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+                        1200.0,
+                    );
+                let skybox_uniforms = programs::SkyBoxProgram::get_uniforms(
+                    &matrix,
+                    &skybox_shader.cubemap,
+                    &day_night,
+                    &fog,
+                    &ibl,
+                    &probe_position,
+                );
+                probe_target
+                    .draw(
+                        &skybox_shader.vertex_buffer,
+                        &skybox_shader.index_buffer,
+                        &programs.skybox.0,
+                        &skybox_uniforms,
+                        &probe_params,
+                    )
+                    .unwrap();
+            }
+
+            ssao.enabled = ssao_enabled;
+            light_buffer.clusters.enabled = clustered_lighting;
+        }
+
         let mut target = display.draw();
         let size = target.get_dimensions();
 
         // I actually need to declare this at a higher level, so they have a higher lifetime that the framebuffer
         // todo: update this so that the framebuffer doesn't get created every frame
+        // the scene is rendered in hdr, the final pass tone maps it
         let framebuffer_render_texture = glium::Texture2d::empty_with_format(
             &display,
-            glium::texture::UncompressedFloatFormat::U8U8U8U8,
+            glium::texture::UncompressedFloatFormat::F16F16F16F16,
             glium::texture::MipmapsOption::NoMipmap,
             size.0,
             size.1,
         )
         .unwrap();
 
-        let framebuffer_depth_buffer = glium::framebuffer::DepthRenderBuffer::new(
-            &display,
-            glium::texture::DepthFormat::I24,
-            size.0,
-            size.1,
-        )
-        .unwrap();
-
-        let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(
-            &display,
-            &framebuffer_render_texture,
-            &framebuffer_depth_buffer,
-        )
-        .unwrap();
-
-        // framebuffer.clear_color_srgb_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
-        framebuffer.clear_depth(1.0);
-
         let view_matrix = camera.get_view_matrix();
 
         let projection_view = projection_matrix * view_matrix;
@@ -444,49 +1434,275 @@ fn main() {
             ..Default::default()
         };
 
-        cube_container.draw(
-            &mut framebuffer,
-            CubeContainerPrograms {
-                cube: &programs.textured_object,
-                light_cube: &programs.light_object,
-            },
-            &params,
-            CubeContainerDrawData {
-                projection_view: &projection_view,
-                camera_pos: camera.position,
-                spot_light: &spot_light,
-                t: directional_light_intensity,
+        // the deferred renderer draws the lit objects into the gbuffer first, its depth is then
+        // shared with the forward objects, the forward renderer only needs it for the ssao
+        deferred_renderer.gbuffer.resize(&display, size);
+        let geometry_pass = deferred_renderer.enabled || ssao.enabled;
+        if geometry_pass {
+            let mut gbuffer_target = deferred_renderer.gbuffer.get_framebuffer(&display);
+            gbuffer_target.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
+            cube_container.draw_cubes_gbuffer(
+                &mut gbuffer_target,
+                &programs.gbuffer,
+                &params,
+                &projection_view,
+                camera.position,
+            );
+            kakyoin_container.draw_objects_gbuffer(
+                &mut gbuffer_target,
+                Renderable3dObjectContainerGBufferPrograms {
+                    gbuffer: &programs.gbuffer,
+                    pbr_gbuffer: &programs.pbr_gbuffer,
+                },
+                &params,
+                &projection_view,
+                camera.position,
+            );
+            chrome_kakyoin_container.draw_objects_gbuffer(
+                &mut gbuffer_target,
+                Renderable3dObjectContainerGBufferPrograms {
+                    gbuffer: &programs.gbuffer,
+                    pbr_gbuffer: &programs.pbr_gbuffer,
+                },
+                &params,
+                &projection_view,
+                camera.position,
+            );
+            material_ball_container.draw_objects_gbuffer(
+                &mut gbuffer_target,
+                Renderable3dObjectContainerGBufferPrograms {
+                    gbuffer: &programs.gbuffer,
+                    pbr_gbuffer: &programs.pbr_gbuffer,
+                },
+                &params,
+                &projection_view,
+                camera.position,
+            );
+        }
+
+        ssao.resize(&display, size);
+        ssao.update(
+            &display,
+            SsaoPrograms {
+                ssao: &programs.ssao,
+                blur: &programs.ssao_blur,
             },
+            &deferred_renderer.gbuffer,
+            &view_matrix,
+            &projection_matrix,
         );
 
-        let ligths: [&PointLight; 4] = [
-            &cube_container.light_cubes[0].light, 
-            &cube_container.light_cubes[1].light, 
-            &cube_container.light_cubes[2].light, 
-            &cube_container.light_cubes[3].light
-        ];
+        let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(
+            &display,
+            &framebuffer_render_texture,
+            &deferred_renderer.gbuffer.depth,
+        )
+        .unwrap();
+
+        if deferred_renderer.enabled {
+            // without the depth buffer, the lighting reads it from the gbuffer, the objects drawn
+            // after it use the framebuffer with the depth again
+            let mut lighting_target =
+                glium::framebuffer::SimpleFrameBuffer::new(&display, &framebuffer_render_texture)
+                    .unwrap();
+            lighting_target.clear_color(0.0, 0.0, 0.0, 1.0);
+
+            deferred_renderer.draw_lighting(
+                &mut lighting_target,
+                DeferredLightingPrograms {
+                    screen: &programs.deferred_lighting,
+                    point_light: &programs.deferred_point_light,
+                },
+                DeferredLightingDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    point_lights: &point_lights,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                },
+            );
+
+            cube_container.draw_light_cubes(
+                &mut framebuffer,
+                &programs.light_object,
+                &params,
+                &projection_view,
+            );
+
+            // the deferred lighting is only phong without the environment mapping, the pbr and
+            // environment mapped objects are lit again over it
+            cube_container.draw_environment_mapped_cubes(
+                &mut framebuffer,
+                &programs.textured_object,
+                &params,
+                &CubeContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+            chrome_kakyoin_container.draw(
+                &mut framebuffer,
+                Renderable3dObjectContainerPrograms {
+                    textured_object: &programs.textured_object,
+                    pbr_object: &programs.pbr_object,
+                },
+                &params,
+                Renderable3dObjectContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+            material_ball_container.draw(
+                &mut framebuffer,
+                Renderable3dObjectContainerPrograms {
+                    textured_object: &programs.textured_object,
+                    pbr_object: &programs.pbr_object,
+                },
+                &params,
+                Renderable3dObjectContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+        } else {
+            // framebuffer.clear_color_srgb_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+            // the objects are drawn again on top of the depth of the geometry pass
+            if !geometry_pass {
+                framebuffer.clear_depth(1.0);
+            }
 
-        kakyoin_container.draw(
+            cube_container.draw(
+                &mut framebuffer,
+                CubeContainerPrograms {
+                    cube: &programs.textured_object,
+                    light_cube: &programs.light_object,
+                },
+                &params,
+                CubeContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+
+            kakyoin_container.draw(
+                &mut framebuffer,
+                Renderable3dObjectContainerPrograms {
+                    textured_object: &programs.textured_object,
+                    pbr_object: &programs.pbr_object,
+                },
+                &params,
+                Renderable3dObjectContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+            chrome_kakyoin_container.draw(
+                &mut framebuffer,
+                Renderable3dObjectContainerPrograms {
+                    textured_object: &programs.textured_object,
+                    pbr_object: &programs.pbr_object,
+                },
+                &params,
+                Renderable3dObjectContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+            material_ball_container.draw(
+                &mut framebuffer,
+                Renderable3dObjectContainerPrograms {
+                    textured_object: &programs.textured_object,
+                    pbr_object: &programs.pbr_object,
+                },
+                &params,
+                Renderable3dObjectContainerDrawData {
+                    projection_view: &projection_view,
+                    camera_pos: camera.position,
+                    lights: &light_buffer,
+                    shadow_maps: &shadow_maps,
+                    ssao: &ssao,
+                    ibl: &ibl,
+                    fog: &fog,
+                    environment: &sky_cubemap.cubemap,
+                    reflection_probes: Some(&reflection_probes),
+                },
+            );
+        }
+
+        fluid_container.draw(
             &mut framebuffer,
-            &programs.textured_object,
+            &programs.fluid_particle,
             &params,
-            Renderable3dObjectContainerDrawData {
+            FluidContainerDrawData {
                 projection_view: &projection_view,
-                camera_pos: camera.position,
-                spot_light: &spot_light,
-                point_lights: &ligths,
-                directional_light_intensity: directional_light_intensity,
+                light_direction: &sun.direction,
             },
         );
 
+        trajectory_preview.draw(
+            &mut framebuffer,
+            &programs.light_object,
+            &params,
+            &projection_view,
+        );
+
         // draw skybox
         {
             let matrix = projection_view * crate::objects::renderable_3d_object::create_model_matrix(camera.position, Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)), 1200.0);
 
-            let skybox_uniforms = uniform! {
-                matrix: matrix.to_array(),
-                cubetex: skybox_shader.cubemap.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear),
-           };
+            let skybox_uniforms = programs::SkyBoxProgram::get_uniforms(
+                &matrix,
+                &skybox_shader.cubemap,
+                &day_night,
+                &fog,
+                &ibl,
+                &camera.position,
+            );
             framebuffer
                 .draw(
                     &skybox_shader.vertex_buffer,
@@ -499,9 +1715,72 @@ fn main() {
         }
 
 
+        water_container.draw(
+            &mut framebuffer,
+            &programs.water,
+            &params,
+            &projection_view,
+        );
+
+        // the light shafts are added over everything, the scene depth is complete at this point
+        {
+            let volumetric_light_programs = VolumetricLightPrograms {
+                raymarch: &programs.volumetric_light,
+                temporal: &programs.volumetric_light_temporal,
+                composite: &programs.volumetric_light_composite,
+            };
+            volumetric_light.resize(&display, size);
+            volumetric_light.update(
+                &display,
+                &volumetric_light_programs,
+                &deferred_renderer.gbuffer,
+                &light_buffer,
+                &shadow_maps,
+                &camera.position,
+                &projection_view,
+            );
+            // without the depth buffer, it's read by the composite
+            let mut scene_target =
+                glium::framebuffer::SimpleFrameBuffer::new(&display, &framebuffer_render_texture)
+                    .unwrap();
+            volumetric_light.draw(
+                &mut scene_target,
+                &volumetric_light_programs,
+                &deferred_renderer.gbuffer,
+                &camera.position,
+                &projection_view,
+            );
+        }
+
+        tone_mapping.update_exposure(
+            &display,
+            ToneMappingPrograms {
+                luminance: &programs.luminance,
+                downsample: &programs.downsample,
+                adaptation: &programs.exposure_adaptation,
+            },
+            &framebuffer_render_texture,
+            delta_time.as_secs_f32(),
+        );
+
+        bloom.resize(&display, size);
+        bloom.update(
+            &display,
+            BloomPrograms {
+                bright_pass: &programs.bloom_bright_pass,
+                downsample: &programs.bloom_downsample,
+                upsample: &programs.bloom_upsample,
+            },
+            &framebuffer_render_texture,
+        );
+
         // draw framebuffer to target (with post processing effects)
         target.clear_depth(1.0);
-        let uniforms = programs::MainFramebufferProgram::get_uniforms(&framebuffer_render_texture);
+        let uniforms = programs::MainFramebufferProgram::get_uniforms(
+            &framebuffer_render_texture,
+            &tone_mapping,
+            &bloom,
+        );
         target
             .draw(
                 &main_framebuffer_shader.vertex_buffer,
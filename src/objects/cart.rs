@@ -0,0 +1,89 @@
+use cgmath::{EuclideanSpace, Euler, Matrix4, Point3, Quaternion, Vector3};
+
+use crate::objects::renderable_3d_object::Renderable3dObject;
+use crate::physics::vehicle::Vehicle;
+
+// width of the wheels, the cube shape makes them look like rollers
+const WHEEL_WIDTH: f32 = 0.15;
+
+pub struct Cart {
+    pub chassis_object: Renderable3dObject,
+    pub wheel_objects: Vec<Renderable3dObject>,
+    pub vehicle: Vehicle,
+}
+
+// the renderable objects only support uniform scaling, so the model matrix is built here
+fn create_box_model_matrix(
+    position: Point3<f32>,
+    rotation: Quaternion<f32>,
+    half_extent: Vector3<f32>,
+) -> Matrix4<f32> {
+    Matrix4::from_translation(position.to_vec())
+        * Matrix4::from(rotation)
+        * Matrix4::from_nonuniform_scale(half_extent.x, half_extent.y, half_extent.z)
+}
+
+impl Cart {
+    pub fn new(position: Point3<f32>) -> Self {
+        let vehicle = Vehicle::new_cart(position);
+        let mut cart = Cart {
+            chassis_object: Renderable3dObject::new(position),
+            wheel_objects: vehicle
+                .wheels
+                .iter()
+                .map(|_| Renderable3dObject::new(position))
+                .collect(),
+            vehicle,
+        };
+        cart.sync_objects();
+        cart
+    }
+
+    pub fn handle_keys(&mut self, keys_pressed: [bool; 4]) {
+        let vehicle = &mut self.vehicle;
+        vehicle.throttle = 0.0;
+        vehicle.brake = false;
+        vehicle.steering = 0.0;
+
+        if keys_pressed[0] {
+            // w
+            vehicle.throttle = 1.0;
+        }
+        if keys_pressed[1] {
+            // a
+            vehicle.steering -= 1.0;
+        }
+        if keys_pressed[2] {
+            // s, brakes first and then goes in reverse
+            if vehicle.get_speed() > 0.5 {
+                vehicle.brake = true;
+            } else {
+                vehicle.throttle = -0.5;
+            }
+        }
+        if keys_pressed[3] {
+            // d
+            vehicle.steering += 1.0;
+        }
+    }
+
+    pub fn sync_objects(&mut self) {
+        let chassis = &self.vehicle.chassis;
+        self.chassis_object.position = chassis.position;
+        self.chassis_object.rotation = Euler::from(chassis.rotation);
+        self.chassis_object.model_matrix =
+            create_box_model_matrix(chassis.position, chassis.rotation, chassis.half_extent);
+
+        for (object, wheel) in self.wheel_objects.iter_mut().zip(self.vehicle.wheels.iter()) {
+            let position = self.vehicle.get_wheel_center(wheel);
+            let rotation = self.vehicle.get_wheel_rotation(wheel);
+            object.position = position;
+            object.rotation = Euler::from(rotation);
+            object.model_matrix = create_box_model_matrix(
+                position,
+                rotation,
+                Vector3::new(WHEEL_WIDTH, wheel.radius, wheel.radius),
+            );
+        }
+    }
+}
@@ -1,23 +1,63 @@
 use crate::objects::renderable_3d_object::HasRenderable3dObject;
 use crate::objects::renderable_3d_object::Renderable3dObject;
-use cgmath::{Euler, Point3, Rad};
+use crate::physics::{
+    rigid_body::RigidBody,
+    scene_query::BoxCollider,
+    water::{apply_water_forces, HullSamples, SplashEvent, WaterVolume},
+};
+use cgmath::{EuclideanSpace, Euler, Point3, Rad, Rotation, Vector3};
 
 pub struct Kakyoin {
     pub object: Renderable3dObject,
+    // only the floating ones have a body, it's at the center of the hull
+    pub body: Option<RigidBody>,
 }
 
 impl Kakyoin {
     pub fn new(position: Point3<f32>) -> Self {
         Self {
             object: Renderable3dObject::new(position),
+            body: None,
         }
     }
 
     pub fn from_full(position: Point3<f32>, rotation: Euler<Rad<f32>>, scale: f32) -> Self {
         Self {
             object: Renderable3dObject::from_full(position, rotation, scale),
+            body: None,
         }
     }
+
+    // the buoyancy comes from the samples of its mesh, the box of the body is only for the
+    // inertia and the contacts
+    pub fn new_floating(position: Point3<f32>, hull: &HullSamples, density: f32) -> Self {
+        let mut body =
+            RigidBody::new_box(position + hull.center.to_vec(), hull.half_extent, density);
+        body.mass = density * hull.get_volume();
+        Self {
+            object: Renderable3dObject::new(position),
+            body: Some(body),
+        }
+    }
+
+    pub fn update_body(
+        &mut self,
+        delta_time: f32,
+        gravity: Vector3<f32>,
+        water_volumes: &[WaterVolume],
+        hull: &HullSamples,
+        colliders: &[BoxCollider],
+    ) -> Option<SplashEvent> {
+        let body = self.body.as_mut()?;
+        let splash = apply_water_forces(water_volumes, body, Some(hull), gravity);
+        body.integrate(delta_time, gravity);
+        body.resolve_contacts(colliders);
+
+        self.object.position = body.position - body.rotation.rotate_vector(hull.center.to_vec());
+        self.object.rotation = Euler::from(body.rotation);
+        self.object.update_model();
+        splash
+    }
 }
 
 impl HasRenderable3dObject for Kakyoin {
@@ -0,0 +1,206 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use std::f32::consts::PI;
+
+use crate::shaders::common::PointLight;
+
+// something in the scene a light can be attached to
+#[derive(Copy, Clone, Debug)]
+pub enum LightTarget {
+    Cart(usize),
+    KinematicCube(usize),
+}
+
+// changes a light every frame, they are applied in order over the light as it was created
+pub enum LightBehaviour {
+    // random drops of the brightness like a flame, `seed` keeps lights from flickering together
+    Flicker {
+        speed: f32,
+        amount: f32,
+        seed: f32,
+    },
+    // smooth changes of the brightness, `frequency` times per second
+    Pulse {
+        frequency: f32,
+        amount: f32,
+    },
+    // rotates the hue of the colour, in turns per second
+    ColorCycle {
+        speed: f32,
+    },
+    // circles around `center` on the xz plane, `speed` in radians per second
+    Orbit {
+        center: Point3<f32>,
+        radius: f32,
+        speed: f32,
+    },
+    // stays at `offset` from the target, the light doesn't move if the target doesn't exist
+    Follow {
+        target: LightTarget,
+        offset: Vector3<f32>,
+    },
+}
+
+impl LightBehaviour {
+    pub fn apply(
+        &self,
+        light: &mut PointLight,
+        time: f32,
+        get_target_position: &impl Fn(LightTarget) -> Option<Point3<f32>>,
+    ) {
+        match self {
+            LightBehaviour::Flicker {
+                speed,
+                amount,
+                seed,
+            } => {
+                scale_brightness(
+                    light,
+                    1.0 - amount * value_noise(time * speed + seed * 57.0),
+                );
+            }
+            LightBehaviour::Pulse { frequency, amount } => {
+                let t = 0.5 - 0.5 * (time * frequency * PI * 2.0).cos();
+                scale_brightness(light, 1.0 - amount * t);
+            }
+            LightBehaviour::ColorCycle { speed } => {
+                let angle = time * speed * PI * 2.0;
+                light.ambient = rotate_hue(light.ambient, angle);
+                light.diffuse = rotate_hue(light.diffuse, angle);
+                light.specular = rotate_hue(light.specular, angle);
+            }
+            LightBehaviour::Orbit {
+                center,
+                radius,
+                speed,
+            } => {
+                let angle = time * speed;
+                light.position = center + Vector3::new(angle.cos(), 0.0, angle.sin()) * *radius;
+            }
+            LightBehaviour::Follow { target, offset } => {
+                if let Some(position) = get_target_position(*target) {
+                    light.position = position + offset;
+                }
+            }
+        }
+    }
+}
+
+fn scale_brightness(light: &mut PointLight, factor: f32) {
+    light.ambient *= factor;
+    light.diffuse *= factor;
+    light.specular *= factor;
+}
+
+// rotation around the grey axis, keeps the brightness of the colour
+fn rotate_hue(color: Vector3<f32>, angle: f32) -> Vector3<f32> {
+    let axis = Vector3::new(1.0, 1.0, 1.0).normalize();
+    let (sin, cos) = angle.sin_cos();
+    let rotated = color * cos + axis.cross(color) * sin + axis * axis.dot(color) * (1.0 - cos);
+    Vector3::new(rotated.x.max(0.0), rotated.y.max(0.0), rotated.z.max(0.0))
+}
+
+// smooth noise between 0 and 1, a new random value every unit of x
+fn value_noise(x: f32) -> f32 {
+    fn hash(i: f32) -> f32 {
+        ((i * 12.9898).sin() * 43758.547).fract().abs()
+    }
+
+    let i = x.floor();
+    let t = x - i;
+    let t = t * t * (3.0 - 2.0 * t);
+    hash(i) * (1.0 - t) + hash(i + 1.0) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_light() -> PointLight {
+        PointLight {
+            position: Point3::new(1.0, 2.0, 3.0),
+            ambient: Vector3::new(0.1, 0.1, 0.1),
+            diffuse: Vector3::new(1.0, 0.5, 0.25),
+            specular: Vector3::new(1.0, 1.0, 1.0),
+            constant: 1.0,
+            linear: 0.09,
+            quadratic: 0.032,
+            casts_shadows: false,
+        }
+    }
+
+    fn no_targets(_: LightTarget) -> Option<Point3<f32>> {
+        None
+    }
+
+    #[test]
+    fn orbit_keeps_the_radius_from_the_center() {
+        let center = Point3::new(4.0, 1.0, -2.0);
+        let behaviour = LightBehaviour::Orbit {
+            center,
+            radius: 3.0,
+            speed: 1.3,
+        };
+        for step in 0..50 {
+            let mut light = create_light();
+            behaviour.apply(&mut light, step as f32 * 0.37, &no_targets);
+            assert!(((light.position - center).magnitude() - 3.0).abs() < 1e-4);
+            assert!((light.position.y - center.y).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn follow_stays_still_without_a_target() {
+        let behaviour = LightBehaviour::Follow {
+            target: LightTarget::Cart(7),
+            offset: Vector3::new(0.0, 1.0, 0.0),
+        };
+        let mut light = create_light();
+        behaviour.apply(&mut light, 1.0, &no_targets);
+        assert_eq!(light.position, create_light().position);
+
+        behaviour.apply(&mut light, 1.0, &|_| Some(Point3::new(5.0, 0.0, 5.0)));
+        assert_eq!(light.position, Point3::new(5.0, 1.0, 5.0));
+    }
+
+    #[test]
+    fn pulse_and_flicker_keep_the_brightness_within_the_amount() {
+        let amount = 0.4;
+        let behaviours = [
+            LightBehaviour::Pulse {
+                frequency: 0.7,
+                amount,
+            },
+            LightBehaviour::Flicker {
+                speed: 9.0,
+                amount,
+                seed: 0.3,
+            },
+        ];
+        let original = create_light();
+        for behaviour in behaviours.iter() {
+            for step in 0..200 {
+                let mut light = create_light();
+                behaviour.apply(&mut light, step as f32 * 0.05, &no_targets);
+                let factor = light.diffuse.x / original.diffuse.x;
+                assert!(factor >= 1.0 - amount - 1e-5 && factor <= 1.0 + 1e-5);
+                // every colour is scaled by the same factor
+                assert!((light.diffuse - original.diffuse * factor).magnitude() < 1e-5);
+                assert!((light.specular - original.specular * factor).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_hue_keeps_the_grey_component() {
+        let grey = |color: Vector3<f32>| (color.x + color.y + color.z) / 3.0;
+        let color = Vector3::new(0.8, 0.5, 0.4);
+        for step in 0..12 {
+            let rotated = rotate_hue(color, step as f32 * 0.5);
+            assert!((grey(rotated) - grey(color)).abs() < 1e-5);
+        }
+        // a grey colour has no hue to rotate
+        let white = Vector3::new(0.7, 0.7, 0.7);
+        assert!((rotate_hue(white, 1.0) - white).magnitude() < 1e-5);
+    }
+}
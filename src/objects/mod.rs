@@ -1,3 +1,6 @@
+pub mod cart;
+pub mod light_behaviour;
 pub mod renderable_3d_object;
 pub mod simple_objects;
 pub mod kakyoin;
+pub mod material_ball;
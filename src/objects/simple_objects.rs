@@ -1,6 +1,9 @@
-use cgmath::{Euler, Point3, Rad};
+use cgmath::{Euler, Point3, Rad, Vector3};
 
+use crate::objects::light_behaviour::{LightBehaviour, LightTarget};
 use crate::objects::renderable_3d_object::Renderable3dObject;
+use crate::physics::kinematic::{KinematicBody, KinematicPath};
+use crate::physics::rigid_body::RigidBody;
 use crate::shaders::common::PointLight;
 
 pub struct Cube {
@@ -22,14 +25,85 @@ impl Cube {
 
 pub struct SimpleLightCube {
     pub object: Renderable3dObject,
+    // the light as it was created, the behaviours are applied over it every frame
+    pub base_light: PointLight,
+    // the light of this frame, changes to it are lost on the next update
     pub light: PointLight,
+    pub behaviours: Vec<LightBehaviour>,
+    time: f32,
 }
 
 impl SimpleLightCube {
     pub fn new(rotation: Euler<Rad<f32>>, scale: f32, light: PointLight) -> Self {
         SimpleLightCube {
             object: Renderable3dObject::from_full(light.position, rotation, scale),
+            base_light: light.clone(),
             light,
+            behaviours: Vec::new(),
+            time: 0.0,
         }
     }
+
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        get_target_position: &impl Fn(LightTarget) -> Option<Point3<f32>>,
+    ) {
+        self.time += delta_time;
+
+        let mut light = self.base_light.clone();
+        for behaviour in self.behaviours.iter() {
+            behaviour.apply(&mut light, self.time, get_target_position);
+        }
+        self.light = light;
+        self.sync_object();
+    }
+
+    // the cube is drawn where the light is
+    pub fn sync_object(&mut self) {
+        self.object.position = self.light.position;
+        self.object.update_model();
+    }
+}
+
+pub struct KinematicCube {
+    pub object: Renderable3dObject,
+    pub body: KinematicBody,
+}
+
+impl KinematicCube {
+    pub fn new(path: KinematicPath, scale: f32) -> Self {
+        let body = KinematicBody::new(path, Vector3::new(scale, scale, scale));
+        KinematicCube {
+            object: Renderable3dObject::from_full(body.position, Euler::from(body.rotation), scale),
+            body,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.body.update(delta_time);
+        self.object.position = self.body.position;
+        self.object.rotation = Euler::from(self.body.rotation);
+        self.object.update_model();
+    }
+}
+
+pub struct DynamicCube {
+    pub object: Renderable3dObject,
+    pub body: RigidBody,
+}
+
+impl DynamicCube {
+    pub fn new(position: Point3<f32>, scale: f32, density: f32) -> Self {
+        DynamicCube {
+            object: Renderable3dObject::from_full(position, Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)), scale),
+            body: RigidBody::new_box(position, Vector3::new(scale, scale, scale), density),
+        }
+    }
+
+    pub fn sync_object(&mut self) {
+        self.object.position = self.body.position;
+        self.object.rotation = Euler::from(self.body.rotation);
+        self.object.update_model();
+    }
 }
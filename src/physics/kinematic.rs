@@ -0,0 +1,196 @@
+use cgmath::{InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3, Zero};
+
+use crate::physics::scene_query::BoxCollider;
+
+pub enum KinematicPath {
+    // goes back and forth between two points, taking `period` seconds for the round trip
+    PingPong {
+        from: Point3<f32>,
+        to: Point3<f32>,
+        period: f32,
+    },
+    // stays in place rotating around its own center
+    Spin {
+        center: Point3<f32>,
+        axis: Vector3<f32>,
+        angular_speed: f32,
+    },
+}
+
+impl KinematicPath {
+    pub fn get_transform(&self, time: f32) -> (Point3<f32>, Quaternion<f32>) {
+        match self {
+            KinematicPath::PingPong { from, to, period } => {
+                if *period <= 0.0 {
+                    return (*from, Quaternion::new(1.0, 0.0, 0.0, 0.0));
+                }
+                // smooth the turns so the velocity doesn't jump at the ends
+                let phase = (time / period).fract() * std::f32::consts::PI * 2.0;
+                let t = 0.5 - 0.5 * phase.cos();
+                (
+                    from + (to - from) * t,
+                    Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                )
+            }
+            KinematicPath::Spin {
+                center,
+                axis,
+                angular_speed,
+            } => (
+                *center,
+                Quaternion::from_axis_angle(axis.normalize(), Rad(angular_speed * time)),
+            ),
+        }
+    }
+}
+
+// body that is moved by its path instead of by forces. The velocities are derived
+// from how much the transform changed during the last tick, so anything touching
+// it gets pushed and carried along.
+pub struct KinematicBody {
+    pub path: KinematicPath,
+    pub half_extent: Vector3<f32>,
+    pub position: Point3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub linear_velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+    previous_position: Point3<f32>,
+    previous_rotation: Quaternion<f32>,
+    time: f32,
+}
+
+impl KinematicBody {
+    pub fn new(path: KinematicPath, half_extent: Vector3<f32>) -> Self {
+        let (position, rotation) = path.get_transform(0.0);
+        Self {
+            path,
+            half_extent,
+            position,
+            rotation,
+            linear_velocity: Vector3::zero(),
+            angular_velocity: Vector3::zero(),
+            previous_position: position,
+            previous_rotation: rotation,
+            time: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        if delta_time <= 0.0 {
+            return;
+        }
+
+        self.time += delta_time;
+        self.previous_position = self.position;
+        self.previous_rotation = self.rotation;
+
+        let (position, rotation) = self.path.get_transform(self.time);
+        self.position = position;
+        self.rotation = rotation;
+
+        self.linear_velocity = (self.position - self.previous_position) / delta_time;
+
+        // axis-angle of the rotation done in this tick
+        let mut delta = self.rotation * self.previous_rotation.invert();
+        if delta.s < 0.0 {
+            // take the shortest way around
+            delta = -delta;
+        }
+        let sin_half_angle = delta.v.magnitude();
+        self.angular_velocity = if sin_half_angle > 1e-6 {
+            let angle = 2.0 * sin_half_angle.atan2(delta.s);
+            delta.v / sin_half_angle * (angle / delta_time)
+        } else {
+            Vector3::zero()
+        };
+    }
+
+    pub fn get_collider(&self) -> BoxCollider {
+        BoxCollider {
+            center: self.position,
+            half_extent: self.half_extent,
+            rotation: self.rotation,
+            linear_velocity: self.linear_velocity,
+            angular_velocity: self.angular_velocity,
+        }
+    }
+
+    // where a point attached to the body during the last tick ended up
+    pub fn carry_point(&self, point: Point3<f32>) -> Point3<f32> {
+        let delta = self.rotation * self.previous_rotation.invert();
+        self.position + delta.rotate_vector(point - self.previous_position)
+    }
+
+    // true if the point is inside the footprint and closer than `height` to the top face, a bit
+    // under it still counts since the feet sink in when the body moved up
+    pub fn is_standing_on(&self, point: Point3<f32>, height: f32) -> bool {
+        let local = self
+            .previous_rotation
+            .invert()
+            .rotate_vector(point - self.previous_position);
+        local.x.abs() <= self.half_extent.x
+            && local.z.abs() <= self.half_extent.z
+            && (local.y - self.half_extent.y).abs() <= height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong_goes_to_the_end_and_back() {
+        let path = KinematicPath::PingPong {
+            from: Point3::new(0.0, 0.0, 0.0),
+            to: Point3::new(10.0, 0.0, 0.0),
+            period: 4.0,
+        };
+        assert!(path.get_transform(0.0).0.x.abs() < 1e-4);
+        assert!((path.get_transform(2.0).0.x - 10.0).abs() < 1e-4);
+        assert!(path.get_transform(4.0).0.x.abs() < 1e-4);
+    }
+
+    #[test]
+    fn ping_pong_without_period_stays_in_place() {
+        let path = KinematicPath::PingPong {
+            from: Point3::new(1.0, 2.0, 3.0),
+            to: Point3::new(10.0, 0.0, 0.0),
+            period: 0.0,
+        };
+        let mut body = KinematicBody::new(path, Vector3::new(1.0, 1.0, 1.0));
+        body.update(0.1);
+        assert_eq!(body.position, Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(body.linear_velocity, Vector3::zero());
+    }
+
+    #[test]
+    fn spin_gives_the_angular_velocity() {
+        let path = KinematicPath::Spin {
+            center: Point3::new(0.0, 0.0, 0.0),
+            axis: Vector3::new(0.0, 1.0, 0.0),
+            angular_speed: 2.0,
+        };
+        let mut body = KinematicBody::new(path, Vector3::new(1.0, 1.0, 1.0));
+        body.update(0.01);
+        assert!((body.angular_velocity - Vector3::new(0.0, 2.0, 0.0)).magnitude() < 1e-2);
+    }
+
+    #[test]
+    fn carries_what_stands_on_the_top_face() {
+        // the top face starts at y = 1 and goes up
+        let path = KinematicPath::PingPong {
+            from: Point3::new(0.0, 0.0, 0.0),
+            to: Point3::new(0.0, 10.0, 0.0),
+            period: 20.0,
+        };
+        let mut body = KinematicBody::new(path, Vector3::new(1.0, 1.0, 1.0));
+        body.update(1.0);
+
+        let feet = Point3::new(0.5, 1.0, 0.0);
+        assert!(body.is_standing_on(feet, 0.1));
+        assert!((body.carry_point(feet).y - (body.position.y + 1.0)).abs() < 1e-5);
+        // too high above it, or outside of its footprint
+        assert!(!body.is_standing_on(Point3::new(0.5, 1.5, 0.0), 0.1));
+        assert!(!body.is_standing_on(Point3::new(1.5, 1.0, 0.0), 0.1));
+    }
+}
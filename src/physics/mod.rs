@@ -0,0 +1,14 @@
+pub mod kinematic;
+pub mod rigid_body;
+pub mod scene_query;
+pub mod spatial_hash;
+pub mod sph;
+pub mod trajectory;
+pub mod vehicle;
+pub mod water;
+
+use cgmath::Vector3;
+
+pub fn default_gravity() -> Vector3<f32> {
+    Vector3::new(0.0, -9.81, 0.0)
+}
@@ -0,0 +1,164 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Point3, Quaternion, Rotation, SquareMatrix, Vector3, Zero};
+
+use crate::physics::scene_query::BoxCollider;
+
+// dynamic box driven by forces and impulses
+pub struct RigidBody {
+    pub position: Point3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub linear_velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+    pub half_extent: Vector3<f32>,
+    pub mass: f32,
+    pub restitution: f32,
+    pub friction: f32,
+    // fraction of the volume under water, updated by the water volumes
+    pub submerged_fraction: f32,
+    force: Vector3<f32>,
+    torque: Vector3<f32>,
+}
+
+impl RigidBody {
+    pub fn new_box(position: Point3<f32>, half_extent: Vector3<f32>, density: f32) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            linear_velocity: Vector3::zero(),
+            angular_velocity: Vector3::zero(),
+            half_extent,
+            mass: density * 8.0 * half_extent.x * half_extent.y * half_extent.z,
+            restitution: 0.2,
+            friction: 0.5,
+            submerged_fraction: 0.0,
+            force: Vector3::zero(),
+            torque: Vector3::zero(),
+        }
+    }
+
+    pub fn get_volume(&self) -> f32 {
+        8.0 * self.half_extent.x * self.half_extent.y * self.half_extent.z
+    }
+
+    pub fn get_inverse_inertia(&self) -> Matrix3<f32> {
+        let e = self.half_extent;
+        let local = Matrix3::from_diagonal(Vector3::new(
+            3.0 / (self.mass * (e.y * e.y + e.z * e.z)),
+            3.0 / (self.mass * (e.x * e.x + e.z * e.z)),
+            3.0 / (self.mass * (e.x * e.x + e.y * e.y)),
+        ));
+        let rotation = Matrix3::from(self.rotation);
+        rotation * local * rotation.transpose()
+    }
+
+    pub fn get_point_velocity(&self, point: Point3<f32>) -> Vector3<f32> {
+        self.linear_velocity + self.angular_velocity.cross(point - self.position)
+    }
+
+    pub fn get_world_point(&self, local: Vector3<f32>) -> Point3<f32> {
+        self.position + self.rotation.rotate_vector(local)
+    }
+
+    pub fn get_corners(&self) -> [Point3<f32>; 8] {
+        let e = self.half_extent;
+        let mut corners = [self.position; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let local = Vector3::new(
+                if i & 1 == 0 { -e.x } else { e.x },
+                if i & 2 == 0 { -e.y } else { e.y },
+                if i & 4 == 0 { -e.z } else { e.z },
+            );
+            *corner = self.get_world_point(local);
+        }
+        corners
+    }
+
+    pub fn get_collider(&self) -> BoxCollider {
+        BoxCollider {
+            center: self.position,
+            half_extent: self.half_extent,
+            rotation: self.rotation,
+            linear_velocity: self.linear_velocity,
+            angular_velocity: self.angular_velocity,
+        }
+    }
+
+    pub fn apply_force_at_point(&mut self, force: Vector3<f32>, point: Point3<f32>) {
+        self.force += force;
+        self.torque += (point - self.position).cross(force);
+    }
+
+    pub fn apply_torque(&mut self, torque: Vector3<f32>) {
+        self.torque += torque;
+    }
+
+    pub fn apply_impulse_at_point(&mut self, impulse: Vector3<f32>, point: Point3<f32>) {
+        self.linear_velocity += impulse / self.mass;
+        self.angular_velocity += self.get_inverse_inertia() * (point - self.position).cross(impulse);
+    }
+
+    // semi-implicit euler, the accumulated forces are cleared afterwards
+    pub fn integrate(&mut self, delta_time: f32, gravity: Vector3<f32>) {
+        self.linear_velocity += (self.force / self.mass + gravity) * delta_time;
+        self.angular_velocity += self.get_inverse_inertia() * self.torque * delta_time;
+
+        self.position += self.linear_velocity * delta_time;
+        let spin = Quaternion::from_sv(0.0, self.angular_velocity) * self.rotation * 0.5;
+        self.rotation = (self.rotation + spin * delta_time).normalize();
+
+        self.force = Vector3::zero();
+        self.torque = Vector3::zero();
+    }
+
+    // pushes the corners out of the colliders and removes the velocity going into them
+    pub fn resolve_contacts(&mut self, colliders: &[BoxCollider]) {
+        for corner in self.get_corners().iter() {
+            for collider in colliders.iter() {
+                let local = collider.get_local_point(*corner);
+                let inside = (0..3).all(|axis| local[axis].abs() < collider.half_extent[axis]);
+                if !inside {
+                    continue;
+                }
+
+                let mut closest_axis = 0;
+                let mut closest_depth = f32::MAX;
+                for axis in 0..3 {
+                    let depth = collider.half_extent[axis] - local[axis].abs();
+                    if depth < closest_depth {
+                        closest_axis = axis;
+                        closest_depth = depth;
+                    }
+                }
+                let mut local_normal = Vector3::zero();
+                local_normal[closest_axis] = local[closest_axis].signum();
+                let normal = collider.rotation.rotate_vector(local_normal);
+
+                self.position += normal * closest_depth;
+                let point = *corner + normal * closest_depth;
+
+                let relative_velocity =
+                    self.get_point_velocity(point) - collider.get_point_velocity(point);
+                let normal_speed = relative_velocity.dot(normal);
+                if normal_speed >= 0.0 {
+                    continue;
+                }
+
+                let arm = point - self.position;
+                let inverse_inertia = self.get_inverse_inertia();
+                let angular_term = (inverse_inertia * arm.cross(normal)).cross(arm).dot(normal);
+                let impulse =
+                    -(1.0 + self.restitution) * normal_speed / (1.0 / self.mass + angular_term);
+                self.apply_impulse_at_point(normal * impulse, point);
+
+                let tangential_velocity = relative_velocity - normal * normal_speed;
+                let tangential_speed = tangential_velocity.magnitude();
+                if tangential_speed > 1e-4 {
+                    let tangent = tangential_velocity / tangential_speed;
+                    let angular_term = (inverse_inertia * arm.cross(tangent)).cross(arm).dot(tangent);
+                    let friction_impulse = (tangential_speed / (1.0 / self.mass + angular_term))
+                        .min(self.friction * impulse);
+                    self.apply_impulse_at_point(-tangent * friction_impulse, point);
+                }
+            }
+        }
+    }
+}
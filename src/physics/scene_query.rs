@@ -0,0 +1,566 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation, Vector3, Zero};
+use std::collections::HashMap;
+
+// slightly off the axes, so the rays don't go along the edges of axis aligned meshes
+const CONTAINS_RAY_DIRECTIONS: [[f32; 3]; 3] = [
+    [1.0, 0.013, 0.007],
+    [0.011, 1.0, 0.017],
+    [0.019, 0.005, 1.0],
+];
+
+#[derive(Copy, Clone)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+// oriented box that can be moving, static colliders just have zero velocities
+#[derive(Copy, Clone)]
+pub struct BoxCollider {
+    pub center: Point3<f32>,
+    pub half_extent: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub linear_velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+}
+
+// static triangle soup in world space, the triangles are sorted into a grid so only the ones
+// close to a point are tested
+pub struct MeshCollider {
+    pub triangles: Vec<[Point3<f32>; 3]>,
+    pub bounds: Aabb,
+    cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+#[derive(Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: Point3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn from_center(center: Point3<f32>, half_extent: Vector3<f32>) -> Self {
+        Self {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (0..3).all(|axis| point[axis] >= self.min[axis] && point[axis] <= self.max[axis])
+    }
+
+    // slab test, direction has to be normalized
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<RayHit> {
+        let mut t_min = 0.0f32;
+        let mut t_max = max_distance;
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+
+        for axis in 0..3 {
+            if direction[axis].abs() < f32::EPSILON {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+
+            let inverse = 1.0 / direction[axis];
+            let mut t_near = (self.min[axis] - origin[axis]) * inverse;
+            let mut t_far = (self.max[axis] - origin[axis]) * inverse;
+            let mut sign = -1.0;
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+                sign = 1.0;
+            }
+
+            if t_near > t_min {
+                t_min = t_near;
+                normal = Vector3::new(0.0, 0.0, 0.0);
+                normal[axis] = sign;
+            }
+            t_max = t_max.min(t_far);
+
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        // rays starting inside the box don't count as a hit
+        if normal.magnitude2() == 0.0 {
+            return None;
+        }
+
+        Some(RayHit {
+            distance: t_min,
+            point: origin + direction * t_min,
+            normal,
+        })
+    }
+}
+
+impl BoxCollider {
+    pub fn new_static(center: Point3<f32>, half_extent: Vector3<f32>) -> Self {
+        Self {
+            center,
+            half_extent,
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            linear_velocity: Vector3::zero(),
+            angular_velocity: Vector3::zero(),
+        }
+    }
+
+    pub fn get_local_point(&self, point: Point3<f32>) -> Point3<f32> {
+        Point3::from_vec(self.rotation.invert().rotate_vector(point - self.center))
+    }
+
+    pub fn get_point_velocity(&self, point: Point3<f32>) -> Vector3<f32> {
+        self.linear_velocity + self.angular_velocity.cross(point - self.center)
+    }
+
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<RayHit> {
+        let local_box = Aabb::from_center(Point3::origin(), self.half_extent);
+        let local_direction = self.rotation.invert().rotate_vector(direction);
+
+        local_box
+            .raycast(self.get_local_point(origin), local_direction, max_distance)
+            .map(|hit| RayHit {
+                distance: hit.distance,
+                point: origin + direction * hit.distance,
+                normal: self.rotation.rotate_vector(hit.normal),
+            })
+    }
+}
+
+impl MeshCollider {
+    pub fn new(triangles: Vec<[Point3<f32>; 3]>) -> Self {
+        let mut bounds = Aabb {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        };
+        let mut total_size = 0.0;
+        for triangle in triangles.iter() {
+            let triangle_bounds = get_triangle_bounds(triangle);
+            for axis in 0..3 {
+                bounds.min[axis] = bounds.min[axis].min(triangle_bounds.min[axis]);
+                bounds.max[axis] = bounds.max[axis].max(triangle_bounds.max[axis]);
+            }
+            let size = triangle_bounds.max - triangle_bounds.min;
+            total_size += size.x.max(size.y).max(size.z);
+        }
+
+        // about the size of a triangle, so each one is in a few cells
+        let cell_size = (total_size / triangles.len().max(1) as f32).max(1e-3);
+        let mut cells: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+        for (i, triangle) in triangles.iter().enumerate() {
+            let triangle_bounds = get_triangle_bounds(triangle);
+            let min = get_cell(triangle_bounds.min, cell_size);
+            let max = get_cell(triangle_bounds.max, cell_size);
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        cells.entry((x, y, z)).or_default().push(i);
+                    }
+                }
+            }
+        }
+
+        Self {
+            triangles,
+            bounds,
+            cell_size,
+            cells,
+        }
+    }
+
+    // closest point of the surface and the normal of its triangle, if it's closer than
+    // max_distance
+    pub fn get_closest_point(
+        &self,
+        point: Point3<f32>,
+        max_distance: f32,
+    ) -> Option<(Point3<f32>, Vector3<f32>)> {
+        let margin = Vector3::new(max_distance, max_distance, max_distance);
+        let bounds = Aabb {
+            min: self.bounds.min - margin,
+            max: self.bounds.max + margin,
+        };
+        if !bounds.contains(point) {
+            return None;
+        }
+
+        let mut closest = None;
+        let mut closest_distance2 = max_distance * max_distance;
+        let (x, y, z) = get_cell(point, self.cell_size);
+        let reach = (max_distance / self.cell_size).ceil() as i32;
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let indices = match self.cells.get(&(x + dx, y + dy, z + dz)) {
+                        Some(indices) => indices,
+                        None => continue,
+                    };
+                    for &i in indices.iter() {
+                        let [a, b, c] = self.triangles[i];
+                        let closest_point = get_closest_point_on_triangle(point, a, b, c);
+                        let distance2 = (point - closest_point).magnitude2();
+                        if distance2 < closest_distance2 {
+                            closest_distance2 = distance2;
+                            closest = Some((closest_point, (b - a).cross(c - a).normalize()));
+                        }
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    // only the rays that get into the bounds go through the triangles, the normal faces the ray,
+    // direction has to be normalized
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Option<RayHit> {
+        if !self.bounds.contains(origin)
+            && self
+                .bounds
+                .raycast(origin, direction, max_distance)
+                .is_none()
+        {
+            return None;
+        }
+
+        let mut closest: Option<RayHit> = None;
+        for triangle in self.triangles.iter() {
+            let max_distance = closest.map_or(max_distance, |hit| hit.distance);
+            let distance = match raycast_triangle(origin, direction, triangle) {
+                Some(distance) if distance <= max_distance => distance,
+                _ => continue,
+            };
+            let [a, b, c] = *triangle;
+            let normal = (b - a).cross(c - a).normalize();
+            closest = Some(RayHit {
+                distance,
+                point: origin + direction * distance,
+                normal: if normal.dot(direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                },
+            });
+        }
+
+        closest
+    }
+
+    // an odd number of triangles crossed on the way out is inside, each ray votes so a hole in
+    // the mesh only spoils one of them, this goes through every triangle so it's for
+    // precomputing
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        if !self.bounds.contains(point) {
+            return false;
+        }
+
+        let votes = CONTAINS_RAY_DIRECTIONS
+            .iter()
+            .filter(|&&direction| {
+                let direction = Vector3::from(direction).normalize();
+                let crossings = self
+                    .triangles
+                    .iter()
+                    .filter(|triangle| raycast_triangle(point, direction, triangle).is_some())
+                    .count();
+                crossings % 2 == 1
+            })
+            .count();
+        votes >= 2
+    }
+}
+
+// moller-trumbore, the distance to the triangle along the ray from either side
+pub fn raycast_triangle(
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    triangle: &[Point3<f32>; 3],
+) -> Option<f32> {
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let p = direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let ao = origin - a;
+    let u = ao.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = ao.cross(ab);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = ac.dot(q) * inverse;
+    if distance < 0.0 {
+        return None;
+    }
+    Some(distance)
+}
+
+fn get_cell(point: Point3<f32>, cell_size: f32) -> (i32, i32, i32) {
+    (
+        (point.x / cell_size).floor() as i32,
+        (point.y / cell_size).floor() as i32,
+        (point.z / cell_size).floor() as i32,
+    )
+}
+
+fn get_triangle_bounds(triangle: &[Point3<f32>; 3]) -> Aabb {
+    let [a, b, c] = *triangle;
+    Aabb {
+        min: Point3::new(
+            a.x.min(b.x).min(c.x),
+            a.y.min(b.y).min(c.y),
+            a.z.min(b.z).min(c.z),
+        ),
+        max: Point3::new(
+            a.x.max(b.x).max(c.x),
+            a.y.max(b.y).max(c.y),
+            a.z.max(b.z).max(c.z),
+        ),
+    }
+}
+
+// from real-time collision detection, checks which vertex, edge or the face is the closest
+// with barycentric coordinates
+pub fn get_closest_point_on_triangle(
+    p: Point3<f32>,
+    a: Point3<f32>,
+    b: Point3<f32>,
+    c: Point3<f32>,
+) -> Point3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+pub fn raycast(
+    colliders: &[BoxCollider],
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<RayHit> {
+    let direction = direction.normalize();
+    let mut closest: Option<RayHit> = None;
+
+    for collider in colliders.iter() {
+        let max_distance = match closest {
+            Some(hit) => hit.distance,
+            None => max_distance,
+        };
+        if let Some(hit) = collider.raycast(origin, direction, max_distance) {
+            closest = Some(hit);
+        }
+    }
+
+    closest
+}
+
+// the same for the meshes
+pub fn raycast_meshes(
+    mesh_colliders: &[MeshCollider],
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    max_distance: f32,
+) -> Option<RayHit> {
+    let direction = direction.normalize();
+    let mut closest: Option<RayHit> = None;
+
+    for mesh in mesh_colliders.iter() {
+        let max_distance = match closest {
+            Some(hit) => hit.distance,
+            None => max_distance,
+        };
+        if let Some(hit) = mesh.raycast(origin, direction, max_distance) {
+            closest = Some(hit);
+        }
+    }
+
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_box() -> Aabb {
+        Aabb::from_center(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn aabb_raycast_hits_the_closest_face() {
+        let hit = create_box()
+            .raycast(
+                Point3::new(0.0, 0.0, -5.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                10.0,
+            )
+            .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!((hit.point - Point3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+        assert_eq!(hit.normal, Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn aabb_raycast_misses() {
+        let aabb = create_box();
+        // passes next to the box
+        assert!(aabb
+            .raycast(
+                Point3::new(2.0, 0.0, -5.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                10.0
+            )
+            .is_none());
+        // points away from it
+        assert!(aabb
+            .raycast(
+                Point3::new(0.0, 0.0, -5.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                10.0
+            )
+            .is_none());
+        // too short to reach it
+        assert!(aabb
+            .raycast(
+                Point3::new(0.0, 0.0, -5.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                3.0
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn aabb_raycast_from_inside_doesnt_hit() {
+        assert!(create_box()
+            .raycast(
+                Point3::new(0.0, 0.5, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                10.0
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn raycast_returns_the_closest_collider() {
+        let colliders = [
+            BoxCollider::new_static(Point3::new(0.0, 0.0, 10.0), Vector3::new(1.0, 1.0, 1.0)),
+            BoxCollider::new_static(Point3::new(0.0, 0.0, 5.0), Vector3::new(1.0, 1.0, 1.0)),
+        ];
+        let hit = raycast(
+            &colliders,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+            100.0,
+        )
+        .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn closest_point_on_triangle() {
+        let a = Point3::new(0.0, 0.0, 0.0);
+        let b = Point3::new(2.0, 0.0, 0.0);
+        let c = Point3::new(0.0, 0.0, 2.0);
+        // above the face
+        let point = get_closest_point_on_triangle(Point3::new(0.5, 3.0, 0.5), a, b, c);
+        assert!((point - Point3::new(0.5, 0.0, 0.5)).magnitude() < 1e-5);
+        // past a vertex
+        let point = get_closest_point_on_triangle(Point3::new(-1.0, 1.0, -1.0), a, b, c);
+        assert!((point - a).magnitude() < 1e-5);
+        // past the long edge
+        let point = get_closest_point_on_triangle(Point3::new(2.0, 0.0, 2.0), a, b, c);
+        assert!((point - Point3::new(1.0, 0.0, 1.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn mesh_collider_finds_the_closest_triangle() {
+        // two triangles of a floor at y = 0
+        let mesh = MeshCollider::new(vec![
+            [
+                Point3::new(-1.0, 0.0, -1.0),
+                Point3::new(-1.0, 0.0, 1.0),
+                Point3::new(1.0, 0.0, 1.0),
+            ],
+            [
+                Point3::new(-1.0, 0.0, -1.0),
+                Point3::new(1.0, 0.0, 1.0),
+                Point3::new(1.0, 0.0, -1.0),
+            ],
+        ]);
+
+        let (point, normal) = mesh
+            .get_closest_point(Point3::new(0.5, 0.2, -0.5), 0.5)
+            .unwrap();
+        assert!((point - Point3::new(0.5, 0.0, -0.5)).magnitude() < 1e-5);
+        assert!((normal - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-5);
+        assert!(mesh
+            .get_closest_point(Point3::new(0.5, 0.7, -0.5), 0.5)
+            .is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use cgmath::Point3;
+use std::collections::HashMap;
+
+type Cell = (i32, i32, i32);
+
+pub struct SpatialHash {
+    pub cell_size: f32,
+    cells: HashMap<Cell, Vec<usize>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn get_cell(&self, point: Point3<f32>) -> Cell {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+            (point.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn rebuild(&mut self, points: &[Point3<f32>]) {
+        // keep the allocated cells of the last step around, most of them will be reused, the
+        // ones that were already empty are dropped so the map doesn't grow with every cell the
+        // points ever went through
+        self.cells.retain(|_, indices| {
+            let used = !indices.is_empty();
+            indices.clear();
+            used
+        });
+
+        for (i, point) in points.iter().enumerate() {
+            let cell = self.get_cell(*point);
+            self.cells.entry(cell).or_default().push(i);
+        }
+    }
+
+    // writes every index in the 27 cells around the point, so the result contains
+    // all points closer than cell_size (and a few more)
+    pub fn query(&self, point: Point3<f32>, result: &mut Vec<usize>) {
+        result.clear();
+
+        let (x, y, z) = self.get_cell(point);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(indices) = self.cells.get(&(x + dx, y + dy, z + dz)) {
+                        result.extend_from_slice(indices);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn query_finds_every_close_point() {
+        // points on a skewed grid across the cell borders, some of them negative
+        let points: Vec<Point3<f32>> = (0..500)
+            .map(|i| {
+                let i = i as f32;
+                Point3::new(
+                    (i * 0.37).sin() * 3.0,
+                    (i * 0.53).cos() * 3.0,
+                    (i * 0.11).sin() * 3.0,
+                )
+            })
+            .collect();
+        let mut spatial_hash = SpatialHash::new(0.5);
+        spatial_hash.rebuild(&points);
+
+        let mut result = Vec::new();
+        for point in points.iter() {
+            spatial_hash.query(*point, &mut result);
+            for (j, other) in points.iter().enumerate() {
+                if (other - point).magnitude() < spatial_hash.cell_size {
+                    assert!(result.contains(&j));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rebuild_forgets_the_old_points() {
+        let mut spatial_hash = SpatialHash::new(1.0);
+        spatial_hash.rebuild(&[Point3::new(0.5, 0.5, 0.5), Point3::new(10.0, 0.0, 0.0)]);
+        spatial_hash.rebuild(&[Point3::new(10.0, 0.0, 0.0)]);
+
+        let mut result = Vec::new();
+        spatial_hash.query(Point3::new(0.5, 0.5, 0.5), &mut result);
+        assert!(result.is_empty());
+        spatial_hash.query(Point3::new(10.2, 0.0, 0.0), &mut result);
+        assert_eq!(result, vec![0]);
+    }
+
+    #[test]
+    fn rebuild_drops_the_cells_left_empty() {
+        let mut spatial_hash = SpatialHash::new(1.0);
+        for i in 0..100 {
+            spatial_hash.rebuild(&[Point3::new(i as f32, 0.0, 0.0)]);
+        }
+        // the cell of the last point and the one it just left
+        assert_eq!(spatial_hash.cells.len(), 2);
+    }
+}
@@ -0,0 +1,476 @@
+use cgmath::{InnerSpace, Point3, Rotation, Vector3, Zero};
+use std::f32::consts::PI;
+
+use crate::physics::rigid_body::RigidBody;
+use crate::physics::scene_query::{Aabb, BoxCollider, MeshCollider};
+use crate::physics::spatial_hash::SpatialHash;
+
+#[derive(Clone)]
+pub struct SphSettings {
+    // distance between particles when the fluid is at rest
+    pub particle_spacing: f32,
+    pub smoothing_radius: f32,
+    pub rest_density: f32,
+    pub stiffness: f32,
+    pub viscosity: f32,
+    pub gravity: Vector3<f32>,
+    // fraction of the normal velocity kept after bouncing on a boundary
+    pub restitution: f32,
+    // fraction of the tangential velocity (relative to the collider) lost on contact
+    pub friction: f32,
+    pub time_step: f32,
+    pub max_steps_per_update: u32,
+}
+
+impl SphSettings {
+    pub fn water(gravity: Vector3<f32>) -> Self {
+        let particle_spacing = 0.25;
+        Self {
+            particle_spacing,
+            smoothing_radius: particle_spacing * 2.0,
+            rest_density: 1000.0,
+            stiffness: 300.0,
+            viscosity: 2.0,
+            gravity,
+            restitution: 0.2,
+            friction: 0.05,
+            time_step: 1.0 / 120.0,
+            max_steps_per_update: 4,
+        }
+    }
+
+    pub fn goo(gravity: Vector3<f32>) -> Self {
+        Self {
+            viscosity: 40.0,
+            restitution: 0.0,
+            friction: 0.5,
+            ..Self::water(gravity)
+        }
+    }
+
+    pub fn get_particle_mass(&self) -> f32 {
+        self.rest_density * self.particle_spacing.powi(3)
+    }
+}
+
+pub struct SphSimulation {
+    pub settings: SphSettings,
+    // the fluid can't leave this box
+    pub bounds: Aabb,
+    pub positions: Vec<Point3<f32>>,
+    pub velocities: Vec<Vector3<f32>>,
+    pub densities: Vec<f32>,
+    pub pressures: Vec<f32>,
+    forces: Vec<Vector3<f32>>,
+    spatial_hash: SpatialHash,
+    neighbours: Vec<usize>,
+    time_accumulator: f32,
+}
+
+impl SphSimulation {
+    pub fn new(settings: SphSettings, bounds: Aabb) -> Self {
+        Self {
+            spatial_hash: SpatialHash::new(settings.smoothing_radius),
+            settings,
+            bounds,
+            positions: Vec::new(),
+            velocities: Vec::new(),
+            densities: Vec::new(),
+            pressures: Vec::new(),
+            forces: Vec::new(),
+            neighbours: Vec::new(),
+            time_accumulator: 0.0,
+        }
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.velocities.clear();
+        self.densities.clear();
+        self.pressures.clear();
+        self.forces.clear();
+    }
+
+    pub fn add_particle(&mut self, position: Point3<f32>, velocity: Vector3<f32>) {
+        self.positions.push(position);
+        self.velocities.push(velocity);
+        self.densities.push(self.settings.rest_density);
+        self.pressures.push(0.0);
+        self.forces.push(Vector3::zero());
+    }
+
+    // fills the box with particles at rest spacing, slightly jittered so the
+    // block doesn't stay perfectly stacked
+    pub fn add_block(&mut self, block: Aabb) {
+        let spacing = self.settings.particle_spacing;
+        let size = block.max - block.min;
+        let counts = (
+            (size.x / spacing).floor() as u32,
+            (size.y / spacing).floor() as u32,
+            (size.z / spacing).floor() as u32,
+        );
+
+        for x in 0..counts.0 {
+            for y in 0..counts.1 {
+                for z in 0..counts.2 {
+                    let jitter = ((x * 7 + y * 13 + z * 17) % 10) as f32 * 0.001 * spacing;
+                    let position = block.min
+                        + Vector3::new(
+                            (x as f32 + 0.5) * spacing + jitter,
+                            (y as f32 + 0.5) * spacing,
+                            (z as f32 + 0.5) * spacing - jitter,
+                        );
+                    self.add_particle(position, Vector3::zero());
+                }
+            }
+        }
+    }
+
+    // advances the simulation by fixed time steps, carrying the remaining time
+    // to the next update, the fluid pushes the bodies and they push it back
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+        bodies: &mut [&mut RigidBody],
+    ) {
+        self.time_accumulator += delta_time;
+
+        let mut steps = 0;
+        while self.time_accumulator >= self.settings.time_step {
+            if steps == self.settings.max_steps_per_update {
+                // too slow to catch up, drop the time instead of spiraling
+                self.time_accumulator = 0.0;
+                break;
+            }
+            self.step(colliders, mesh_colliders, bodies);
+            self.time_accumulator -= self.settings.time_step;
+            steps += 1;
+        }
+    }
+
+    pub fn step(
+        &mut self,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+        bodies: &mut [&mut RigidBody],
+    ) {
+        self.spatial_hash.rebuild(&self.positions);
+        self.compute_densities();
+        self.compute_forces();
+        self.integrate();
+        self.resolve_collisions(colliders, mesh_colliders, bodies);
+    }
+
+    fn compute_densities(&mut self) {
+        let h = self.settings.smoothing_radius;
+        let h2 = h * h;
+        let mass = self.settings.get_particle_mass();
+        let poly6 = 315.0 / (64.0 * PI * h.powi(9));
+
+        for i in 0..self.positions.len() {
+            self.spatial_hash
+                .query(self.positions[i], &mut self.neighbours);
+
+            let mut density = 0.0;
+            for &j in self.neighbours.iter() {
+                let r2 = (self.positions[j] - self.positions[i]).magnitude2();
+                if r2 < h2 {
+                    density += mass * poly6 * (h2 - r2).powi(3);
+                }
+            }
+
+            self.densities[i] = density;
+            // negative pressures make the free surface clump together
+            self.pressures[i] =
+                (self.settings.stiffness * (density - self.settings.rest_density)).max(0.0);
+        }
+    }
+
+    fn compute_forces(&mut self) {
+        let h = self.settings.smoothing_radius;
+        let mass = self.settings.get_particle_mass();
+        let spiky_gradient = -45.0 / (PI * h.powi(6));
+        let viscosity_laplacian = 45.0 / (PI * h.powi(6));
+
+        for i in 0..self.positions.len() {
+            self.spatial_hash
+                .query(self.positions[i], &mut self.neighbours);
+
+            let mut pressure_force = Vector3::zero();
+            let mut viscosity_force = Vector3::zero();
+            for &j in self.neighbours.iter() {
+                if i == j {
+                    continue;
+                }
+
+                let offset = self.positions[i] - self.positions[j];
+                let r = offset.magnitude();
+                if r >= h || r < 1e-6 {
+                    continue;
+                }
+
+                let direction = offset / r;
+                let density_j = self.densities[j];
+
+                pressure_force -= direction
+                    * (mass * (self.pressures[i] + self.pressures[j]) / (2.0 * density_j)
+                        * spiky_gradient
+                        * (h - r).powi(2));
+
+                viscosity_force += (self.velocities[j] - self.velocities[i])
+                    * (self.settings.viscosity * mass / density_j * viscosity_laplacian * (h - r));
+            }
+
+            self.forces[i] = pressure_force + viscosity_force;
+        }
+    }
+
+    fn integrate(&mut self) {
+        let dt = self.settings.time_step;
+        for i in 0..self.positions.len() {
+            let acceleration = self.forces[i] / self.densities[i] + self.settings.gravity;
+            self.velocities[i] += acceleration * dt;
+            self.positions[i] += self.velocities[i] * dt;
+        }
+    }
+
+    fn resolve_collisions(
+        &mut self,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+        bodies: &mut [&mut RigidBody],
+    ) {
+        let radius = self.settings.particle_spacing * 0.5;
+        let restitution = self.settings.restitution;
+        let friction = self.settings.friction;
+        let mass = self.settings.get_particle_mass();
+
+        for i in 0..self.positions.len() {
+            let position = &mut self.positions[i];
+            let velocity = &mut self.velocities[i];
+
+            // keep inside the bounds
+            for axis in 0..3 {
+                let min = self.bounds.min[axis] + radius;
+                let max = self.bounds.max[axis] - radius;
+                if position[axis] < min {
+                    position[axis] = min;
+                    if velocity[axis] < 0.0 {
+                        velocity[axis] *= -restitution;
+                    }
+                } else if position[axis] > max {
+                    position[axis] = max;
+                    if velocity[axis] > 0.0 {
+                        velocity[axis] *= -restitution;
+                    }
+                }
+            }
+
+            for collider in colliders.iter() {
+                push_out_of_box(collider, radius, restitution, friction, position, velocity);
+            }
+
+            // the velocity the particle lost or gained goes to the body as an impulse
+            for body in bodies.iter_mut() {
+                let previous_velocity = *velocity;
+                let collider = body.get_collider();
+                if push_out_of_box(&collider, radius, restitution, friction, position, velocity) {
+                    body.apply_impulse_at_point((previous_velocity - *velocity) * mass, *position);
+                }
+            }
+
+            // the meshes are static, the particle is kept a radius away from the surface and
+            // put back on the front side if it got behind a triangle
+            for mesh in mesh_colliders.iter() {
+                let (point, normal) = match mesh.get_closest_point(*position, radius) {
+                    Some(closest) => closest,
+                    None => continue,
+                };
+                let offset = *position - point;
+                let distance = offset.magnitude();
+                let normal = if offset.dot(normal) > 0.0 && distance > 1e-6 {
+                    offset / distance
+                } else {
+                    normal
+                };
+                *position = point + normal * radius;
+
+                let normal_speed = velocity.dot(normal);
+                if normal_speed < 0.0 {
+                    let tangential_velocity = *velocity - normal * normal_speed;
+                    *velocity = tangential_velocity * (1.0 - friction)
+                        - normal * (normal_speed * restitution);
+                }
+            }
+        }
+    }
+}
+
+// pushes the particle out of the box through the closest face, the velocity is resolved
+// relative to the collider so moving ones drag the fluid along, returns true on contact
+fn push_out_of_box(
+    collider: &BoxCollider,
+    radius: f32,
+    restitution: f32,
+    friction: f32,
+    position: &mut Point3<f32>,
+    velocity: &mut Vector3<f32>,
+) -> bool {
+    let local = collider.get_local_point(*position);
+    let half_extent = collider.half_extent + Vector3::new(radius, radius, radius);
+    let inside = (0..3).all(|axis| local[axis].abs() < half_extent[axis]);
+    if !inside {
+        return false;
+    }
+
+    let mut closest_axis = 0;
+    let mut closest_depth = f32::MAX;
+    for axis in 0..3 {
+        let depth = half_extent[axis] - local[axis].abs();
+        if depth < closest_depth {
+            closest_axis = axis;
+            closest_depth = depth;
+        }
+    }
+
+    let mut local_normal = Vector3::zero();
+    local_normal[closest_axis] = local[closest_axis].signum();
+    let normal = collider.rotation.rotate_vector(local_normal);
+    *position += normal * closest_depth;
+
+    let collider_velocity = collider.get_point_velocity(*position);
+    let relative_velocity = *velocity - collider_velocity;
+    let normal_speed = relative_velocity.dot(normal);
+    if normal_speed < 0.0 {
+        let tangential_velocity = relative_velocity - normal * normal_speed;
+        *velocity = collider_velocity + tangential_velocity * (1.0 - friction)
+            - normal * (normal_speed * restitution);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::default_gravity;
+
+    fn create_simulation(bounds: Aabb) -> SphSimulation {
+        SphSimulation::new(SphSettings::water(default_gravity()), bounds)
+    }
+
+    #[test]
+    fn lattice_is_at_rest_density() {
+        let block = Aabb {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(2.0, 2.0, 2.0),
+        };
+        let mut simulation = create_simulation(block);
+        simulation.add_block(block);
+        simulation.spatial_hash.rebuild(&simulation.positions);
+        simulation.compute_densities();
+
+        // a particle in the middle has all its neighbours, the ones on the sides don't
+        let center = Point3::new(1.0, 1.0, 1.0);
+        let (i, _) = simulation
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| (i, (position - center).magnitude()))
+            .fold((0, f32::MAX), |closest, current| {
+                if current.1 < closest.1 {
+                    current
+                } else {
+                    closest
+                }
+            });
+        let rest_density = simulation.settings.rest_density;
+        assert!((simulation.densities[i] - rest_density).abs() < rest_density * 0.05);
+    }
+
+    #[test]
+    fn particles_stay_inside_the_bounds() {
+        let bounds = Aabb {
+            min: Point3::new(-1.0, -1.0, -1.0),
+            max: Point3::new(1.0, 1.0, 1.0),
+        };
+        let mut simulation = create_simulation(bounds);
+        simulation.add_block(Aabb {
+            min: Point3::new(-1.0, 0.0, -1.0),
+            max: Point3::new(0.0, 1.0, 0.0),
+        });
+        // thrown against the walls
+        for velocity in simulation.velocities.iter_mut() {
+            *velocity = Vector3::new(20.0, -10.0, 15.0);
+        }
+
+        for _ in 0..240 {
+            simulation.step(&[], &[], &mut []);
+        }
+
+        for (position, velocity) in simulation
+            .positions
+            .iter()
+            .zip(simulation.velocities.iter())
+        {
+            assert!(bounds.contains(*position));
+            assert!(velocity.x.is_finite() && velocity.y.is_finite() && velocity.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn particles_push_the_bodies() {
+        let mut simulation = create_simulation(Aabb {
+            min: Point3::new(-5.0, -5.0, -5.0),
+            max: Point3::new(5.0, 5.0, 5.0),
+        });
+        simulation.add_particle(Point3::new(0.0, 0.6, 0.0), Vector3::new(0.0, -2.0, 0.0));
+        let mut body = RigidBody::new_box(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.5, 0.5, 0.5),
+            100.0,
+        );
+
+        simulation.resolve_collisions(&[], &[], &mut [&mut body]);
+
+        // the momentum the particle lost went to the body
+        let mass = simulation.settings.get_particle_mass();
+        let particle_momentum = (simulation.velocities[0].y + 2.0) * mass;
+        assert!(simulation.velocities[0].y > -2.0);
+        assert!(body.linear_velocity.y < 0.0);
+        assert!((body.linear_velocity.y * body.mass + particle_momentum).abs() < 1e-4);
+    }
+
+    #[test]
+    fn particles_land_on_a_mesh() {
+        let mut simulation = create_simulation(Aabb {
+            min: Point3::new(-5.0, -5.0, -5.0),
+            max: Point3::new(5.0, 5.0, 5.0),
+        });
+        simulation.add_particle(Point3::new(0.0, 1.0, 0.0), Vector3::zero());
+        let floor = [MeshCollider::new(vec![
+            [
+                Point3::new(-2.0, 0.0, -2.0),
+                Point3::new(-2.0, 0.0, 2.0),
+                Point3::new(2.0, 0.0, 2.0),
+            ],
+            [
+                Point3::new(-2.0, 0.0, -2.0),
+                Point3::new(2.0, 0.0, 2.0),
+                Point3::new(2.0, 0.0, -2.0),
+            ],
+        ])];
+
+        for _ in 0..240 {
+            simulation.step(&[], &floor, &mut []);
+        }
+
+        let radius = simulation.settings.particle_spacing * 0.5;
+        assert!((simulation.positions[0].y - radius).abs() < 1e-3);
+    }
+}
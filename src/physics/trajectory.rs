@@ -0,0 +1,170 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::physics::scene_query::{raycast, raycast_meshes, BoxCollider, MeshCollider, RayHit};
+
+pub struct Trajectory {
+    pub points: Vec<Point3<f32>>,
+    pub hit: Option<RayHit>,
+}
+
+impl Trajectory {
+    // integrates the ballistic path step by step, stopping at the first surface
+    // crossed by one of the segments, get_acceleration gives the gravity and the other forces
+    // at a point for a velocity
+    pub fn predict(
+        origin: Point3<f32>,
+        velocity: Vector3<f32>,
+        get_acceleration: impl Fn(Point3<f32>, Vector3<f32>) -> Vector3<f32>,
+        colliders: &[BoxCollider],
+        mesh_colliders: &[MeshCollider],
+        time_step: f32,
+        max_steps: usize,
+    ) -> Self {
+        let mut points = Vec::with_capacity(max_steps + 1);
+        points.push(origin);
+
+        let mut position = origin;
+        let mut velocity = velocity;
+        for _ in 0..max_steps {
+            velocity += get_acceleration(position, velocity) * time_step;
+            let next_position = position + velocity * time_step;
+
+            let segment = next_position - position;
+            let length = segment.magnitude();
+            if length > 0.0 {
+                let box_hit = raycast(colliders, position, segment, length);
+                let max_distance = box_hit.map_or(length, |hit| hit.distance);
+                let hit =
+                    raycast_meshes(mesh_colliders, position, segment, max_distance).or(box_hit);
+                if let Some(hit) = hit {
+                    points.push(hit.point);
+                    return Self {
+                        points,
+                        hit: Some(hit),
+                    };
+                }
+            }
+
+            points.push(next_position);
+            position = next_position;
+        }
+
+        Self { points, hit: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lands_where_the_parabola_does() {
+        let height = 10.0;
+        let speed = 5.0;
+        let gravity = 9.81;
+        // the top of the ground is at y = 0
+        let ground = [BoxCollider::new_static(
+            Point3::new(0.0, -1.0, 0.0),
+            Vector3::new(100.0, 1.0, 100.0),
+        )];
+
+        let trajectory = Trajectory::predict(
+            Point3::new(0.0, height, 0.0),
+            Vector3::new(speed, 0.0, 0.0),
+            |_, _| Vector3::new(0.0, -gravity, 0.0),
+            &ground,
+            &[],
+            1.0 / 120.0,
+            1000,
+        );
+
+        let hit = trajectory.hit.unwrap();
+        let landing_x = speed * (2.0 * height / gravity).sqrt();
+        assert!(hit.point.y.abs() < 1e-4);
+        assert!((hit.point.x - landing_x).abs() < 0.05);
+        assert_eq!(hit.normal, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn stops_after_max_steps_without_hit() {
+        let trajectory = Trajectory::predict(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            |_, _| Vector3::new(0.0, -9.81, 0.0),
+            &[],
+            &[],
+            0.1,
+            20,
+        );
+        assert!(trajectory.hit.is_none());
+        assert_eq!(trajectory.points.len(), 21);
+    }
+
+    #[test]
+    fn follows_the_acceleration_at_each_point() {
+        // pushed sideways only past x = 1
+        let trajectory = Trajectory::predict(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            |point, _| {
+                if point.x > 1.0 {
+                    Vector3::new(0.0, 0.0, 2.0)
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            },
+            &[],
+            &[],
+            0.1,
+            20,
+        );
+
+        for point in trajectory.points.iter() {
+            if point.x <= 1.0 + 1e-4 {
+                assert!(point.z.abs() < 1e-6);
+            }
+        }
+        assert!(trajectory.points.last().unwrap().z > 0.5);
+    }
+
+    #[test]
+    fn hits_the_closer_of_a_mesh_and_a_box() {
+        // a wall of two triangles at x = 5, in front of a box at x = 10
+        let wall = [MeshCollider::new(vec![
+            [
+                Point3::new(5.0, -5.0, -5.0),
+                Point3::new(5.0, 5.0, -5.0),
+                Point3::new(5.0, 5.0, 5.0),
+            ],
+            [
+                Point3::new(5.0, -5.0, -5.0),
+                Point3::new(5.0, 5.0, 5.0),
+                Point3::new(5.0, -5.0, 5.0),
+            ],
+        ])];
+        let boxes = [BoxCollider::new_static(
+            Point3::new(11.0, 0.0, 0.0),
+            Vector3::new(1.0, 5.0, 5.0),
+        )];
+
+        let predict = |mesh_colliders: &[MeshCollider]| {
+            Trajectory::predict(
+                Point3::new(0.0, 0.0, 0.0),
+                Vector3::new(10.0, 0.0, 0.0),
+                |_, _| Vector3::new(0.0, 0.0, 0.0),
+                &boxes,
+                mesh_colliders,
+                0.15,
+                100,
+            )
+            .hit
+            .unwrap()
+        };
+
+        let hit = predict(&wall);
+        assert!((hit.point.x - 5.0).abs() < 1e-4);
+        // facing back to where the path came from
+        assert!((hit.normal - Vector3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-5);
+        assert!((predict(&[]).point.x - 10.0).abs() < 1e-4);
+    }
+}
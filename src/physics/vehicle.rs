@@ -0,0 +1,274 @@
+use cgmath::{InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+
+use crate::physics::{
+    rigid_body::RigidBody,
+    scene_query::{raycast, BoxCollider},
+};
+
+// the vehicle doesn't step with the whole frame because the springs are quite stiff
+const MAX_TIME_STEP: f32 = 1.0 / 120.0;
+
+pub struct Wheel {
+    // where the suspension is attached, in chassis space
+    pub attachment: Vector3<f32>,
+    pub rest_length: f32,
+    pub radius: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub steerable: bool,
+    pub powered: bool,
+    // how much the spring is compressed, 0.0 when the wheel is in the air
+    pub compression: f32,
+    pub contact_point: Option<Point3<f32>>,
+    pub steer_angle: f32,
+    // only used for rendering
+    pub spin_angle: f32,
+}
+
+impl Wheel {
+    pub fn new(attachment: Vector3<f32>, steerable: bool, powered: bool) -> Self {
+        Self {
+            attachment,
+            rest_length: 0.5,
+            radius: 0.4,
+            stiffness: 20000.0,
+            damping: 2500.0,
+            steerable,
+            powered,
+            compression: 0.0,
+            contact_point: None,
+            steer_angle: 0.0,
+            spin_angle: 0.0,
+        }
+    }
+
+    // distance from the attachment point to the wheel center
+    pub fn get_suspension_length(&self) -> f32 {
+        self.rest_length - self.compression
+    }
+}
+
+pub struct Vehicle {
+    pub chassis: RigidBody,
+    pub wheels: Vec<Wheel>,
+    pub engine_torque: f32,
+    pub brake_force: f32,
+    pub max_steer_angle: f32,
+    pub anti_roll_stiffness: f32,
+    pub tire_grip: f32,
+    // how high the side forces are applied, 0.0 is at the center of mass and 1.0 on the ground,
+    // lower values make the cart harder to roll over
+    pub roll_influence: f32,
+    // inputs, throttle and steering go from -1.0 to 1.0
+    pub throttle: f32,
+    pub brake: bool,
+    pub steering: f32,
+}
+
+// simplified Pacejka curve, grip rises quickly with the slip and then falls off a bit
+fn get_slip_curve(slip: f32) -> f32 {
+    (1.9 * (10.0 * slip).atan()).sin()
+}
+
+impl Vehicle {
+    // four wheels, steering in the front and traction in the back
+    pub fn new_cart(position: Point3<f32>) -> Self {
+        let half_extent = Vector3::new(0.9, 0.3, 1.8);
+        let mut chassis = RigidBody::new_box(position, half_extent, 150.0);
+        chassis.friction = 0.3;
+
+        let x = half_extent.x;
+        let y = -half_extent.y;
+        let z = half_extent.z - 0.4;
+        Self {
+            chassis,
+            wheels: vec![
+                Wheel::new(Vector3::new(-x, y, z), true, false),
+                Wheel::new(Vector3::new(x, y, z), true, false),
+                Wheel::new(Vector3::new(-x, y, -z), false, true),
+                Wheel::new(Vector3::new(x, y, -z), false, true),
+            ],
+            engine_torque: 1200.0,
+            brake_force: 4000.0,
+            max_steer_angle: 0.5,
+            anti_roll_stiffness: 8000.0,
+            tire_grip: 1.2,
+            roll_influence: 0.2,
+            throttle: 0.0,
+            brake: false,
+            steering: 0.0,
+        }
+    }
+
+    pub fn get_forward(&self) -> Vector3<f32> {
+        self.chassis.rotation.rotate_vector(Vector3::unit_z())
+    }
+
+    pub fn get_up(&self) -> Vector3<f32> {
+        self.chassis.rotation.rotate_vector(Vector3::unit_y())
+    }
+
+    pub fn get_wheel_center(&self, wheel: &Wheel) -> Point3<f32> {
+        self.chassis.get_world_point(wheel.attachment) - self.get_up() * wheel.get_suspension_length()
+    }
+
+    pub fn get_wheel_rotation(&self, wheel: &Wheel) -> Quaternion<f32> {
+        self.chassis.rotation
+            * Quaternion::from_angle_y(Rad(wheel.steer_angle))
+            * Quaternion::from_angle_x(Rad(wheel.spin_angle))
+    }
+
+    pub fn update(&mut self, delta_time: f32, gravity: Vector3<f32>, colliders: &[BoxCollider]) {
+        let steps = (delta_time / MAX_TIME_STEP).ceil().max(1.0);
+        let time_step = delta_time / steps;
+        for _ in 0..steps as u32 {
+            self.step(time_step, gravity, colliders);
+        }
+    }
+
+    fn step(&mut self, delta_time: f32, gravity: Vector3<f32>, colliders: &[BoxCollider]) {
+        let up = self.get_up();
+        let forward = self.get_forward();
+        let mass_share = self.chassis.mass / self.wheels.len() as f32;
+        let powered_count = self.wheels.iter().filter(|wheel| wheel.powered).count().max(1);
+
+        // suspension and tires
+        let mut forces = Vec::with_capacity(self.wheels.len());
+        for wheel in self.wheels.iter_mut() {
+            wheel.steer_angle = if wheel.steerable {
+                -self.steering * self.max_steer_angle
+            } else {
+                0.0
+            };
+
+            let attachment = self.chassis.get_world_point(wheel.attachment);
+            let max_distance = wheel.rest_length + wheel.radius;
+            let hit = raycast(colliders, attachment, -up, max_distance);
+
+            let previous_compression = wheel.compression;
+            let hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    wheel.compression = 0.0;
+                    wheel.contact_point = None;
+                    continue;
+                }
+            };
+            wheel.compression = (max_distance - hit.distance).min(wheel.rest_length);
+            wheel.contact_point = Some(hit.point);
+
+            let compression_speed = (wheel.compression - previous_compression) / delta_time;
+            let load = (wheel.stiffness * wheel.compression + wheel.damping * compression_speed)
+                .max(0.0);
+            forces.push((up * load, attachment));
+
+            // tire frame projected on the ground
+            let wheel_forward = Quaternion::from_axis_angle(up, Rad(wheel.steer_angle))
+                .rotate_vector(forward);
+            let wheel_forward =
+                (wheel_forward - hit.normal * wheel_forward.dot(hit.normal)).normalize();
+            let wheel_side = hit.normal.cross(wheel_forward).normalize();
+
+            let velocity = self.chassis.get_point_velocity(hit.point);
+            let forward_speed = velocity.dot(wheel_forward);
+            let side_speed = velocity.dot(wheel_side);
+            wheel.spin_angle += forward_speed / wheel.radius * delta_time;
+
+            let max_grip = self.tire_grip * load;
+            let slip_angle = side_speed.atan2(forward_speed.abs() + 0.5);
+            let side_force = -get_slip_curve(slip_angle) * max_grip;
+
+            let mut forward_force = -forward_speed * 30.0;
+            if wheel.powered {
+                forward_force +=
+                    self.throttle * self.engine_torque / wheel.radius / powered_count as f32;
+            }
+            if self.brake {
+                // don't brake past zero, that would push the cart backwards
+                let stopping_force = forward_speed.abs() * mass_share / delta_time;
+                forward_force -= forward_speed.signum() * self.brake_force.min(stopping_force);
+            }
+
+            // friction circle
+            let mut tire_force = wheel_forward * forward_force + wheel_side * side_force;
+            if tire_force.magnitude() > max_grip {
+                tire_force = tire_force.normalize() * max_grip;
+            }
+            let height = (self.chassis.position - hit.point).dot(up);
+            let force_point = hit.point + up * height * (1.0 - self.roll_influence);
+            forces.push((tire_force, force_point));
+        }
+
+        forces.extend(self.get_anti_roll_forces());
+
+        for (force, point) in forces {
+            self.chassis.apply_force_at_point(force, point);
+        }
+
+        self.chassis.integrate(delta_time, gravity);
+        self.chassis.resolve_contacts(colliders);
+    }
+
+    // anti-roll bars between the wheels of each axle, they push the more compressed side up
+    // and the other one down so the chassis stays level
+    fn get_anti_roll_forces(&self) -> Vec<(Vector3<f32>, Point3<f32>)> {
+        let up = self.get_up();
+        let mut forces = Vec::new();
+        for pair in self.wheels.chunks(2) {
+            if let [left, right] = pair {
+                let force = (left.compression - right.compression) * self.anti_roll_stiffness;
+                if left.contact_point.is_some() {
+                    forces.push((up * force, self.chassis.get_world_point(left.attachment)));
+                }
+                if right.contact_point.is_some() {
+                    forces.push((up * -force, self.chassis.get_world_point(right.attachment)));
+                }
+            }
+        }
+        forces
+    }
+
+    // positive when going forward
+    pub fn get_speed(&self) -> f32 {
+        self.chassis.linear_velocity.dot(self.get_forward())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Zero;
+
+    #[test]
+    fn anti_roll_levels_the_chassis() {
+        let mut vehicle = Vehicle::new_cart(Point3::new(0.0, 0.0, 0.0));
+        // the left wheels are pushed in, so the chassis leans to the left
+        for (i, wheel) in vehicle.wheels.iter_mut().enumerate() {
+            wheel.compression = if i % 2 == 0 { 0.3 } else { 0.1 };
+            wheel.contact_point = Some(Point3::new(0.0, -1.0, 0.0));
+        }
+
+        for (force, point) in vehicle.get_anti_roll_forces() {
+            vehicle.chassis.apply_force_at_point(force, point);
+        }
+        vehicle.chassis.integrate(0.01, Vector3::zero());
+
+        let left = vehicle.chassis.get_world_point(vehicle.wheels[0].attachment);
+        let right = vehicle.chassis.get_world_point(vehicle.wheels[1].attachment);
+        assert!(vehicle.chassis.get_point_velocity(left).y > 0.0);
+        assert!(vehicle.chassis.get_point_velocity(right).y < 0.0);
+    }
+
+    #[test]
+    fn anti_roll_does_nothing_when_level() {
+        let mut vehicle = Vehicle::new_cart(Point3::new(0.0, 0.0, 0.0));
+        for wheel in vehicle.wheels.iter_mut() {
+            wheel.compression = 0.2;
+            wheel.contact_point = Some(Point3::new(0.0, -1.0, 0.0));
+        }
+
+        for (force, _) in vehicle.get_anti_roll_forces() {
+            assert!(force.magnitude() < 1e-6);
+        }
+    }
+}
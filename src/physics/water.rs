@@ -0,0 +1,420 @@
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+use crate::physics::{
+    rigid_body::RigidBody,
+    scene_query::{Aabb, MeshCollider},
+};
+
+// samples per axis used to approximate the submerged part of a box
+const BOX_SAMPLES: u32 = 4;
+
+pub struct WaterVolume {
+    // the top of the box is the water surface
+    pub bounds: Aabb,
+    pub density: f32,
+    pub current: Vector3<f32>,
+    pub linear_drag: f32,
+    pub angular_drag: f32,
+    // vertical speed needed when entering the water to produce a splash
+    pub splash_speed: f32,
+}
+
+pub struct SplashEvent {
+    pub position: Point3<f32>,
+    pub speed: f32,
+}
+
+// points spread through a hull, relative to its center, each one stands for the same part of its
+// volume
+pub struct HullSamples {
+    pub points: Vec<Vector3<f32>>,
+    pub sample_volume: f32,
+    // middle of the points in the space of the mesh, the body of the hull is there
+    pub center: Point3<f32>,
+    // of the bounds of the mesh, for the inertia and the contacts of the body
+    pub half_extent: Vector3<f32>,
+}
+
+impl HullSamples {
+    // a grid through the bounds of the mesh, only the points inside it are kept, the mesh has to
+    // be closed for that
+    pub fn new(hull: &MeshCollider, samples_per_axis: u32) -> Self {
+        let size = hull.bounds.max - hull.bounds.min;
+        let cell_size = size / samples_per_axis as f32;
+
+        let mut points = Vec::new();
+        for x in 0..samples_per_axis {
+            for y in 0..samples_per_axis {
+                for z in 0..samples_per_axis {
+                    let point = hull.bounds.min
+                        + Vector3::new(
+                            (x as f32 + 0.5) * cell_size.x,
+                            (y as f32 + 0.5) * cell_size.y,
+                            (z as f32 + 0.5) * cell_size.z,
+                        );
+                    if hull.contains(point) {
+                        points.push(point.to_vec());
+                    }
+                }
+            }
+        }
+
+        let center =
+            Point3::from_vec(points.iter().sum::<Vector3<f32>>() / points.len().max(1) as f32);
+        for point in points.iter_mut() {
+            *point -= center.to_vec();
+        }
+
+        Self {
+            points,
+            sample_volume: cell_size.x * cell_size.y * cell_size.z,
+            center,
+            half_extent: size / 2.0,
+        }
+    }
+
+    pub fn get_volume(&self) -> f32 {
+        self.points.len() as f32 * self.sample_volume
+    }
+}
+
+impl WaterVolume {
+    pub fn new(bounds: Aabb, current: Vector3<f32>) -> Self {
+        Self {
+            bounds,
+            density: 1000.0,
+            current,
+            linear_drag: 1.0,
+            angular_drag: 1.0,
+            splash_speed: 2.0,
+        }
+    }
+
+    pub fn get_surface_height(&self) -> f32 {
+        self.bounds.max.y
+    }
+
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        self.bounds.contains(point)
+    }
+
+    // applies buoyancy and drag on every submerged sample of the box, which also
+    // gives the torque that keeps floating bodies upright, returns the fraction of the
+    // box inside this volume
+    pub fn apply_forces(&self, body: &mut RigidBody, gravity: Vector3<f32>) -> f32 {
+        let sample_volume = body.get_volume() / BOX_SAMPLES.pow(3) as f32;
+
+        let mut submerged_samples = 0;
+        for x in 0..BOX_SAMPLES {
+            for y in 0..BOX_SAMPLES {
+                for z in 0..BOX_SAMPLES {
+                    let cell = |i: u32, extent: f32| {
+                        ((i as f32 + 0.5) / BOX_SAMPLES as f32 * 2.0 - 1.0) * extent
+                    };
+                    let point = body.get_world_point(Vector3::new(
+                        cell(x, body.half_extent.x),
+                        cell(y, body.half_extent.y),
+                        cell(z, body.half_extent.z),
+                    ));
+                    if self.apply_sample_forces(body, point, sample_volume, gravity) {
+                        submerged_samples += 1;
+                    }
+                }
+            }
+        }
+
+        submerged_samples as f32 / BOX_SAMPLES.pow(3) as f32
+    }
+
+    // the same with the samples of a hull instead of the box, the body has to be at the center
+    // of the hull
+    pub fn apply_hull_forces(
+        &self,
+        body: &mut RigidBody,
+        hull: &HullSamples,
+        gravity: Vector3<f32>,
+    ) -> f32 {
+        let mut submerged_samples = 0;
+        for local in hull.points.iter() {
+            let point = body.get_world_point(*local);
+            if self.apply_sample_forces(body, point, hull.sample_volume, gravity) {
+                submerged_samples += 1;
+            }
+        }
+
+        submerged_samples as f32 / hull.points.len().max(1) as f32
+    }
+
+    // buoyancy and drag of the water a sample pushes away, false if it isn't under water
+    fn apply_sample_forces(
+        &self,
+        body: &mut RigidBody,
+        point: Point3<f32>,
+        sample_volume: f32,
+        gravity: Vector3<f32>,
+    ) -> bool {
+        if !self.contains(point) {
+            return false;
+        }
+
+        let sample_mass = self.density * sample_volume;
+        let buoyancy = -gravity * sample_mass;
+        let relative_velocity = body.get_point_velocity(point) - self.current;
+        let drag = -relative_velocity * (self.linear_drag * sample_mass);
+        body.apply_force_at_point(buoyancy + drag, point);
+        true
+    }
+
+    // of a small body of this density all under water, the forces of apply_forces without the
+    // torque, for predicting its path
+    pub fn get_acceleration(
+        &self,
+        point: Point3<f32>,
+        velocity: Vector3<f32>,
+        gravity: Vector3<f32>,
+        density: f32,
+    ) -> Vector3<f32> {
+        if !self.contains(point) {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        let drag = -(velocity - self.current) * self.linear_drag;
+        (-gravity + drag) * (self.density / density)
+    }
+
+    // the caps of the sphere under the surface and under the bottom, only the center is checked
+    // against the sides of the volume
+    pub fn get_sphere_submerged_fraction(&self, center: Point3<f32>, radius: f32) -> f32 {
+        let bounds = &self.bounds;
+        if center.x < bounds.min.x
+            || center.x > bounds.max.x
+            || center.z < bounds.min.z
+            || center.z > bounds.max.z
+        {
+            return 0.0;
+        }
+
+        let fraction_below = |height: f32| {
+            let cap = (height - (center.y - radius)).max(0.0).min(2.0 * radius);
+            cap * cap * (3.0 * radius - cap) / (4.0 * radius * radius * radius)
+        };
+        fraction_below(bounds.max.y) - fraction_below(bounds.min.y)
+    }
+}
+
+// applies the forces of every volume on the body, the angular drag and the splash only
+// depend on how much of the body is under water in total, the box of the body is sampled
+// without a hull
+pub fn apply_water_forces(
+    volumes: &[WaterVolume],
+    body: &mut RigidBody,
+    hull: Option<&HullSamples>,
+    gravity: Vector3<f32>,
+) -> Option<SplashEvent> {
+    let mut submerged_fraction = 0.0;
+    let mut angular_drag = 0.0;
+    // the volume with most of the body, it gets the splash
+    let mut deepest: Option<(&WaterVolume, f32)> = None;
+    for volume in volumes.iter() {
+        let fraction = match hull {
+            Some(hull) => volume.apply_hull_forces(body, hull, gravity),
+            None => volume.apply_forces(body, gravity),
+        };
+        submerged_fraction += fraction;
+        angular_drag += volume.angular_drag * fraction;
+        if fraction > deepest.map_or(0.0, |(_, deepest_fraction)| deepest_fraction) {
+            deepest = Some((volume, fraction));
+        }
+    }
+
+    let previous_fraction = body.submerged_fraction;
+    body.submerged_fraction = submerged_fraction.min(1.0);
+
+    if body.submerged_fraction > 0.0 {
+        body.apply_torque(-body.angular_velocity * (angular_drag * body.mass));
+    }
+
+    let (volume, _) = deepest?;
+    let vertical_speed = -body.linear_velocity.y;
+    if previous_fraction == 0.0 && vertical_speed > volume.splash_speed {
+        return Some(SplashEvent {
+            position: Point3::new(
+                body.position.x,
+                volume.get_surface_height(),
+                body.position.z,
+            ),
+            speed: vertical_speed,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::default_gravity;
+    use cgmath::InnerSpace;
+
+    fn create_volume(min: Point3<f32>, max: Point3<f32>) -> WaterVolume {
+        WaterVolume::new(Aabb { min, max }, Vector3::new(0.0, 0.0, 0.0))
+    }
+
+    // two triangles for each face
+    fn create_box_triangles(min: Point3<f32>, max: Point3<f32>) -> Vec<[Point3<f32>; 3]> {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        let faces = [
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+        ];
+        faces
+            .iter()
+            .flat_map(|face| {
+                vec![
+                    [corner(face[0]), corner(face[1]), corner(face[2])],
+                    [corner(face[0]), corner(face[2]), corner(face[3])],
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sphere_submerged_fraction_follows_the_surface() {
+        let volume = create_volume(
+            Point3::new(-10.0, -10.0, -10.0),
+            Point3::new(10.0, 0.0, 10.0),
+        );
+
+        let fraction = |y: f32| volume.get_sphere_submerged_fraction(Point3::new(0.0, y, 0.0), 1.0);
+        assert!((fraction(0.0) - 0.5).abs() < 1e-6);
+        assert!((fraction(-5.0) - 1.0).abs() < 1e-6);
+        assert!(fraction(2.0).abs() < 1e-6);
+        // a cap as high as a quarter of the radius
+        assert!((fraction(0.75) - 0.25 * 0.25 * 2.75 / 4.0).abs() < 1e-6);
+        // and outside the sides
+        assert!(
+            volume
+                .get_sphere_submerged_fraction(Point3::new(20.0, -5.0, 0.0), 1.0)
+                .abs()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn hull_samples_are_only_inside_the_mesh() {
+        // two unit boxes with a gap of the same size between them
+        let mut triangles =
+            create_box_triangles(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        triangles.extend(create_box_triangles(
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(3.0, 1.0, 1.0),
+        ));
+        let hull = HullSamples::new(&MeshCollider::new(triangles), 6);
+
+        assert_eq!(hull.points.len(), 4 * 36);
+        assert!((hull.get_volume() - 2.0).abs() < 1e-5);
+        assert!((hull.center - Point3::new(1.5, 0.5, 0.5)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn half_density_hull_floats_half_submerged() {
+        let volumes = [create_volume(
+            Point3::new(-10.0, -10.0, -10.0),
+            Point3::new(10.0, 0.0, 10.0),
+        )];
+        let hull = HullSamples::new(
+            &MeshCollider::new(create_box_triangles(
+                Point3::new(-1.0, -0.25, -1.0),
+                Point3::new(1.0, 0.25, 1.0),
+            )),
+            4,
+        );
+        let mut body = RigidBody::new_box(Point3::new(0.0, 1.0, 0.0), hull.half_extent, 500.0);
+
+        for _ in 0..2000 {
+            apply_water_forces(&volumes, &mut body, Some(&hull), default_gravity());
+            body.integrate(1.0 / 120.0, default_gravity());
+        }
+
+        assert!((body.submerged_fraction - 0.5).abs() < 1e-6);
+        assert!(body.position.y.abs() < 0.0625 + 1e-3);
+    }
+
+    #[test]
+    fn half_density_box_floats_half_submerged() {
+        // the water surface is at y = 0
+        let volumes = [create_volume(
+            Point3::new(-10.0, -10.0, -10.0),
+            Point3::new(10.0, 0.0, 10.0),
+        )];
+        // flat, a cube of this density would roll onto an edge
+        let mut body = RigidBody::new_box(
+            Point3::new(0.0, 1.0, 0.0),
+            Vector3::new(1.0, 0.25, 1.0),
+            500.0,
+        );
+
+        for _ in 0..2000 {
+            apply_water_forces(&volumes, &mut body, None, default_gravity());
+            body.integrate(1.0 / 120.0, default_gravity());
+        }
+
+        assert!((body.submerged_fraction - 0.5).abs() < 1e-6);
+        // anywhere between the two layers of samples closest to the surface
+        assert!(body.position.y.abs() < 0.0625 + 1e-3);
+        assert!(body.linear_velocity.magnitude() < 0.05);
+    }
+
+    #[test]
+    fn splashes_once_with_several_volumes() {
+        let volumes = [
+            create_volume(
+                Point3::new(-10.0, -10.0, -10.0),
+                Point3::new(0.0, 0.0, 10.0),
+            ),
+            create_volume(Point3::new(0.0, -10.0, -10.0), Point3::new(10.0, 0.0, 10.0)),
+        ];
+        let mut body = RigidBody::new_box(
+            Point3::new(-5.0, 0.0, 0.0),
+            Vector3::new(0.5, 0.5, 0.5),
+            500.0,
+        );
+        body.linear_velocity = Vector3::new(0.0, -5.0, 0.0);
+
+        assert!(apply_water_forces(&volumes, &mut body, None, default_gravity()).is_some());
+        assert!(body.submerged_fraction > 0.0);
+        assert!(apply_water_forces(&volumes, &mut body, None, default_gravity()).is_none());
+    }
+
+    #[test]
+    fn submerged_fraction_adds_up_across_volumes() {
+        let volumes = [
+            create_volume(
+                Point3::new(-10.0, -10.0, -10.0),
+                Point3::new(0.0, 10.0, 10.0),
+            ),
+            create_volume(
+                Point3::new(0.0, -10.0, -10.0),
+                Point3::new(10.0, 10.0, 10.0),
+            ),
+        ];
+        // half of the box in each volume
+        let mut body = RigidBody::new_box(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.5, 0.5, 0.5),
+            500.0,
+        );
+
+        apply_water_forces(&volumes, &mut body, None, default_gravity());
+        assert!((body.submerged_fraction - 1.0).abs() < 1e-6);
+    }
+}
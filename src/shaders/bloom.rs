@@ -0,0 +1,157 @@
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction},
+    Display, Surface,
+};
+
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{
+    BloomBrightPassProgram, BloomDownsampleProgram, BloomSettings, BloomUpsampleProgram,
+};
+
+pub struct BloomPrograms<'a, 'b, 'c> {
+    pub bright_pass: &'a BloomBrightPassProgram,
+    pub downsample: &'b BloomDownsampleProgram,
+    pub upsample: &'c BloomUpsampleProgram,
+}
+
+// the first texture is half of the screen, each one after it is half of the previous one
+const BLOOM_TEXTURE_COUNT: usize = 6;
+
+// glow around the brightest parts of the hdr scene, they are blurred by downsampling them a few
+// times and then upsampling them back while adding every size together
+pub struct Bloom {
+    pub settings: BloomSettings,
+    textures: Vec<Texture2d>,
+    size: (u32, u32),
+    screen_quad: MainFramebufferShader,
+}
+
+impl Bloom {
+    pub fn new(display: &Display, size: (u32, u32)) -> Self {
+        Self {
+            settings: BloomSettings {
+                enabled: true,
+                intensity: 0.15,
+                threshold: 1.0,
+                radius: 1.0,
+            },
+            textures: Self::create_textures(display, size),
+            size,
+            screen_quad: MainFramebufferShader::new(display),
+        }
+    }
+
+    fn create_textures(display: &Display, size: (u32, u32)) -> Vec<Texture2d> {
+        let mut textures = Vec::with_capacity(BLOOM_TEXTURE_COUNT);
+        let (mut width, mut height) = size;
+        for _ in 0..BLOOM_TEXTURE_COUNT {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+            textures.push(
+                Texture2d::empty_with_format(
+                    display,
+                    UncompressedFloatFormat::F16F16F16F16,
+                    MipmapsOption::NoMipmap,
+                    width,
+                    height,
+                )
+                .unwrap(),
+            );
+        }
+        textures
+    }
+
+    // the textures have to follow the window size
+    pub fn resize(&mut self, display: &Display, size: (u32, u32)) {
+        if self.size != size {
+            self.textures = Self::create_textures(display, size);
+            self.size = size;
+        }
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        }
+    }
+
+    // added to the scene before the tone mapping
+    pub fn get_texture(&self) -> &Texture2d {
+        &self.textures[0]
+    }
+
+    pub fn update(&mut self, display: &Display, programs: BloomPrograms, scene: &Texture2d) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        let sampler = self.get_sampler();
+        let params = glium::DrawParameters::default();
+
+        let mut target = SimpleFrameBuffer::new(display, &self.textures[0]).unwrap();
+        let uniforms =
+            BloomBrightPassProgram::get_uniforms(scene, sampler, self.settings.threshold);
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.bright_pass.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+
+        for i in 1..self.textures.len() {
+            let mut target = SimpleFrameBuffer::new(display, &self.textures[i]).unwrap();
+            let uniforms = BloomDownsampleProgram::get_uniforms(&self.textures[i - 1], sampler);
+            target
+                .draw(
+                    &self.screen_quad.vertex_buffer,
+                    self.screen_quad.index_buffer,
+                    &programs.downsample.0,
+                    &uniforms,
+                    &params,
+                )
+                .unwrap();
+        }
+
+        // every smaller texture is blurred and added on top of the bigger one
+        let upsample_params = glium::DrawParameters {
+            blend: glium::Blend {
+                color: glium::BlendingFunction::Addition {
+                    source: glium::LinearBlendingFactor::One,
+                    destination: glium::LinearBlendingFactor::One,
+                },
+                alpha: glium::BlendingFunction::AlwaysReplace,
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            ..Default::default()
+        };
+        for i in (1..self.textures.len()).rev() {
+            let mut target = SimpleFrameBuffer::new(display, &self.textures[i - 1]).unwrap();
+            let uniforms = BloomUpsampleProgram::get_uniforms(
+                &self.textures[i],
+                sampler,
+                self.settings.radius,
+            );
+            target
+                .draw(
+                    &self.screen_quad.vertex_buffer,
+                    self.screen_quad.index_buffer,
+                    &programs.upsample.0,
+                    &uniforms,
+                    &upsample_params,
+                )
+                .unwrap();
+        }
+    }
+}
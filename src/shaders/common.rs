@@ -4,8 +4,9 @@ use obj::FromRawVertex;
 use obj::ObjResult;
 use std::collections::hash_map::{Entry, HashMap};
 use std::io::Cursor;
+use std::rc::Rc;
 
-use cgmath::{Point3, Vector3};
+use cgmath::{InnerSpace, Point3, Vector2, Vector3};
 use glium::{
     texture::{RawImage2d, SrgbTexture2d, Texture2d},
     Display,
@@ -14,7 +15,159 @@ use glium::{
 pub struct Material {
     pub diffuse: glium::texture::SrgbTexture2d,
     pub specular: glium::texture::SrgbTexture2d,
+    // tangent space normals, not srgb
+    pub normal: glium::texture::Texture2d,
+    pub parallax: Option<ParallaxMap>,
+    // bound as the height map without parallax, the sampler can't be left unbound
+    pub empty_height: glium::texture::Texture2d,
+    pub environment: Option<EnvironmentMapping>,
     pub shininess: f32,
+    // added over the lit colour, multiplied by the factor so a black factor turns it off
+    pub emissive: glium::texture::SrgbTexture2d,
+    pub emissive_factor: Vector3<f32>,
+    pub emissive_light: Option<EmissiveLight>,
+}
+
+// height map for the parallax occlusion mapping, white is the surface and black is the deepest
+pub struct ParallaxMap {
+    pub height: glium::texture::Texture2d,
+    // depth of the black parts, in texture coordinates
+    pub depth_scale: f32,
+    // the view ray steps through the height map in this many layers, more is slower but
+    // doesn't leave steps at grazing angles
+    pub layers: i32,
+}
+
+// the skybox seen in the surface, mixed over its lit colour
+pub struct EnvironmentMapping {
+    pub mode: EnvironmentMappingMode,
+    // a reflective surface reflects this much when facing the view and all of it at grazing
+    // angles, a refractive one is this much glass over the lit colour
+    pub reflectivity: f32,
+    // the reflectivity is multiplied by the specular map, so only the shiny parts reflect
+    pub reflectivity_from_specular: bool,
+    // index of refraction of the glass, the fresnel reflection of the glass also comes from it
+    pub ior: f32,
+    // colour of the light that goes through the glass
+    pub tint: Vector3<f32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EnvironmentMappingMode {
+    Reflective,
+    Refractive,
+}
+
+// metallic/roughness material of the cook-torrance program, the maps are multiplied by the factors
+pub struct PbrMaterial {
+    pub base_color: glium::texture::SrgbTexture2d,
+    // roughness in the green channel and metallic in the blue one, like gltf
+    pub metallic_roughness: glium::texture::Texture2d,
+    pub normal: glium::texture::Texture2d,
+    pub ambient_occlusion: glium::texture::Texture2d,
+    pub emissive: glium::texture::SrgbTexture2d,
+    pub base_color_factor: Vector3<f32>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: Vector3<f32>,
+    pub emissive_light: Option<EmissiveLight>,
+}
+
+impl PbrMaterial {
+    // white maps, so only the factors are used until a map is set
+    pub fn new(display: &Display) -> Self {
+        Self {
+            base_color: create_white_srgb_texture(display),
+            metallic_roughness: create_white_texture(display),
+            normal: create_flat_normal_map(display),
+            ambient_occlusion: create_white_texture(display),
+            emissive: create_white_srgb_texture(display),
+            base_color_factor: Vector3::new(1.0, 1.0, 1.0),
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+            emissive_light: None,
+        }
+    }
+}
+
+// a point light in the middle of an emissive mesh, so the glow also lights what's around it
+#[derive(Clone)]
+pub struct EmissiveLight {
+    // average colour of the emission
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl EmissiveLight {
+    // the emissive map is read back once to get its average colour
+    pub fn new(emissive: &SrgbTexture2d, emissive_factor: Vector3<f32>, intensity: f32) -> Self {
+        let image: RawImage2d<u8> = emissive.read();
+        let pixel_count = (image.width * image.height).max(1) as f32;
+        let sum = image
+            .data
+            .chunks(4)
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, pixel| {
+                sum + Vector3::new(
+                    srgb_to_linear(pixel[0]),
+                    srgb_to_linear(pixel[1]),
+                    srgb_to_linear(pixel[2]),
+                )
+            });
+        let average = sum / pixel_count;
+
+        Self {
+            color: Vector3::new(
+                average.x * emissive_factor.x,
+                average.y * emissive_factor.y,
+                average.z * emissive_factor.z,
+            ),
+            intensity,
+            linear: 0.22,
+            quadratic: 0.2,
+        }
+    }
+
+    // the shadows of the mesh itself would block it, so it has none
+    pub fn get_point_light(&self, position: Point3<f32>) -> PointLight {
+        let color = self.color * self.intensity;
+        PointLight {
+            position,
+            ambient: Vector3::new(0.0, 0.0, 0.0),
+            diffuse: color,
+            specular: color,
+            constant: 1.0,
+            linear: self.linear,
+            quadratic: self.quadratic,
+            casts_shadows: false,
+        }
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// the legacy assets keep the phong material, each object picks the program of its material
+pub enum ObjectMaterial {
+    Phong(Material),
+    Pbr(PbrMaterial),
+}
+
+impl ObjectMaterial {
+    pub fn get_emissive_light(&self) -> Option<&EmissiveLight> {
+        match self {
+            ObjectMaterial::Phong(material) => material.emissive_light.as_ref(),
+            ObjectMaterial::Pbr(material) => material.emissive_light.as_ref(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -24,6 +177,8 @@ pub struct DirectionalLight {
     pub ambient: Vector3<f32>,
     pub diffuse: Vector3<f32>,
     pub specular: Vector3<f32>,
+
+    pub casts_shadows: bool,
 }
 
 #[derive(Clone)]
@@ -37,6 +192,33 @@ pub struct PointLight {
     pub constant: f32,
     pub linear: f32,
     pub quadratic: f32,
+
+    pub casts_shadows: bool,
+}
+
+// below this fraction of its brightest channel a light isn't visible anymore
+const LIGHT_CUTOFF: f32 = 5.0 / 256.0;
+
+impl PointLight {
+    // the distance where the attenuation makes the light too dim to see, infinite when it doesn't fade
+    pub fn get_radius(&self) -> f32 {
+        let brightest = [self.ambient, self.diffuse, self.specular]
+            .iter()
+            .map(|color| color.x.max(color.y).max(color.z))
+            .fold(0.0, f32::max);
+        let attenuation = brightest / LIGHT_CUTOFF;
+        if attenuation <= self.constant {
+            0.0
+        } else if self.quadratic > 0.0 {
+            let discriminant = self.linear * self.linear
+                - 4.0 * self.quadratic * (self.constant - attenuation);
+            (-self.linear + discriminant.sqrt()) / (2.0 * self.quadratic)
+        } else if self.linear > 0.0 {
+            (attenuation - self.constant) / self.linear
+        } else {
+            f32::INFINITY
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -49,6 +231,15 @@ pub struct SpotLight {
     pub ambient: Vector3<f32>,
     pub diffuse: Vector3<f32>,
     pub specular: Vector3<f32>,
+
+    // attenuation, 1.0, 0.0 and 0.0 don't fade with the distance
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+
+    pub casts_shadows: bool,
+    // pattern projected by the light, it can be shared by several lights
+    pub cookie: Option<Rc<Texture2d>>,
 }
 
 #[derive(Copy, Clone)]
@@ -56,6 +247,78 @@ pub struct Vertex3d {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coords: [f32; 2],
+    // the w is the sign of the bitangent, cross(normal, tangent) * w
+    pub tangent: [f32; 4],
+}
+
+// tangents that follow the texture coordinates, every triangle adds its tangent and bitangent to
+// its vertices weighted by the corner angle, then they are made perpendicular to the normal
+// like in mikktspace
+pub fn generate_tangents(vertices: &mut [Vertex3d], indices: &[usize]) {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+    let mut bitangents = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let positions: Vec<Vector3<f32>> = triangle
+            .iter()
+            .map(|&i| Vector3::from(vertices[i].position))
+            .collect();
+        let tex_coords: Vec<Vector2<f32>> = triangle
+            .iter()
+            .map(|&i| Vector2::from(vertices[i].tex_coords))
+            .collect();
+
+        let edge1 = positions[1] - positions[0];
+        let edge2 = positions[2] - positions[0];
+        let delta_uv1 = tex_coords[1] - tex_coords[0];
+        let delta_uv2 = tex_coords[2] - tex_coords[0];
+
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+        // the texture is degenerate on this triangle
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) / determinant;
+        let bitangent = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) / determinant;
+
+        for corner in 0..3 {
+            let to_next = positions[(corner + 1) % 3] - positions[corner];
+            let to_previous = positions[(corner + 2) % 3] - positions[corner];
+            if to_next.magnitude2() == 0.0 || to_previous.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = to_next
+                .normalize()
+                .dot(to_previous.normalize())
+                .clamp(-1.0, 1.0)
+                .acos();
+
+            tangents[triangle[corner]] += tangent * angle;
+            bitangents[triangle[corner]] += bitangent * angle;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        // gram-schmidt
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            // any direction perpendicular to the normal
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+        let sign = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, sign];
+    }
 }
 
 impl<I: FromPrimitive + Copy> FromRawVertex<I> for Vertex3d {
@@ -67,6 +330,8 @@ impl<I: FromPrimitive + Copy> FromRawVertex<I> for Vertex3d {
     ) -> ObjResult<(Vec<Self>, Vec<I>)> {
         let mut vb = Vec::with_capacity(polygons.len() * 3);
         let mut ib = Vec::with_capacity(polygons.len() * 3);
+        // the indices as usize, for the tangents
+        let mut triangles = Vec::with_capacity(polygons.len() * 3);
         {
             let mut cache = HashMap::new();
             let mut map = |pi: usize, ni: usize, ti: usize| {
@@ -81,9 +346,10 @@ impl<I: FromPrimitive + Copy> FromRawVertex<I> for Vertex3d {
                             position: [p.0, p.1, p.2],
                             normal: [n.0, n.1, n.2],
                             tex_coords: [t.0, t.1],
+                            tangent: [0.0, 0.0, 0.0, 1.0],
                         };
-                        let index = I::from_usize(vb.len())
-                            .expect("Unable to convert the index from usize");
+                        let index = (vb.len(), I::from_usize(vb.len())
+                            .expect("Unable to convert the index from usize"));
                         vb.push(vertex);
                         entry.insert(index);
                         index
@@ -91,7 +357,8 @@ impl<I: FromPrimitive + Copy> FromRawVertex<I> for Vertex3d {
                     // Cache hit -> use it
                     Entry::Occupied(entry) => *entry.get(),
                 };
-                ib.push(index)
+                triangles.push(index.0);
+                ib.push(index.1)
             };
 
             for polygon in polygons {
@@ -106,6 +373,7 @@ impl<I: FromPrimitive + Copy> FromRawVertex<I> for Vertex3d {
                 }
             }
         }
+        generate_tangents(&mut vb, &triangles);
         vb.shrink_to_fit();
         Ok((vb, ib))
     }
@@ -122,6 +390,13 @@ pub struct Vertex2d {
     pub tex_coords: [f32; 2],
 }
 
+#[derive(Copy, Clone)]
+pub struct ParticleInstance {
+    pub offset: [f32; 3],
+}
+
+implement_vertex!(ParticleInstance, offset);
+
 pub fn load_srgb_texture(
     display: &Display,
     texture_bytes: &dyn std::convert::AsRef<[u8]>,
@@ -138,6 +413,19 @@ pub fn load_texture(
     Texture2d::new(display, load_raw_image(texture_bytes, image_format)).unwrap()
 }
 
+// points straight out of the surface, for the materials without a normal map
+pub fn create_flat_normal_map(display: &Display) -> Texture2d {
+    Texture2d::new(display, vec![vec![(128u8, 128u8, 255u8)]]).unwrap()
+}
+
+pub fn create_white_texture(display: &Display) -> Texture2d {
+    Texture2d::new(display, vec![vec![(255u8, 255u8, 255u8)]]).unwrap()
+}
+
+pub fn create_white_srgb_texture(display: &Display) -> SrgbTexture2d {
+    SrgbTexture2d::new(display, vec![vec![(255u8, 255u8, 255u8)]]).unwrap()
+}
+
 fn load_raw_image(
     image_bytes: &dyn std::convert::AsRef<[u8]>,
     image_format: image::ImageFormat,
@@ -156,7 +444,7 @@ fn load_raw_image(
 
 #[allow(dead_code)]
 pub fn main() {
-    implement_vertex!(Vertex3d, position, normal, tex_coords);
+    implement_vertex!(Vertex3d, position, normal, tex_coords, tangent);
     implement_vertex!(PositionalVertex, position);
     implement_vertex!(Vertex2d, position, tex_coords);
 }
@@ -4,12 +4,26 @@ use glium::{
     Display, IndexBuffer, Surface, VertexBuffer,
 };
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 use crate::shaders::common::{load_texture, PositionalVertex};
 
+// right, left, top, bottom, front, back
+const SKYBOX_IMAGES: [&[u8]; 6] = [
+    include_bytes!("../../assets/skybox/right.jpg"),
+    include_bytes!("../../assets/skybox/left.jpg"),
+    include_bytes!("../../assets/skybox/top.jpg"),
+    include_bytes!("../../assets/skybox/bottom.jpg"),
+    include_bytes!("../../assets/skybox/front.jpg"),
+    include_bytes!("../../assets/skybox/back.jpg"),
+];
+
 pub struct CubeMapShader {
     pub vertex_buffer: VertexBuffer<PositionalVertex>,
     pub index_buffer: IndexBuffer<u16>,
     pub cubemap: Cubemap,
+    pub cache_key: u64,
 }
 
 impl CubeMapShader {
@@ -18,44 +32,31 @@ impl CubeMapShader {
             vertex_buffer: Self::create_vertex_buffer(display),
             index_buffer: Self::create_index_buffer(display),
             cubemap: Self::load_cubemap(display),
+            cache_key: Self::get_cache_key(),
         }
     }
 
     fn load_textures(display: &Display) -> [glium::Texture2d; 6] {
+        let load = |bytes: &[u8]| load_texture(display, &bytes, image::ImageFormat::Jpeg);
         [
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/right.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/left.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/top.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/bottom.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/front.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
-            load_texture(
-                display,
-                &include_bytes!("../../assets/skybox/back.jpg"),
-                image::ImageFormat::Jpeg,
-            ),
+            load(SKYBOX_IMAGES[0]),
+            load(SKYBOX_IMAGES[1]),
+            load(SKYBOX_IMAGES[2]),
+            load(SKYBOX_IMAGES[3]),
+            load(SKYBOX_IMAGES[4]),
+            load(SKYBOX_IMAGES[5]),
         ]
     }
 
+    // changes when any of the images does, for the caches made from the skybox
+    fn get_cache_key() -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for bytes in SKYBOX_IMAGES.iter() {
+            bytes.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
     fn load_cubemap(display: &Display) -> Cubemap {
         let textures = Self::load_textures(display);
         let size = textures[0].width();
@@ -88,7 +89,7 @@ impl CubeMapShader {
         cubemap
     }
 
-    fn create_vertex_buffer(display: &Display) -> VertexBuffer<PositionalVertex> {
+    pub fn create_vertex_buffer(display: &Display) -> VertexBuffer<PositionalVertex> {
         let side: f32 = 1.0;
 
         VertexBuffer::new(
@@ -177,7 +178,7 @@ impl CubeMapShader {
         .unwrap()
     }
 
-    fn create_index_buffer(display: &Display) -> IndexBuffer<u16> {
+    pub fn create_index_buffer(display: &Display) -> IndexBuffer<u16> {
         glium::IndexBuffer::new(
             display,
             glium::index::PrimitiveType::TrianglesList,
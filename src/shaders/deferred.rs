@@ -0,0 +1,215 @@
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+use glium::{
+    framebuffer::{MultiOutputFrameBuffer, SimpleFrameBuffer},
+    texture::{DepthFormat, DepthTexture2d, MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior},
+    Display, Surface,
+};
+
+use crate::shaders::common::PointLight;
+use crate::shaders::fog::Fog;
+use crate::shaders::ibl::ImageBasedLighting;
+use crate::shaders::lights::{LightBuffer, MAX_POINT_LIGHTS};
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{DeferredLightingProgram, DeferredPointLightProgram};
+use crate::shaders::shadow_map::ShadowMaps;
+use crate::shaders::sphere_shader::SphereShader;
+use crate::shaders::ssao::Ssao;
+
+// surface properties written by the geometry pass of the deferred renderer, the forward renderer
+// also uses its depth and normals for the ssao
+pub struct GBuffer {
+    // linear diffuse colour
+    pub albedo: Texture2d,
+    pub specular: Texture2d,
+    // world space normal, the alpha has the shininess
+    pub normal: Texture2d,
+    // hdr colour of the emissive materials, added once by the full screen pass
+    pub emissive: Texture2d,
+    // also used as the depth buffer of the forward objects drawn after the lighting
+    pub depth: DepthTexture2d,
+    pub size: (u32, u32),
+}
+
+impl GBuffer {
+    pub fn new(display: &Display, size: (u32, u32)) -> Self {
+        Self {
+            albedo: Self::create_color_texture(display, size),
+            specular: Self::create_color_texture(display, size),
+            normal: Self::create_color_texture(display, size),
+            emissive: Self::create_color_texture(display, size),
+            depth: DepthTexture2d::empty_with_format(
+                display,
+                DepthFormat::F32,
+                MipmapsOption::NoMipmap,
+                size.0,
+                size.1,
+            )
+            .unwrap(),
+            size,
+        }
+    }
+
+    fn create_color_texture(display: &Display, size: (u32, u32)) -> Texture2d {
+        Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16F16,
+            MipmapsOption::NoMipmap,
+            size.0,
+            size.1,
+        )
+        .unwrap()
+    }
+
+    // the textures have to follow the window size
+    pub fn resize(&mut self, display: &Display, size: (u32, u32)) {
+        if self.size != size {
+            *self = Self::new(display, size);
+        }
+    }
+
+    pub fn get_framebuffer(&self, display: &Display) -> MultiOutputFrameBuffer<'_> {
+        MultiOutputFrameBuffer::with_depth_buffer(
+            display,
+            [
+                ("g_albedo", &self.albedo),
+                ("g_specular", &self.specular),
+                ("g_normal", &self.normal),
+                ("g_emissive", &self.emissive),
+            ]
+            .iter()
+            .cloned(),
+            &self.depth,
+        )
+        .unwrap()
+    }
+
+    // every pixel is read back as is
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct DeferredLightingPrograms<'a, 'b> {
+    pub screen: &'a DeferredLightingProgram,
+    pub point_light: &'b DeferredPointLightProgram,
+}
+
+pub struct DeferredLightingDrawData<'a, 'b, 'c, 'd, 'e, 'f, 'g> {
+    pub projection_view: &'a Matrix4<f32>,
+    pub camera_pos: Point3<f32>,
+    // the same lights as in the LightBuffer, used to place the light volumes
+    pub point_lights: &'b [&'b PointLight],
+    pub lights: &'c LightBuffer,
+    pub shadow_maps: &'d ShadowMaps,
+    pub ssao: &'e Ssao,
+    pub ibl: &'f ImageBasedLighting,
+    pub fog: &'g Fog,
+}
+
+// lights the gbuffer, the directional and spot lights with a full screen pass and every point
+// light with a sphere that covers its radius
+pub struct DeferredRenderer {
+    pub enabled: bool,
+    pub gbuffer: GBuffer,
+    screen_quad: MainFramebufferShader,
+    light_volume: SphereShader,
+}
+
+impl DeferredRenderer {
+    pub fn new(display: &Display, size: (u32, u32)) -> Self {
+        Self {
+            enabled: false,
+            gbuffer: GBuffer::new(display, size),
+            screen_quad: MainFramebufferShader::new(display),
+            light_volume: SphereShader::new(display, 16, 12),
+        }
+    }
+
+    pub fn draw_lighting(
+        &self,
+        target: &mut SimpleFrameBuffer,
+        programs: DeferredLightingPrograms,
+        data: DeferredLightingDrawData,
+    ) {
+        let inverse_projection_view = data.projection_view.invert().unwrap();
+
+        let screen_params = glium::DrawParameters::default();
+        let uniforms = DeferredLightingProgram::get_uniforms(
+            &self.gbuffer,
+            &inverse_projection_view,
+            &data.camera_pos,
+            data.lights,
+            data.shadow_maps,
+            data.ssao,
+            data.ibl,
+            data.fog,
+        );
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.screen.0,
+                &uniforms,
+                &screen_params,
+            )
+            .unwrap();
+
+        // only the back faces are drawn so the lights still work with the camera inside of them,
+        // without depth test every pixel behind the front of the volume is shaded
+        let volume_params = glium::DrawParameters {
+            blend: glium::Blend {
+                color: glium::BlendingFunction::Addition {
+                    source: glium::LinearBlendingFactor::One,
+                    destination: glium::LinearBlendingFactor::One,
+                },
+                alpha: glium::BlendingFunction::AlwaysReplace,
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            backface_culling: glium::draw_parameters::BackfaceCullingMode::CullClockwise,
+            ..Default::default()
+        };
+        for (i, light) in data.point_lights.iter().take(MAX_POINT_LIGHTS).enumerate() {
+            // the lights that never fade are limited to half the view distance, the volume would
+            // be cut by the far plane otherwise
+            let radius = light.get_radius().min(500.0);
+            if radius <= 0.0 {
+                continue;
+            }
+            // the sphere mesh is a bit smaller than a real sphere
+            let radius = radius * 1.1;
+            let model = Matrix4::from_translation(Vector3::new(
+                light.position.x,
+                light.position.y,
+                light.position.z,
+            )) * Matrix4::from_scale(radius);
+            let matrix = data.projection_view * model;
+
+            let uniforms = DeferredPointLightProgram::get_uniforms(
+                &matrix,
+                i as i32,
+                &self.gbuffer,
+                &inverse_projection_view,
+                &data.camera_pos,
+                data.lights,
+                data.shadow_maps,
+                data.ssao,
+                data.ibl,
+                data.fog,
+            );
+            target
+                .draw(
+                    &self.light_volume.vertex_buffer,
+                    &self.light_volume.index_buffer,
+                    &programs.point_light.0,
+                    &uniforms,
+                    &volume_params,
+                )
+                .unwrap();
+        }
+    }
+}
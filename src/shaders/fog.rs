@@ -0,0 +1,37 @@
+use cgmath::Vector3;
+
+// exponential fog over the distance plus a layer of denser fog close to the ground, the far
+// objects and the skybox fade into it instead of being cut at the far plane
+pub struct Fog {
+    pub enabled: bool,
+    pub color: Vector3<f32>,
+    // of the constant colour, it follows the day and night cycle like the sky
+    pub brightness: f32,
+    // uses the blurred sky around each direction as the colour instead
+    pub color_from_sky: bool,
+    // per world unit
+    pub density: f32,
+    // density at height, it halves every ln(2) / height_falloff units above it
+    pub height_density: f32,
+    pub height: f32,
+    pub height_falloff: f32,
+    // how far away the skybox is fogged, it's infinitely far so only the height fog is thinner
+    // looking up
+    pub sky_distance: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            color: Vector3::new(0.6, 0.65, 0.7),
+            brightness: 1.0,
+            color_from_sky: true,
+            density: 0.0005,
+            height_density: 0.03,
+            height: -8.0,
+            height_falloff: 0.15,
+            sky_distance: 1000.0,
+        }
+    }
+}
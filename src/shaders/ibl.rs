@@ -0,0 +1,412 @@
+use cgmath::Vector3;
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{cubemap::Cubemap, CubeLayer, MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction},
+    Display, Surface,
+};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{IblBrdfProgram, IblIrradianceProgram, IblPrefilterProgram};
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+// the roughness goes from 0 to 1 over the mipmaps
+pub const PREFILTERED_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+// the sky is copied at this size with all of its mipmaps, the sampling reads the blurrier
+// levels instead of taking more samples
+const ENVIRONMENT_SIZE: u32 = 256;
+
+// has to change when the precomputation does, so the old caches are made again
+const CACHE_VERSION: u32 = 2;
+const CACHE_MAGIC: &[u8; 4] = b"IBL\0";
+
+type Pixel = (f32, f32, f32, f32);
+
+// the direction of a texel is forward + right * s + up * t, with s and t between -1 and 1, this
+// follows the opengl cubemap layout
+pub struct CubeFace {
+    pub layer: CubeLayer,
+    pub forward: Vector3<f32>,
+    pub right: Vector3<f32>,
+    pub up: Vector3<f32>,
+}
+
+fn get_cube_faces() -> [CubeFace; 6] {
+    let face = |layer, forward: [f32; 3], right: [f32; 3], up: [f32; 3]| CubeFace {
+        layer,
+        forward: forward.into(),
+        right: right.into(),
+        up: up.into(),
+    };
+    [
+        face(
+            CubeLayer::PositiveX,
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, -1.0],
+            [0.0, -1.0, 0.0],
+        ),
+        face(
+            CubeLayer::NegativeX,
+            [-1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, -1.0, 0.0],
+        ),
+        face(
+            CubeLayer::PositiveY,
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ),
+        face(
+            CubeLayer::NegativeY,
+            [0.0, -1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, -1.0],
+        ),
+        face(
+            CubeLayer::PositiveZ,
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0],
+        ),
+        face(
+            CubeLayer::NegativeZ,
+            [0.0, 0.0, -1.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0],
+        ),
+    ]
+}
+
+pub struct IblPrograms<'a, 'b, 'c> {
+    pub irradiance: &'a IblIrradianceProgram,
+    pub prefilter: &'b IblPrefilterProgram,
+    pub brdf: &'c IblBrdfProgram,
+}
+
+// ambient light from the sky, used instead of the constant ambient terms of the lights
+pub struct ImageBasedLighting {
+    pub enabled: bool,
+    pub intensity: f32,
+    // the maps of the skybox are of the skybox during the day, this dims them with the day/night
+    // cycle, the sky drawn by SkyCubemap is already as bright as it should be
+    pub sky_brightness: f32,
+    // cosine weighted average of the sky around each normal, for the diffuse light
+    pub irradiance: Cubemap,
+    // the sky blurred by the ggx lobe, rougher in each mipmap, for the reflections
+    pub prefiltered: Cubemap,
+    // scale and bias of the fresnel for each view angle and roughness
+    pub brdf_lut: Texture2d,
+    // smaller copy of the sky with mipmaps, the sampling reads it
+    environment: Cubemap,
+    screen_quad: MainFramebufferShader,
+    // the maps are of the skybox, they only have to be loaded again after use_sky
+    skybox_loaded: bool,
+}
+
+impl ImageBasedLighting {
+    // the maps are empty until use_skybox or use_sky, only the lut is made here
+    pub fn new(display: &Display, programs: &IblPrograms) -> Self {
+        let ibl = Self {
+            enabled: true,
+            intensity: 0.3,
+            sky_brightness: 1.0,
+            irradiance: Self::create_cubemap(display, IRRADIANCE_SIZE, MipmapsOption::NoMipmap),
+            prefiltered: Self::create_cubemap(
+                display,
+                PREFILTERED_SIZE,
+                MipmapsOption::EmptyMipmapsMax(PREFILTERED_LEVELS - 1),
+            ),
+            brdf_lut: Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::F16F16,
+                MipmapsOption::NoMipmap,
+                BRDF_LUT_SIZE,
+                BRDF_LUT_SIZE,
+            )
+            .unwrap(),
+            environment: Self::create_cubemap(
+                display,
+                ENVIRONMENT_SIZE,
+                MipmapsOption::EmptyMipmaps,
+            ),
+            screen_quad: MainFramebufferShader::new(display),
+            skybox_loaded: false,
+        };
+
+        let mut target = SimpleFrameBuffer::new(display, &ibl.brdf_lut).unwrap();
+        target
+            .draw(
+                &ibl.screen_quad.vertex_buffer,
+                ibl.screen_quad.index_buffer,
+                &programs.brdf.0,
+                &glium::uniforms::EmptyUniforms,
+                &glium::DrawParameters::default(),
+            )
+            .unwrap();
+        ibl
+    }
+
+    // the maps of the skybox only depend on it, so they are saved to the cache file, cache_key
+    // has to change with the skybox
+    pub fn use_skybox(
+        &mut self,
+        display: &Display,
+        programs: &IblPrograms,
+        skybox: &Cubemap,
+        cache_key: u64,
+        cache_path: &str,
+    ) {
+        if self.skybox_loaded {
+            return;
+        }
+        self.skybox_loaded = true;
+
+        use std::time::Instant;
+        let now = Instant::now();
+        match self.load_cache(display, cache_key, cache_path) {
+            Ok(()) => println!(
+                "Loaded the image based lighting in {} milliseconds",
+                now.elapsed().as_millis()
+            ),
+            Err(error) => {
+                println!("Couldn't load the image based lighting cache: {}", error);
+                self.refilter(display, programs, skybox);
+                println!(
+                    "Precomputed the image based lighting in {} milliseconds",
+                    now.elapsed().as_millis()
+                );
+                if let Err(error) = self.save_cache(cache_key, cache_path) {
+                    println!("Couldn't save the image based lighting cache: {}", error);
+                }
+            }
+        }
+    }
+
+    // the maps of the sky drawn by SkyCubemap, they change with the time of day so they aren't
+    // cached
+    pub fn use_sky(&mut self, display: &Display, programs: &IblPrograms, sky: &Cubemap) {
+        self.skybox_loaded = false;
+        self.sky_brightness = 1.0;
+        self.refilter(display, programs, sky);
+    }
+
+    fn create_cubemap(display: &Display, size: u32, mipmaps: MipmapsOption) -> Cubemap {
+        Cubemap::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16F16,
+            mipmaps,
+            size,
+        )
+        .unwrap()
+    }
+
+    // for the irradiance and the lut
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        }
+    }
+
+    // blends between the mipmaps, for the prefiltered reflections
+    pub fn get_mipmap_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            minify_filter: MinifySamplerFilter::LinearMipmapLinear,
+            ..self.get_sampler()
+        }
+    }
+
+    // the irradiance and the prefiltered maps of another sky, the lut stays the same
+    fn refilter(&self, display: &Display, programs: &IblPrograms, sky: &Cubemap) {
+        self.copy_environment(display, sky);
+        let environment = &self.environment;
+        let screen_quad = &self.screen_quad;
+        let params = glium::DrawParameters::default();
+        let environment_sampler = self.get_mipmap_sampler();
+
+        for face in get_cube_faces().iter() {
+            let mut target =
+                SimpleFrameBuffer::new(display, self.irradiance.main_level().image(face.layer))
+                    .unwrap();
+            let uniforms =
+                IblIrradianceProgram::get_uniforms(environment, environment_sampler, face);
+            target
+                .draw(
+                    &screen_quad.vertex_buffer,
+                    screen_quad.index_buffer,
+                    &programs.irradiance.0,
+                    &uniforms,
+                    &params,
+                )
+                .unwrap();
+
+            for level in 0..PREFILTERED_LEVELS {
+                let mipmap = self.prefiltered.mipmap(level).unwrap();
+                let mut target = SimpleFrameBuffer::new(display, mipmap.image(face.layer)).unwrap();
+                let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+                let uniforms = IblPrefilterProgram::get_uniforms(
+                    environment,
+                    environment_sampler,
+                    face,
+                    roughness,
+                );
+                target
+                    .draw(
+                        &screen_quad.vertex_buffer,
+                        screen_quad.index_buffer,
+                        &programs.prefilter.0,
+                        &uniforms,
+                        &params,
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    // each mipmap of the environment is blitted from the one above it
+    fn copy_environment(&self, display: &Display, sky: &Cubemap) {
+        let environment = &self.environment;
+        for face in get_cube_faces().iter() {
+            let source =
+                SimpleFrameBuffer::new(display, sky.main_level().image(face.layer)).unwrap();
+            let target =
+                SimpleFrameBuffer::new(display, environment.main_level().image(face.layer))
+                    .unwrap();
+            source.fill(&target, MagnifySamplerFilter::Linear);
+
+            for level in 1..environment.get_mipmap_levels() {
+                let source = SimpleFrameBuffer::new(
+                    display,
+                    environment.mipmap(level - 1).unwrap().image(face.layer),
+                )
+                .unwrap();
+                let target = SimpleFrameBuffer::new(
+                    display,
+                    environment.mipmap(level).unwrap().image(face.layer),
+                )
+                .unwrap();
+                source.fill(&target, MagnifySamplerFilter::Linear);
+            }
+        }
+    }
+
+    // every cubemap face and mipmap as rows of f32 rgba pixels
+    fn save_cache(&self, cache_key: u64, cache_path: &str) -> io::Result<()> {
+        if let Some(directory) = std::path::Path::new(cache_path).parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut file = BufWriter::new(File::create(cache_path)?);
+        file.write_all(CACHE_MAGIC)?;
+        file.write_all(&CACHE_VERSION.to_le_bytes())?;
+        file.write_all(&cache_key.to_le_bytes())?;
+
+        let mut write_pixels = |pixels: Vec<Vec<Pixel>>| -> io::Result<()> {
+            for (r, g, b, a) in pixels.into_iter().flatten() {
+                for value in [r, g, b, a].iter() {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+            Ok(())
+        };
+
+        for face in get_cube_faces().iter() {
+            write_pixels(Self::read_cubemap_image(&self.irradiance, 0, face.layer))?;
+            for level in 0..PREFILTERED_LEVELS {
+                write_pixels(Self::read_cubemap_image(
+                    &self.prefiltered,
+                    level,
+                    face.layer,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_cubemap_image(cubemap: &Cubemap, level: u32, layer: CubeLayer) -> Vec<Vec<Pixel>> {
+        let mipmap = cubemap.mipmap(level).unwrap();
+        let size = mipmap.dimensions();
+        let image: glium::texture::TextureAnyImage = mipmap.image(layer).into();
+        image.raw_read(&glium::Rect {
+            left: 0,
+            bottom: 0,
+            width: size,
+            height: size,
+        })
+    }
+
+    fn load_cache(&self, display: &Display, cache_key: u64, cache_path: &str) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(cache_path)?);
+
+        let mut magic = [0u8; 4];
+        let mut version = [0u8; 4];
+        let mut key = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        file.read_exact(&mut version)?;
+        file.read_exact(&mut key)?;
+        if &magic != CACHE_MAGIC
+            || u32::from_le_bytes(version) != CACHE_VERSION
+            || u64::from_le_bytes(key) != cache_key
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the cache was made for another skybox or version",
+            ));
+        }
+
+        let mut read_texture = |size: u32| -> io::Result<Texture2d> {
+            let mut bytes = vec![0u8; (size * size * 16) as usize];
+            file.read_exact(&mut bytes)?;
+            let values: Vec<f32> = bytes
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect();
+            let pixels: Vec<Vec<Pixel>> = values
+                .chunks_exact((size * 4) as usize)
+                .map(|row| {
+                    row.chunks_exact(4)
+                        .map(|pixel| (pixel[0], pixel[1], pixel[2], pixel[3]))
+                        .collect()
+                })
+                .collect();
+            Ok(Texture2d::with_format(
+                display,
+                pixels,
+                UncompressedFloatFormat::F16F16F16F16,
+                MipmapsOption::NoMipmap,
+            )
+            .unwrap())
+        };
+
+        // the loaded images are blitted into place, like the skybox faces
+        for face in get_cube_faces().iter() {
+            let texture = read_texture(IRRADIANCE_SIZE)?;
+            let target =
+                SimpleFrameBuffer::new(display, self.irradiance.main_level().image(face.layer))
+                    .unwrap();
+            texture
+                .as_surface()
+                .fill(&target, MagnifySamplerFilter::Nearest);
+
+            for level in 0..PREFILTERED_LEVELS {
+                let mipmap = self.prefiltered.mipmap(level).unwrap();
+                let texture = read_texture(mipmap.dimensions())?;
+                let target = SimpleFrameBuffer::new(display, mipmap.image(face.layer)).unwrap();
+                texture
+                    .as_surface()
+                    .fill(&target, MagnifySamplerFilter::Nearest);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use cgmath::{Matrix4, Point3, Transform, Vector3};
+use glium::{
+    texture::buffer_texture::{BufferTexture, BufferTextureType},
+    Display,
+};
+
+use crate::camera::Camera;
+use crate::shaders::common::PointLight;
+use crate::shaders::lights::MAX_POINT_LIGHTS;
+
+// these have to match the defines in SimpleTexturedObjectProgram
+pub const CLUSTER_COUNT_X: usize = 16;
+pub const CLUSTER_COUNT_Y: usize = 9;
+pub const CLUSTER_COUNT_Z: usize = 24;
+const CLUSTER_COUNT: usize = CLUSTER_COUNT_X * CLUSTER_COUNT_Y * CLUSTER_COUNT_Z;
+
+// room for this many lights per cluster on average, the rest gets dropped
+const AVERAGE_LIGHTS_PER_CLUSTER: usize = 32;
+const MAX_LIGHT_INDICES: usize = CLUSTER_COUNT * AVERAGE_LIGHTS_PER_CLUSTER;
+
+// view space bounding box of a cluster
+#[derive(Copy, Clone)]
+struct ClusterBounds {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl ClusterBounds {
+    fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let mut distance_squared = 0.0;
+        for axis in 0..3 {
+            let closest = center[axis].max(self.min[axis]).min(self.max[axis]);
+            distance_squared += (center[axis] - closest) * (center[axis] - closest);
+        }
+        distance_squared <= radius * radius
+    }
+}
+
+// where a depth slice starts, the last one ends at the far plane
+fn get_slice_depth(slice: usize, near_plane: f32, far_plane: f32) -> f32 {
+    near_plane * (far_plane / near_plane).powf(slice as f32 / CLUSTER_COUNT_Z as f32)
+}
+
+// the depths out of the frustum go to the first or the last slice
+fn get_slice(depth: f32, near_plane: f32, far_plane: f32) -> usize {
+    let slice = (depth / near_plane).ln() / (far_plane / near_plane).ln() * CLUSTER_COUNT_Z as f32;
+    (slice.max(0.0) as usize).min(CLUSTER_COUNT_Z - 1)
+}
+
+// the camera frustum split into a grid of clusters, the x and y follow the screen tiles and the
+// depth slices grow exponentially so the far away ones aren't huge
+pub struct LightClusters {
+    pub enabled: bool,
+    // offset into light_indices and light count for every cluster
+    pub grid: BufferTexture<(u32, u32)>,
+    pub light_indices: BufferTexture<u32>,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    bounds: Vec<ClusterBounds>,
+    // the bounds only change with the projection
+    bounds_projection: (f32, f32, f32, f32),
+    cluster_lights: Vec<Vec<u32>>,
+}
+
+impl LightClusters {
+    pub fn new(display: &Display) -> Self {
+        Self {
+            enabled: true,
+            grid: BufferTexture::empty_dynamic(display, CLUSTER_COUNT, BufferTextureType::Unsigned)
+                .unwrap(),
+            light_indices: BufferTexture::empty_dynamic(
+                display,
+                MAX_LIGHT_INDICES,
+                BufferTextureType::Unsigned,
+            )
+            .unwrap(),
+            view: Matrix4::from_scale(1.0),
+            projection: Matrix4::from_scale(1.0),
+            near_plane: 0.1,
+            far_plane: 1000.0,
+            bounds: Vec::new(),
+            bounds_projection: (0.0, 0.0, 0.0, 0.0),
+            cluster_lights: vec![Vec::new(); CLUSTER_COUNT],
+        }
+    }
+
+    fn update_bounds(&mut self, fov: f32, aspect_ratio: f32) {
+        let projection = (fov, aspect_ratio, self.near_plane, self.far_plane);
+        if self.bounds_projection == projection {
+            return;
+        }
+        self.bounds_projection = projection;
+
+        let half_height = (fov / 2.0).tan();
+        let half_width = half_height * aspect_ratio;
+        self.bounds.clear();
+        for z in 0..CLUSTER_COUNT_Z {
+            let near_depth = get_slice_depth(z, self.near_plane, self.far_plane);
+            let far_depth = get_slice_depth(z + 1, self.near_plane, self.far_plane);
+            for y in 0..CLUSTER_COUNT_Y {
+                let bottom = (y as f32 / CLUSTER_COUNT_Y as f32) * 2.0 - 1.0;
+                let top = ((y + 1) as f32 / CLUSTER_COUNT_Y as f32) * 2.0 - 1.0;
+                for x in 0..CLUSTER_COUNT_X {
+                    let left = (x as f32 / CLUSTER_COUNT_X as f32) * 2.0 - 1.0;
+                    let right = ((x + 1) as f32 / CLUSTER_COUNT_X as f32) * 2.0 - 1.0;
+
+                    let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+                    let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+                    for depth in [near_depth, far_depth].iter() {
+                        for ndc_x in [left, right].iter() {
+                            for ndc_y in [bottom, top].iter() {
+                                let corner = Vector3::new(
+                                    ndc_x * half_width * depth,
+                                    ndc_y * half_height * depth,
+                                    -depth,
+                                );
+                                min = Vector3::new(
+                                    min.x.min(corner.x),
+                                    min.y.min(corner.y),
+                                    min.z.min(corner.z),
+                                );
+                                max = Vector3::new(
+                                    max.x.max(corner.x),
+                                    max.y.max(corner.y),
+                                    max.z.max(corner.z),
+                                );
+                            }
+                        }
+                    }
+                    self.bounds.push(ClusterBounds { min, max });
+                }
+            }
+        }
+    }
+
+    // the point lights have to be in the same order as in the LightBuffer
+    pub fn update(&mut self, point_lights: &[&PointLight], camera: &Camera, aspect_ratio: f32) {
+        self.view = camera.get_view_matrix();
+        self.projection = camera.get_projection_matrix(aspect_ratio);
+        self.near_plane = camera.near_plane;
+        self.far_plane = camera.far_plane;
+        self.update_bounds(camera.fov, aspect_ratio);
+
+        for lights in self.cluster_lights.iter_mut() {
+            lights.clear();
+        }
+
+        for (i, light) in point_lights.iter().take(MAX_POINT_LIGHTS).enumerate() {
+            let radius = light.get_radius();
+            let center = self.view.transform_point(light.position);
+            let depth = -center.z;
+            if depth + radius < self.near_plane || depth - radius > self.far_plane {
+                continue;
+            }
+
+            let first_slice = get_slice(depth - radius, self.near_plane, self.far_plane);
+            let last_slice = get_slice(depth + radius, self.near_plane, self.far_plane);
+            for z in first_slice..=last_slice {
+                let slice_start = z * CLUSTER_COUNT_X * CLUSTER_COUNT_Y;
+                for cluster in slice_start..slice_start + CLUSTER_COUNT_X * CLUSTER_COUNT_Y {
+                    if self.bounds[cluster].intersects_sphere(center, radius) {
+                        self.cluster_lights[cluster].push(i as u32);
+                    }
+                }
+            }
+        }
+
+        let mut grid = Vec::with_capacity(CLUSTER_COUNT);
+        let mut light_indices = Vec::new();
+        for lights in self.cluster_lights.iter() {
+            let offset = light_indices.len();
+            let count = lights.len().min(MAX_LIGHT_INDICES - offset);
+            light_indices.extend_from_slice(&lights[..count]);
+            grid.push((offset as u32, count as u32));
+        }
+
+        self.grid.write(&grid);
+        if !light_indices.is_empty() {
+            self.light_indices
+                .slice(0..light_indices.len())
+                .unwrap()
+                .write(&light_indices);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_go_from_the_near_to_the_far_plane() {
+        let (near_plane, far_plane) = (0.1, 1000.0);
+        assert!((get_slice_depth(0, near_plane, far_plane) - near_plane).abs() < 1e-6);
+        assert!((get_slice_depth(CLUSTER_COUNT_Z, near_plane, far_plane) - far_plane).abs() < 1e-2);
+
+        assert_eq!(get_slice(near_plane, near_plane, far_plane), 0);
+        assert_eq!(
+            get_slice(far_plane, near_plane, far_plane),
+            CLUSTER_COUNT_Z - 1
+        );
+        // a bit past the start of every slice is still in it
+        for slice in 0..CLUSTER_COUNT_Z {
+            let depth = get_slice_depth(slice, near_plane, far_plane) * 1.01;
+            assert_eq!(get_slice(depth, near_plane, far_plane), slice);
+        }
+    }
+
+    #[test]
+    fn depths_out_of_the_frustum_are_clamped() {
+        let (near_plane, far_plane) = (0.1, 1000.0);
+        assert_eq!(get_slice(0.05, near_plane, far_plane), 0);
+        assert_eq!(get_slice(0.0, near_plane, far_plane), 0);
+        assert_eq!(get_slice(-1.0, near_plane, far_plane), 0);
+        assert_eq!(
+            get_slice(5000.0, near_plane, far_plane),
+            CLUSTER_COUNT_Z - 1
+        );
+    }
+
+    #[test]
+    fn spheres_touching_the_bounds_intersect() {
+        let bounds = ClusterBounds {
+            min: Vector3::new(0.0, 0.0, 0.0),
+            max: Vector3::new(1.0, 1.0, 1.0),
+        };
+        // a face
+        assert!(bounds.intersects_sphere(Point3::new(2.0, 0.5, 0.5), 1.0));
+        assert!(!bounds.intersects_sphere(Point3::new(2.0, 0.5, 0.5), 0.99));
+        // a corner
+        assert!(bounds.intersects_sphere(Point3::new(2.0, 2.0, 2.0), 3.0f32.sqrt() + 1e-5));
+        assert!(!bounds.intersects_sphere(Point3::new(2.0, 2.0, 2.0), 1.7));
+        // inside
+        assert!(bounds.intersects_sphere(Point3::new(0.5, 0.5, 0.5), 0.01));
+    }
+}
@@ -0,0 +1,318 @@
+use glium::{
+    program::{self, BlockLayout},
+    uniforms::{LayoutMismatchError, UniformBlock, UniformBuffer, UniformValue},
+    Display,
+};
+
+use crate::shaders::common::{DirectionalLight, PointLight, SpotLight};
+use crate::shaders::light_clusters::LightClusters;
+use crate::shaders::shadow_map::ShadowMaps;
+
+// these have to match the defines in SimpleTexturedObjectProgram, extra lights are ignored
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 8;
+pub const MAX_POINT_LIGHTS: usize = 128;
+pub const MAX_SPOT_LIGHTS: usize = 32;
+
+// glium's implement_uniform_block! dereferences a null pointer to get the field offsets, which
+// aborts in debug builds, so this does the same with offset_of!
+macro_rules! implement_light_block {
+    ($struct_name:ident, $($field_name:ident),+) => {
+        impl UniformBlock for $struct_name {
+            fn matches(
+                layout: &BlockLayout,
+                base_offset: usize,
+            ) -> Result<(), LayoutMismatchError> {
+                fn field_matches<T: UniformBlock>(
+                    _: fn(&$struct_name) -> &T,
+                    layout: &BlockLayout,
+                    offset: usize,
+                ) -> Result<(), LayoutMismatchError> {
+                    T::matches(layout, offset)
+                }
+
+                let members = match layout {
+                    BlockLayout::Struct { members } => members,
+                    _ => {
+                        return Err(LayoutMismatchError::LayoutMismatch {
+                            expected: layout.clone(),
+                            obtained: Self::build_layout(base_offset),
+                        })
+                    }
+                };
+                $(
+                    let member = members
+                        .iter()
+                        .find(|(name, _)| name == stringify!($field_name))
+                        .ok_or_else(|| LayoutMismatchError::MissingField {
+                            name: stringify!($field_name).to_owned(),
+                        })?;
+                    field_matches(
+                        |light: &$struct_name| &light.$field_name,
+                        &member.1,
+                        base_offset + std::mem::offset_of!($struct_name, $field_name),
+                    )
+                    .map_err(|err| LayoutMismatchError::MemberMismatch {
+                        member: stringify!($field_name).to_owned(),
+                        err: Box::new(err),
+                    })?;
+                )+
+                Ok(())
+            }
+
+            fn build_layout(base_offset: usize) -> BlockLayout {
+                fn field_layout<T: UniformBlock>(
+                    _: fn(&$struct_name) -> &T,
+                    offset: usize,
+                ) -> BlockLayout {
+                    T::build_layout(offset)
+                }
+
+                BlockLayout::Struct {
+                    members: vec![$(
+                        (
+                            stringify!($field_name).to_owned(),
+                            field_layout(
+                                |light: &$struct_name| &light.$field_name,
+                                base_offset + std::mem::offset_of!($struct_name, $field_name),
+                            ),
+                        ),
+                    )+],
+                }
+            }
+        }
+    };
+}
+
+// the gpu versions of the lights follow the std140 layout, every vec3 is followed by a scalar
+// so there's no hidden padding
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuDirectionalLight {
+    direction: [f32; 3],
+    // -1 when the light has no shadow map
+    shadow_index: i32,
+    ambient: [f32; 3],
+    _padding0: f32,
+    diffuse: [f32; 3],
+    _padding1: f32,
+    specular: [f32; 3],
+    _padding2: f32,
+}
+
+implement_light_block!(
+    GpuDirectionalLight,
+    direction,
+    shadow_index,
+    ambient,
+    _padding0,
+    diffuse,
+    _padding1,
+    specular,
+    _padding2
+);
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuPointLight {
+    position: [f32; 3],
+    constant: f32,
+    ambient: [f32; 3],
+    linear: f32,
+    diffuse: [f32; 3],
+    quadratic: f32,
+    specular: [f32; 3],
+    shadow_index: i32,
+}
+
+implement_light_block!(
+    GpuPointLight,
+    position,
+    constant,
+    ambient,
+    linear,
+    diffuse,
+    quadratic,
+    specular,
+    shadow_index
+);
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct GpuSpotLight {
+    position: [f32; 3],
+    cut_off: f32,
+    direction: [f32; 3],
+    outer_cut_off: f32,
+    ambient: [f32; 3],
+    constant: f32,
+    diffuse: [f32; 3],
+    linear: f32,
+    specular: [f32; 3],
+    quadratic: f32,
+    shadow_index: i32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+implement_light_block!(
+    GpuSpotLight,
+    position,
+    cut_off,
+    direction,
+    outer_cut_off,
+    ambient,
+    constant,
+    diffuse,
+    linear,
+    specular,
+    quadratic,
+    shadow_index,
+    _padding0,
+    _padding1,
+    _padding2
+);
+
+// the std140 size of the block is rounded up to 16 bytes, the buffer has to be at least as big
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+pub struct LightBlock {
+    directional_lights: [GpuDirectionalLight; MAX_DIRECTIONAL_LIGHTS],
+    point_lights: [GpuPointLight; MAX_POINT_LIGHTS],
+    spot_lights: [GpuSpotLight; MAX_SPOT_LIGHTS],
+    directional_light_count: i32,
+    point_light_count: i32,
+    spot_light_count: i32,
+}
+
+implement_light_block!(
+    LightBlock,
+    directional_lights,
+    point_lights,
+    spot_lights,
+    directional_light_count,
+    point_light_count,
+    spot_light_count
+);
+
+fn get_shadow_index(slot: Option<usize>) -> i32 {
+    slot.map_or(-1, |slot| slot as i32)
+}
+
+impl GpuDirectionalLight {
+    fn new(light: &DirectionalLight, shadow_index: i32) -> Self {
+        Self {
+            direction: light.direction.into(),
+            shadow_index,
+            ambient: light.ambient.into(),
+            _padding0: 0.0,
+            diffuse: light.diffuse.into(),
+            _padding1: 0.0,
+            specular: light.specular.into(),
+            _padding2: 0.0,
+        }
+    }
+}
+
+impl GpuPointLight {
+    fn new(light: &PointLight, shadow_index: i32) -> Self {
+        Self {
+            position: light.position.into(),
+            constant: light.constant,
+            ambient: light.ambient.into(),
+            linear: light.linear,
+            diffuse: light.diffuse.into(),
+            quadratic: light.quadratic,
+            specular: light.specular.into(),
+            shadow_index,
+        }
+    }
+}
+
+impl GpuSpotLight {
+    fn new(light: &SpotLight, shadow_index: i32) -> Self {
+        Self {
+            position: light.position.into(),
+            cut_off: light.cut_off,
+            direction: light.direction.into(),
+            outer_cut_off: light.outer_cut_off,
+            ambient: light.ambient.into(),
+            constant: light.constant,
+            diffuse: light.diffuse.into(),
+            linear: light.linear,
+            specular: light.specular.into(),
+            quadratic: light.quadratic,
+            shadow_index,
+            _padding0: 0.0,
+            _padding1: 0.0,
+            _padding2: 0.0,
+        }
+    }
+}
+
+// every light of the scene, uploaded once per frame and shared by all the draw calls
+pub struct LightBuffer {
+    pub buffer: UniformBuffer<LightBlock>,
+    // which point lights reach each part of the view, has to be updated with the same lights
+    pub clusters: LightClusters,
+}
+
+impl LightBuffer {
+    pub fn new(display: &Display) -> Self {
+        Self {
+            buffer: UniformBuffer::empty_dynamic(display).unwrap(),
+            clusters: LightClusters::new(display),
+        }
+    }
+
+    pub fn get_uniform_value(&self) -> UniformValue<'_> {
+        fn matches(block: &program::UniformBlock) -> Result<(), LayoutMismatchError> {
+            LightBlock::matches(&block.layout, 0)
+        }
+        UniformValue::Block(self.buffer.as_slice_any(), matches)
+    }
+
+    // the shadow maps need to have their lights assigned first
+    pub fn update(
+        &mut self,
+        directional_lights: &[&DirectionalLight],
+        point_lights: &[&PointLight],
+        spot_lights: &[&SpotLight],
+        shadow_maps: &ShadowMaps,
+    ) {
+        let directional_light_count = directional_lights.len().min(MAX_DIRECTIONAL_LIGHTS);
+        let point_light_count = point_lights.len().min(MAX_POINT_LIGHTS);
+        let spot_light_count = spot_lights.len().min(MAX_SPOT_LIGHTS);
+
+        // the unused entries are never read by the shader
+        let mut block = LightBlock {
+            directional_lights: [GpuDirectionalLight::default(); MAX_DIRECTIONAL_LIGHTS],
+            point_lights: [GpuPointLight::default(); MAX_POINT_LIGHTS],
+            spot_lights: [GpuSpotLight::default(); MAX_SPOT_LIGHTS],
+            directional_light_count: directional_light_count as i32,
+            point_light_count: point_light_count as i32,
+            spot_light_count: spot_light_count as i32,
+        };
+
+        for (i, light) in directional_lights.iter().take(directional_light_count).enumerate() {
+            let shadow_index = get_shadow_index(
+                shadow_maps
+                    .directional
+                    .light_index
+                    .filter(|light_index| *light_index == i)
+                    .map(|_| 0),
+            );
+            block.directional_lights[i] = GpuDirectionalLight::new(light, shadow_index);
+        }
+        for (i, light) in point_lights.iter().take(point_light_count).enumerate() {
+            let shadow_index = get_shadow_index(shadow_maps.point.get_slot(i));
+            block.point_lights[i] = GpuPointLight::new(light, shadow_index);
+        }
+        for (i, light) in spot_lights.iter().take(spot_light_count).enumerate() {
+            let shadow_index = get_shadow_index(shadow_maps.spot.get_slot(i));
+            block.spot_lights[i] = GpuSpotLight::new(light, shadow_index);
+        }
+
+        self.buffer.write(&block);
+    }
+}
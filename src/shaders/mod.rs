@@ -3,4 +3,17 @@ pub mod simple_shaders;
 pub mod common;
 pub mod main_framebuffer_shader;
 pub mod cubemap;
-pub mod renderable_3d_object_shader;
\ No newline at end of file
+pub mod renderable_3d_object_shader;
+pub mod sphere_shader;
+pub mod shadow_map;
+pub mod lights;
+pub mod light_clusters;
+pub mod deferred;
+pub mod tone_mapping;
+pub mod bloom;
+pub mod ssao;
+pub mod ibl;
+pub mod reflection_probes;
+pub mod sky;
+pub mod volumetric_light;
+pub mod fog;
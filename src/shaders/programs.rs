@@ -1,385 +1,3377 @@
-use cgmath::{Matrix4, Point3, Vector3};
-use glium::{uniforms::UniformValue, Display, Program};
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+use glium::{
+    uniforms::{
+        AsUniformValue, MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, UniformValue,
+    },
+    Display, Program,
+};
 
 use crate::common::ToArray;
-use crate::shaders::common::{DirectionalLight, Material, PointLight, SpotLight};
+use crate::shaders::bloom::Bloom;
+use crate::shaders::common::{EnvironmentMappingMode, Material, PbrMaterial};
+use crate::shaders::deferred::GBuffer;
+use crate::shaders::fog::Fog;
+use crate::shaders::ibl::{CubeFace, ImageBasedLighting, PREFILTERED_LEVELS};
+use crate::shaders::lights::LightBuffer;
+use crate::shaders::reflection_probes::EnvironmentMap;
+use crate::shaders::shadow_map::ShadowMaps;
+use crate::shaders::sky::DayNightCycle;
+use crate::shaders::ssao::Ssao;
+use crate::shaders::tone_mapping::ToneMapping;
+use crate::shaders::volumetric_light::VolumetricLight;
+
+// light structs, shadows and the lighting functions, shared by the forward and deferred programs
+const LIGHTING_SHADER_SRC: &str = r#"
+            // the light structs follow the layout of the gpu lights in lights.rs,
+            // shadow_index is -1 when the light has no shadows, for the spot lights it's the slot
+            // of the shadow map and the cookie
+            struct DirectionalLight {
+                vec3 direction;
+                int shadow_index;
+                vec3 ambient;
+                float _padding0;
+                vec3 diffuse;
+                float _padding1;
+                vec3 specular;
+                float _padding2;
+            };
 
-pub struct SimpleTexturedObjectProgram(pub Program);
+            struct SpotLight {
+                vec3 position;
+                float cut_off;
+                vec3 direction;
+                float outer_cut_off;
+                vec3 ambient;
+                float constant;
+                vec3 diffuse;
+                float linear;
+                vec3 specular;
+                float quadratic;
+                int shadow_index;
+                float _padding0;
+                float _padding1;
+                float _padding2;
+            };
 
-pub struct SimpleTexturedObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g> {
-    matrix: &'a Matrix4<f32>,
-    model: &'b Matrix4<f32>,
-    material: &'c Material,
-    directional_light: &'d DirectionalLight,
-    spot_light: &'e SpotLight,
-    point_lights: &'f [&'f PointLight; 4],
-    view: &'g Point3<f32>,
-}
+            struct PointLight {
+                vec3 position;
+                float constant;
+                vec3 ambient;
+                float linear;
+                vec3 diffuse;
+                float quadratic;
+                vec3 specular;
+                int shadow_index;
+            };
 
-impl glium::uniforms::Uniforms for SimpleTexturedObjectUniforms<'_, '_, '_, '_, '_, '_, '_> {
-    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
-        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
-        f("model", UniformValue::Mat4(self.model.to_array()));
+            #define MAX_DIRECTIONAL_LIGHTS 8
+            #define MAX_POINT_LIGHTS 128
+            #define MAX_SPOT_LIGHTS 32
+            layout(std140) uniform Lights {
+                DirectionalLight directional_lights[MAX_DIRECTIONAL_LIGHTS];
+                PointLight point_lights[MAX_POINT_LIGHTS];
+                SpotLight spot_lights[MAX_SPOT_LIGHTS];
+                int directional_light_count;
+                int point_light_count;
+                int spot_light_count;
+            };
+
+            uniform vec3 view_pos;
+
+            uniform bool ssao_enabled;
+            uniform sampler2D ssao_texture;
+
+            // multiplies the ambient light, it has to be set by main with get_ambient_occlusion
+            float ambient_occlusion = 1.0;
+
+            float get_ambient_occlusion() {
+                if (!ssao_enabled) {
+                    return 1.0;
+                }
+                return texture(ssao_texture, gl_FragCoord.xy / vec2(textureSize(ssao_texture, 0))).r;
+            }
+
+            // the ambient light from the sky replaces the ambient terms of the lights
+            uniform bool ibl_enabled;
+            uniform float ibl_intensity;
+            uniform samplerCube irradiance_map;
+            uniform samplerCube prefiltered_map;
+            uniform float prefiltered_max_lod;
+            uniform sampler2D brdf_lut;
+
+            vec3 get_light_ambient(vec3 ambient) {
+                if (ibl_enabled) {
+                    return vec3(0.0);
+                }
+                return ambient;
+            }
+
+            // the shininess decides how blurry the reflection of the sky is
+            vec3 calculate_phong_ibl(vec3 normal, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular, float shininess) {
+                if (!ibl_enabled) {
+                    return vec3(0.0);
+                }
+                float roughness = sqrt(2.0 / (shininess + 2.0));
+                vec3 irradiance = texture(irradiance_map, normal).rgb;
+                vec3 reflected = textureLod(prefiltered_map, reflect(-view_direction, normal), roughness * prefiltered_max_lod).rgb;
+                return (irradiance * tex_diffuse + reflected * tex_specular) * ibl_intensity * ambient_occlusion;
+            }
+
+            uniform sampler2DShadow shadow_map;
+            uniform int shadow_filter_size;
+
+            bool is_outside_shadow_map(vec3 projected) {
+                return projected.z > 1.0 || any(lessThan(projected.xy, vec2(0.0))) || any(greaterThan(projected.xy, vec2(1.0)));
+            }
+
+            // percentage-closer filtering, returns how much the fragment is lit
+            float filter_shadow_map(sampler2DShadow map, vec3 projected, int filter_size) {
+                vec2 texel_size = 1.0 / vec2(textureSize(map, 0));
+                float lit = 0.0;
+                for (int x = -filter_size; x <= filter_size; x++) {
+                    for (int y = -filter_size; y <= filter_size; y++) {
+                        lit += texture(map, vec3(projected.xy + vec2(x, y) * texel_size, projected.z));
+                    }
+                }
+                float taps = float((2 * filter_size + 1) * (2 * filter_size + 1));
+                return lit / taps;
+            }
+
+            float calculate_directional_shadow(vec4 frag_pos_light_space) {
+                vec3 projected = frag_pos_light_space.xyz / frag_pos_light_space.w * 0.5 + 0.5;
+                if (is_outside_shadow_map(projected)) {
+                    return 1.0;
+                }
+                return filter_shadow_map(shadow_map, projected, shadow_filter_size);
+            }
+
+            #define NR_SPOT_SHADOW_MAPS 2
+            uniform mat4 spot_light_space_matrices[NR_SPOT_SHADOW_MAPS];
+            uniform sampler2DShadow spot_shadow_maps[NR_SPOT_SHADOW_MAPS];
+            uniform bool spot_shadow_enabled[NR_SPOT_SHADOW_MAPS];
+            uniform int spot_shadow_filter_size;
+            uniform sampler2D spot_cookies[NR_SPOT_SHADOW_MAPS];
+            uniform bool spot_cookie_enabled[NR_SPOT_SHADOW_MAPS];
+
+            // sampler arrays can only be indexed with constant expressions
+            float filter_spot_shadow_map(int index, vec3 projected) {
+                switch (index) {
+                    case 0: return filter_shadow_map(spot_shadow_maps[0], projected, spot_shadow_filter_size);
+                    default: return filter_shadow_map(spot_shadow_maps[1], projected, spot_shadow_filter_size);
+                }
+            }
+
+            vec3 sample_spot_cookie(int index, vec2 coords) {
+                switch (index) {
+                    case 0: return texture(spot_cookies[0], coords).rgb;
+                    default: return texture(spot_cookies[1], coords).rgb;
+                }
+            }
+
+            // colour the spot light is multiplied by, the cookie pattern times the shadow
+            vec3 calculate_spot_projection(int index, vec3 frag_pos) {
+                if (index < 0) {
+                    return vec3(1.0);
+                }
+                vec4 frag_pos_spot_space = spot_light_space_matrices[index] * vec4(frag_pos, 1.0);
+                // behind the light
+                if (frag_pos_spot_space.w <= 0.0) {
+                    return vec3(0.0);
+                }
+                vec3 projected = frag_pos_spot_space.xyz / frag_pos_spot_space.w * 0.5 + 0.5;
+
+                vec3 projection = vec3(1.0);
+                if (spot_cookie_enabled[index]) {
+                    projection = sample_spot_cookie(index, projected.xy);
+                }
+                if (spot_shadow_enabled[index] && !is_outside_shadow_map(projected)) {
+                    projection *= filter_spot_shadow_map(index, projected);
+                }
+                return projection;
+            }
+
+            #define NR_POINT_SHADOW_MAPS 4
+            uniform samplerCubeShadow point_shadow_maps[NR_POINT_SHADOW_MAPS];
+            uniform float point_shadow_near_plane;
+            uniform float point_shadow_far_plane;
+            uniform float point_shadow_filter_radius;
+
+            // sampler arrays can only be indexed with constant expressions
+            float sample_point_shadow_map(int index, vec4 coords) {
+                switch (index) {
+                    case 0: return texture(point_shadow_maps[0], coords);
+                    case 1: return texture(point_shadow_maps[1], coords);
+                    case 2: return texture(point_shadow_maps[2], coords);
+                    default: return texture(point_shadow_maps[3], coords);
+                }
+            }
+
+            const vec3 point_shadow_offsets[20] = vec3[](
+                vec3( 1,  1,  1), vec3( 1, -1,  1), vec3(-1, -1,  1), vec3(-1,  1,  1),
+                vec3( 1,  1, -1), vec3( 1, -1, -1), vec3(-1, -1, -1), vec3(-1,  1, -1),
+                vec3( 1,  1,  0), vec3( 1, -1,  0), vec3(-1, -1,  0), vec3(-1,  1,  0),
+                vec3( 1,  0,  1), vec3(-1,  0,  1), vec3( 1,  0, -1), vec3(-1,  0, -1),
+                vec3( 0,  1,  1), vec3( 0, -1,  1), vec3( 0, -1, -1), vec3( 0,  1, -1)
+            );
+
+            // soft shadows, returns how much the fragment is lit
+            float calculate_point_shadow(int index, vec3 light_position, vec3 frag_pos, vec3 normal) {
+                if (index < 0) {
+                    return 1.0;
+                }
+
+                // the filter taps are spread around the fragment, so it's pushed away from its own
+                // surface first, otherwise surfaces seen at grazing angles shadow themselves
+                float radius = point_shadow_filter_radius * length(frag_pos - light_position);
+                vec3 light_to_frag = frag_pos + normal * radius * 2.0 - light_position;
+
+                // depth that the cubemap face looking at the fragment would have stored
+                vec3 distances = abs(light_to_frag);
+                float major_axis = max(distances.x, max(distances.y, distances.z));
+                float near = point_shadow_near_plane;
+                float far = point_shadow_far_plane;
+                float depth = (far + near) / (far - near) - (2.0 * far * near) / ((far - near) * major_axis);
+                depth = depth * 0.5 + 0.5;
+
+                // too far from the light to be in the shadow map
+                if (depth >= 1.0) {
+                    return 1.0;
+                }
+
+                float lit = 0.0;
+                for (int i = 0; i < 20; i++) {
+                    lit += sample_point_shadow_map(index, vec4(light_to_frag + point_shadow_offsets[i] * radius, depth));
+                }
+                return lit / 20.0;
+            }
+
+            vec3 calculate_directional_light(DirectionalLight light, vec3 normal, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular, float shininess, float shadow) {
+                vec3 light_dir = normalize(-light.direction);
+
+                // diffuse shading
+                float diff = max(dot(normal, light_dir), 0.0);
+
+                // specular shading
+                vec3 reflect_direction = reflect(-light_dir, normal);
+                float spec = pow(max(dot(light.direction, reflect_direction), 0.0),
+                                 shininess);
+
+                // combine results
+                vec3 ambient = get_light_ambient(light.ambient) * tex_diffuse * ambient_occlusion;
+                vec3 diffuse = light.diffuse * diff * tex_diffuse;
+                vec3 specular = light.specular * spec * tex_specular; 
+                return (ambient + (diffuse + specular) * shadow);
+            }
+
+
+            vec3 calculate_spot_light(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular, float shininess, vec3 projection) {
+                // diffuse
+                vec3 light_direction = normalize(light.position - frag_pos);
+                float diff = max(dot(normal, light_direction), 0.0);
+    
+                // specular
+                vec3 reflect_direction = reflect(-light_direction, normal);  
+                float spec = pow(max(dot(view_direction, reflect_direction), 0.0), 
+                                 shininess);
+
+                vec3 ambient = get_light_ambient(light.ambient) * tex_diffuse * ambient_occlusion;
+                vec3 diffuse = light.diffuse * diff * tex_diffuse;
+                vec3 specular = light.specular * spec * tex_specular; 
+
+                float theta = dot(light_direction, normalize(-light.direction));
+                float epsilon = light.cut_off - light.outer_cut_off;
+                float intensity = clamp((theta - light.outer_cut_off) / epsilon, 0.0, 1.0);
+
+                float distance = length(light.position - frag_pos);
+                float attenuation = 1.0 / (light.constant + light.linear * distance +
+                                    light.quadratic * (distance * distance));
+
+                ambient *= attenuation;
+                diffuse *= intensity * attenuation * projection;
+                specular *= intensity * attenuation * projection;
+
+                return (ambient + diffuse + specular);
+            }
+
+
+            vec3 calculate_point_light(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular, float shininess, float shadow) {
+                // diffuse
+                vec3 light_direction = normalize(light.position - frag_pos);
+                float diff = max(dot(normal, light_direction), 0.0);
+    
+                // specular
+                vec3 reflect_direction = reflect(-light_direction, normal);  
+                float spec = pow(max(dot(view_direction, reflect_direction), 0.0), 
+                                 shininess);
+
+                vec3 ambient = get_light_ambient(light.ambient) * tex_diffuse * ambient_occlusion;
+                vec3 diffuse = light.diffuse * diff * tex_diffuse;
+                vec3 specular = light.specular * spec * tex_specular; 
+
+                float distance = length(light.position - frag_pos);
+                float attenuation = 1.0 / (light.constant + light.linear * distance +
+                                    light.quadratic * (distance * distance));
+                   
+                ambient *= attenuation;
+                diffuse *= attenuation * shadow;
+                specular *= attenuation * shadow;
+
+                return (ambient + diffuse + specular);
+            }
+"#;
+
+// normal mapping, the vertex shader builds the tangent space and the fragment shader moves the
+// normal map into world space with it
+const TANGENT_SPACE_SHADER_SRC: &str = r#"
+            mat3 get_tbn(mat4 model, vec3 normal, vec4 tangent) {
+                mat3 normal_matrix = mat3(transpose(inverse(model)));
+                vec3 n = normalize(normal_matrix * normal);
+                vec3 t = normalize(mat3(model) * tangent.xyz);
+                t = normalize(t - n * dot(n, t));
+                vec3 b = cross(n, t) * tangent.w;
+                return mat3(t, b, n);
+            }
+
+            vec3 get_mapped_normal(mat3 tbn, sampler2D normal_map, vec2 tex_coords) {
+                vec3 normal = texture(normal_map, tex_coords).rgb * 2.0 - 1.0;
+                return normalize(tbn * normal);
+            }
+"#;
+
+// the material of the textured objects, with the parallax occlusion mapping of its height map
+const MATERIAL_SHADER_SRC: &str = r#"
+            struct Material {
+                sampler2D diffuse;
+                sampler2D specular;
+                sampler2D normal;
+                float shininess;
+
+                bool parallax;
+                sampler2D height;
+                float depth_scale;
+                int parallax_layers;
+
+                // see ENVIRONMENT_MAPPING_SHADER_SRC
+                int environment_mode;
+                float reflectivity;
+                bool reflectivity_from_specular;
+                float ior;
+                vec3 environment_tint;
+
+                sampler2D emissive;
+                vec3 emissive_factor;
+            };
+            uniform Material material;
+
+            vec3 get_emission(vec2 tex_coords) {
+                return texture(material.emissive, tex_coords).rgb * material.emissive_factor;
+            }
+
+            // moves the texture coordinates to where the view ray hits the height map, the ray
+            // goes down one layer at a time until it's under the surface
+            // dx and dy are the derivatives of the texture coordinates, they have to be taken at
+            // the top of main because they are undefined inside loops and after early returns
+            vec2 get_parallax_tex_coords(vec2 tex_coords, vec2 dx, vec2 dy, vec3 view_direction) {
+                float layer_depth = 1.0 / float(material.parallax_layers);
+                vec2 layer_offset = view_direction.xy / max(view_direction.z, 0.05) * material.depth_scale * layer_depth;
+
+                vec2 current = tex_coords;
+                float current_layer = 0.0;
+                float depth = 1.0 - textureGrad(material.height, current, dx, dy).r;
+                for (int i = 0; i < material.parallax_layers && current_layer < depth; i++) {
+                    current -= layer_offset;
+                    depth = 1.0 - textureGrad(material.height, current, dx, dy).r;
+                    current_layer += layer_depth;
+                }
 
-        // todo: use a macro or something to simplify this redundant code
+                // interpolates between the layers before and after the ray went under the surface
+                vec2 previous = current + layer_offset;
+                float after = depth - current_layer;
+                float before = 1.0 - textureGrad(material.height, previous, dx, dy).r - current_layer + layer_depth;
+                return mix(current, previous, after / (after - before));
+            }
+
+            // how much light gets to the parallax mapped point, the ray goes up towards the light
+            // and the height map shadows it where it's above the ray
+            float get_parallax_shadow(vec2 tex_coords, vec2 dx, vec2 dy, vec3 light_direction) {
+                // the surface faces away from the light anyway
+                if (light_direction.z <= 0.0) {
+                    return 1.0;
+                }
+
+                float depth = 1.0 - textureGrad(material.height, tex_coords, dx, dy).r;
+                int layers = max(material.parallax_layers / 2, 1);
+                float layer_depth = depth / float(layers);
+                vec2 layer_offset = light_direction.xy / light_direction.z * material.depth_scale * layer_depth;
+
+                vec2 current = tex_coords + layer_offset;
+                float current_layer = depth - layer_depth;
+                float shadow = 0.0;
+                for (int i = 1; i < layers && current_layer > 0.0; i++) {
+                    float surface = 1.0 - textureGrad(material.height, current, dx, dy).r;
+                    // soft shadows, the occluders close to the point are darker
+                    shadow = max(shadow, (current_layer - surface) * (1.0 - float(i) / float(layers)));
+                    current += layer_offset;
+                    current_layer -= layer_depth;
+                }
+                return 1.0 - clamp(shadow * 4.0, 0.0, 1.0);
+            }
+"#;
+
+// the point light clusters of the forward programs
+const CLUSTER_SHADER_SRC: &str = r#"
+            // the point lights are split into clusters on the cpu, see LightClusters
+            #define CLUSTER_COUNT_X 16
+            #define CLUSTER_COUNT_Y 9
+            #define CLUSTER_COUNT_Z 24
+            uniform bool clustered_lighting;
+            uniform usamplerBuffer cluster_grid;
+            uniform usamplerBuffer cluster_light_indices;
+            uniform mat4 cluster_view;
+            uniform mat4 cluster_projection;
+            uniform float cluster_near_plane;
+            uniform float cluster_far_plane;
+
+            int get_cluster_index(vec3 frag_pos) {
+                vec4 view_position = cluster_view * vec4(frag_pos, 1.0);
+                vec4 clip_position = cluster_projection * view_position;
+                vec2 screen_position = (clip_position.xy / clip_position.w) * 0.5 + 0.5;
+                float depth = -view_position.z;
+
+                ivec3 cluster = ivec3(
+                    int(floor(screen_position.x * CLUSTER_COUNT_X)),
+                    int(floor(screen_position.y * CLUSTER_COUNT_Y)),
+                    int(floor(log(depth / cluster_near_plane) / log(cluster_far_plane / cluster_near_plane) * CLUSTER_COUNT_Z))
+                );
+                cluster = clamp(cluster, ivec3(0), ivec3(CLUSTER_COUNT_X - 1, CLUSTER_COUNT_Y - 1, CLUSTER_COUNT_Z - 1));
+                return cluster.x + CLUSTER_COUNT_X * (cluster.y + CLUSTER_COUNT_Y * cluster.z);
+            }
+"#;
+
+pub struct SimpleTexturedObjectProgram(pub Program);
+
+// there's one cubemap per shadow slot, see NR_POINT_SHADOW_MAPS
+const POINT_SHADOW_MAP_NAMES: [&str; 4] = [
+    "point_shadow_maps[0]",
+    "point_shadow_maps[1]",
+    "point_shadow_maps[2]",
+    "point_shadow_maps[3]",
+];
+
+// the same for the spot light slots, see NR_SPOT_SHADOW_MAPS
+const SPOT_LIGHT_SPACE_MATRIX_NAMES: [&str; 2] = [
+    "spot_light_space_matrices[0]",
+    "spot_light_space_matrices[1]",
+];
+const SPOT_SHADOW_MAP_NAMES: [&str; 2] = ["spot_shadow_maps[0]", "spot_shadow_maps[1]"];
+const SPOT_SHADOW_ENABLED_NAMES: [&str; 2] = ["spot_shadow_enabled[0]", "spot_shadow_enabled[1]"];
+const SPOT_COOKIE_ENABLED_NAMES: [&str; 2] = ["spot_cookie_enabled[0]", "spot_cookie_enabled[1]"];
+const SPOT_COOKIE_NAMES: [&str; 2] = ["spot_cookies[0]", "spot_cookies[1]"];
+
+// the shadow maps and spot light cookie used by LIGHTING_SHADER_SRC
+fn visit_shadow_map_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    shadow_maps: &'a ShadowMaps,
+    f: &mut F,
+) {
+    let shadow_map = &shadow_maps.directional;
+    f(
+        "light_space_matrix",
+        UniformValue::Mat4(shadow_map.light_space_matrix.to_array()),
+    );
+    f(
+        "shadow_map",
+        UniformValue::DepthTexture2d(&shadow_map.depth_texture, Some(shadow_map.get_sampler())),
+    );
+    f(
+        "shadow_filter_size",
+        UniformValue::SignedInt(shadow_map.filter_size),
+    );
+
+    let point_shadow_maps = &shadow_maps.point;
+    let point_shadow_sampler = point_shadow_maps.get_sampler();
+    for (name, cubemap) in POINT_SHADOW_MAP_NAMES.iter().zip(point_shadow_maps.cubemaps.iter()) {
         f(
-            "material.diffuse",
-            UniformValue::SrgbTexture2d(&self.material.diffuse, None),
+            name,
+            UniformValue::DepthCubemap(cubemap, Some(point_shadow_sampler)),
         );
+    }
+    f(
+        "point_shadow_near_plane",
+        UniformValue::Float(point_shadow_maps.near_plane),
+    );
+    f(
+        "point_shadow_far_plane",
+        UniformValue::Float(point_shadow_maps.far_plane),
+    );
+    f(
+        "point_shadow_filter_radius",
+        UniformValue::Float(point_shadow_maps.filter_radius),
+    );
+
+    let spot_shadow_maps = &shadow_maps.spot;
+    let spot_shadow_sampler = spot_shadow_maps.get_sampler();
+    let spot_cookie_sampler = spot_shadow_maps.get_cookie_sampler();
+    for (slot, depth_texture) in spot_shadow_maps.depth_textures.iter().enumerate() {
         f(
-            "material.specular",
-            UniformValue::SrgbTexture2d(&self.material.specular, None),
+            SPOT_LIGHT_SPACE_MATRIX_NAMES[slot],
+            UniformValue::Mat4(spot_shadow_maps.light_space_matrices[slot].to_array()),
         );
         f(
-            "material.shininess",
-            UniformValue::Float(self.material.shininess),
+            SPOT_SHADOW_MAP_NAMES[slot],
+            UniformValue::DepthTexture2d(depth_texture, Some(spot_shadow_sampler)),
         );
-
         f(
-            "directional_light.direction",
-            UniformValue::Vec3(self.directional_light.direction.into()),
+            SPOT_SHADOW_ENABLED_NAMES[slot],
+            UniformValue::Bool(spot_shadow_maps.shadows[slot]),
         );
+        let cookie = spot_shadow_maps.cookies[slot].as_deref();
         f(
-            "directional_light.ambient",
-            UniformValue::Vec3(self.directional_light.ambient.into()),
+            SPOT_COOKIE_ENABLED_NAMES[slot],
+            UniformValue::Bool(cookie.is_some()),
         );
         f(
-            "directional_light.diffuse",
-            UniformValue::Vec3(self.directional_light.diffuse.into()),
+            SPOT_COOKIE_NAMES[slot],
+            UniformValue::Texture2d(
+                cookie.unwrap_or(&spot_shadow_maps.empty_cookie),
+                Some(spot_cookie_sampler),
+            ),
         );
+    }
+    f(
+        "spot_shadow_filter_size",
+        UniformValue::SignedInt(spot_shadow_maps.filter_size),
+    );
+}
+
+// the textures and parallax mapping of MATERIAL_SHADER_SRC
+fn visit_material_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    material: &'a Material,
+    f: &mut F,
+) {
+    f(
+        "material.diffuse",
+        UniformValue::SrgbTexture2d(&material.diffuse, None),
+    );
+    f(
+        "material.specular",
+        UniformValue::SrgbTexture2d(&material.specular, None),
+    );
+    f(
+        "material.normal",
+        UniformValue::Texture2d(&material.normal, None),
+    );
+    f("material.shininess", UniformValue::Float(material.shininess));
+    f(
+        "material.emissive",
+        UniformValue::SrgbTexture2d(&material.emissive, None),
+    );
+    f(
+        "material.emissive_factor",
+        UniformValue::Vec3(material.emissive_factor.into()),
+    );
+
+    f(
+        "material.parallax",
+        UniformValue::Bool(material.parallax.is_some()),
+    );
+    let height = match &material.parallax {
+        Some(parallax) => &parallax.height,
+        None => &material.empty_height,
+    };
+    f("material.height", UniformValue::Texture2d(height, None));
+    if let Some(parallax) = &material.parallax {
         f(
-            "directional_light.specular",
-            UniformValue::Vec3(self.directional_light.specular.into()),
+            "material.depth_scale",
+            UniformValue::Float(parallax.depth_scale),
         );
-
         f(
-            "spot_light.position",
-            UniformValue::Vec3(self.spot_light.position.into()),
+            "material.parallax_layers",
+            UniformValue::SignedInt(parallax.layers),
         );
+    }
+
+    // the modes are numbered like in ENVIRONMENT_MAPPING_SHADER_SRC
+    let environment_mode = match &material.environment {
+        None => 0,
+        Some(environment) => match environment.mode {
+            EnvironmentMappingMode::Reflective => 1,
+            EnvironmentMappingMode::Refractive => 2,
+        },
+    };
+    f(
+        "material.environment_mode",
+        UniformValue::SignedInt(environment_mode),
+    );
+    if let Some(environment) = &material.environment {
         f(
-            "spot_light.direction",
-            UniformValue::Vec3(self.spot_light.direction.into()),
+            "material.reflectivity",
+            UniformValue::Float(environment.reflectivity),
         );
         f(
-            "spot_light.cut_off",
-            UniformValue::Float(self.spot_light.cut_off),
+            "material.reflectivity_from_specular",
+            UniformValue::Bool(environment.reflectivity_from_specular),
         );
+        f("material.ior", UniformValue::Float(environment.ior));
         f(
-            "spot_light.outer_cut_off",
-            UniformValue::Float(self.spot_light.outer_cut_off),
+            "material.environment_tint",
+            UniformValue::Vec3(environment.tint.into()),
         );
+    }
+}
+
+// the sky seen in the surfaces of MATERIAL_SHADER_SRC, only the forward program does it
+const ENVIRONMENT_MAPPING_SHADER_SRC: &str = r#"
+            const int ENVIRONMENT_NONE = 0;
+            const int ENVIRONMENT_REFLECTIVE = 1;
+            const int ENVIRONMENT_REFRACTIVE = 2;
+
+            uniform samplerCube environment_map;
+            // the reflection probes are drawn from a point in the scene, so the directions go
+            // from where they hit the box around the probe instead of from the surface
+            uniform bool environment_box_projection;
+            uniform vec3 environment_position;
+            uniform vec3 environment_box_min;
+            uniform vec3 environment_box_max;
+
+            vec3 get_environment_direction(vec3 direction, vec3 frag_pos) {
+                if (!environment_box_projection) {
+                    return direction;
+                }
+                vec3 to_max = (environment_box_max - frag_pos) / direction;
+                vec3 to_min = (environment_box_min - frag_pos) / direction;
+                vec3 furthest = max(to_max, to_min);
+                float distance = min(min(furthest.x, furthest.y), furthest.z);
+                return frag_pos + direction * distance - environment_position;
+            }
+
+            float fresnel_schlick_reflectance(float cos_theta, float f0) {
+                return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+            }
+
+            vec3 apply_environment_mapping(vec3 color, vec3 frag_pos, vec3 normal, vec3 view_direction, vec3 tex_specular) {
+                if (material.environment_mode == ENVIRONMENT_NONE) {
+                    return color;
+                }
+                float reflectivity = material.reflectivity;
+                if (material.reflectivity_from_specular) {
+                    reflectivity *= max(tex_specular.r, max(tex_specular.g, tex_specular.b));
+                }
+
+                float cos_theta = max(dot(normal, view_direction), 0.0);
+                vec3 reflected = texture(environment_map, get_environment_direction(reflect(-view_direction, normal), frag_pos)).rgb;
+                if (material.environment_mode == ENVIRONMENT_REFLECTIVE) {
+                    // like a metal, the reflectivity is what it reflects facing the view
+                    return mix(color, reflected, fresnel_schlick_reflectance(cos_theta, reflectivity));
+                }
+
+                // the glass reflects more at grazing angles and lets the rest of the light through
+                float f0 = pow((material.ior - 1.0) / (material.ior + 1.0), 2.0);
+                float fresnel = fresnel_schlick_reflectance(cos_theta, f0);
+                vec3 refraction = refract(-view_direction, normal, 1.0 / material.ior);
+                vec3 refracted = texture(environment_map, get_environment_direction(refraction, frag_pos)).rgb;
+                vec3 glass = mix(refracted * material.environment_tint, reflected, fresnel);
+                return mix(color, glass, reflectivity);
+            }
+"#;
+
+// the lights and their clusters used by the forward programs
+fn visit_light_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(lights: &'a LightBuffer, f: &mut F) {
+    f("Lights", lights.get_uniform_value());
+
+    let clusters = &lights.clusters;
+    f("clustered_lighting", UniformValue::Bool(clusters.enabled));
+    f("cluster_grid", clusters.grid.as_uniform_value());
+    f("cluster_light_indices", clusters.light_indices.as_uniform_value());
+    f("cluster_view", UniformValue::Mat4(clusters.view.to_array()));
+    f(
+        "cluster_projection",
+        UniformValue::Mat4(clusters.projection.to_array()),
+    );
+    f("cluster_near_plane", UniformValue::Float(clusters.near_plane));
+    f("cluster_far_plane", UniformValue::Float(clusters.far_plane));
+}
+
+// the sky lighting used by LIGHTING_SHADER_SRC
+fn visit_ibl_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    ibl: &'a ImageBasedLighting,
+    f: &mut F,
+) {
+    f("ibl_enabled", UniformValue::Bool(ibl.enabled));
+    f(
+        "ibl_intensity",
+        UniformValue::Float(ibl.intensity * ibl.sky_brightness),
+    );
+    f(
+        "irradiance_map",
+        UniformValue::Cubemap(&ibl.irradiance, Some(ibl.get_sampler())),
+    );
+    f(
+        "prefiltered_map",
+        UniformValue::Cubemap(&ibl.prefiltered, Some(ibl.get_mipmap_sampler())),
+    );
+    f(
+        "prefiltered_max_lod",
+        UniformValue::Float((PREFILTERED_LEVELS - 1) as f32),
+    );
+    f(
+        "brdf_lut",
+        UniformValue::Texture2d(&ibl.brdf_lut, Some(ibl.get_sampler())),
+    );
+}
+
+// distance and height fog, the camera is a parameter since the skybox doesn't have view_pos
+const FOG_SHADER_SRC: &str = r#"
+            uniform bool fog_enabled;
+            uniform vec3 fog_color;
+            uniform bool fog_color_from_sky;
+            uniform samplerCube fog_sky;
+            uniform float fog_sky_brightness;
+            uniform float fog_brightness;
+            uniform float fog_density;
+            uniform float fog_height_density;
+            uniform float fog_height;
+            uniform float fog_height_falloff;
+
+            // how much of the light between the camera and the position is replaced by the fog
+            float get_fog_amount(vec3 position, vec3 camera) {
+                if (!fog_enabled) {
+                    return 0.0;
+                }
+                vec3 ray = position - camera;
+                float distance = length(ray);
+
+                // the height fog density integrated along the ray
+                float camera_density = fog_height_density * exp(-fog_height_falloff * (camera.y - fog_height));
+                float height_change = fog_height_falloff * ray.y;
+                float height_fog = camera_density * distance;
+                if (abs(height_change) > 0.0001) {
+                    height_fog *= (1.0 - exp(-height_change)) / height_change;
+                }
+                return 1.0 - exp(-(fog_density * distance + height_fog));
+            }
+
+            // both colours follow the day and night cycle
+            vec3 get_fog_color(vec3 direction) {
+                if (fog_color_from_sky) {
+                    // the sky around the horizon, so the fog doesn't turn dark looking down
+                    return texture(fog_sky, normalize(vec3(direction.x, max(direction.y, 0.001), direction.z))).rgb * fog_sky_brightness;
+                }
+                return fog_color * fog_brightness;
+            }
+
+            vec3 apply_fog(vec3 color, vec3 position, vec3 camera) {
+                return mix(color, get_fog_color(normalize(position - camera)), get_fog_amount(position, camera));
+            }
+"#;
+
+// the uniforms of FOG_SHADER_SRC, the colour of the sky is the irradiance map of the ibl
+fn visit_fog_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    fog: &Fog,
+    ibl: &'a ImageBasedLighting,
+    f: &mut F,
+) {
+    f("fog_enabled", UniformValue::Bool(fog.enabled));
+    f("fog_color", UniformValue::Vec3(fog.color.into()));
+    f("fog_color_from_sky", UniformValue::Bool(fog.color_from_sky));
+    f(
+        "fog_sky",
+        UniformValue::Cubemap(&ibl.irradiance, Some(ibl.get_sampler())),
+    );
+    f("fog_sky_brightness", UniformValue::Float(ibl.sky_brightness));
+    f("fog_brightness", UniformValue::Float(fog.brightness));
+    f("fog_density", UniformValue::Float(fog.density));
+    f("fog_height_density", UniformValue::Float(fog.height_density));
+    f("fog_height", UniformValue::Float(fog.height));
+    f("fog_height_falloff", UniformValue::Float(fog.height_falloff));
+}
+
+// the ambient occlusion used by LIGHTING_SHADER_SRC
+fn visit_ssao_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(ssao: &'a Ssao, f: &mut F) {
+    f("ssao_enabled", UniformValue::Bool(ssao.enabled));
+    f(
+        "ssao_texture",
+        UniformValue::Texture2d(ssao.get_texture(), Some(ssao.get_sampler())),
+    );
+}
+
+pub struct SimpleTexturedObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j> {
+    matrix: &'a Matrix4<f32>,
+    model: &'b Matrix4<f32>,
+    material: &'c Material,
+    lights: &'d LightBuffer,
+    view: &'e Point3<f32>,
+    shadow_maps: &'f ShadowMaps,
+    ssao: &'g Ssao,
+    ibl: &'h ImageBasedLighting,
+    fog: &'i Fog,
+    environment: EnvironmentMap<'j>,
+}
+
+impl glium::uniforms::Uniforms
+    for SimpleTexturedObjectUniforms<'_, '_, '_, '_, '_, '_, '_, '_, '_, '_>
+{
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("model", UniformValue::Mat4(self.model.to_array()));
+
+        visit_material_uniforms(self.material, &mut f);
+
+        visit_light_uniforms(self.lights, &mut f);
         f(
-            "spot_light.ambient",
-            UniformValue::Vec3(self.spot_light.ambient.into()),
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
         );
+
+        visit_shadow_map_uniforms(self.shadow_maps, &mut f);
+        visit_ssao_uniforms(self.ssao, &mut f);
+        visit_ibl_uniforms(self.ibl, &mut f);
+        visit_fog_uniforms(self.fog, self.ibl, &mut f);
         f(
-            "spot_light.specular",
-            UniformValue::Vec3(self.spot_light.specular.into()),
+            "environment_map",
+            UniformValue::Cubemap(
+                self.environment.cubemap,
+                Some(SamplerBehavior {
+                    minify_filter: MinifySamplerFilter::Linear,
+                    magnify_filter: MagnifySamplerFilter::Linear,
+                    ..Default::default()
+                }),
+            ),
         );
         f(
-            "spot_light.diffuse",
-            UniformValue::Vec3(self.spot_light.diffuse.into()),
+            "environment_box_projection",
+            UniformValue::Bool(self.environment.box_projection.is_some()),
         );
-
-        for (i, point_light) in self.point_lights.iter().enumerate() {
-            f(
-                &format!("point_lights[{}].position", i),
-                UniformValue::Vec3(point_light.position.into()),
-            );
+        if let Some((position, bounds)) = self.environment.box_projection {
             f(
-                &format!("point_lights[{}].ambient", i),
-                UniformValue::Vec3(point_light.ambient.into()),
+                "environment_position",
+                UniformValue::Vec3(position.into()),
             );
             f(
-                &format!("point_lights[{}].diffuse", i),
-                UniformValue::Vec3(point_light.diffuse.into()),
+                "environment_box_min",
+                UniformValue::Vec3(bounds.min.into()),
             );
             f(
-                &format!("point_lights[{}].specular", i),
-                UniformValue::Vec3(point_light.specular.into()),
-            );
-
-            f(
-                &format!("point_lights[{}].constant", i),
-                UniformValue::Float(point_light.constant),
-            );
-            f(
-                &format!("point_lights[{}].linear", i),
-                UniformValue::Float(point_light.linear),
-            );
-            f(
-                &format!("point_lights[{}].quadratic", i),
-                UniformValue::Float(point_light.quadratic),
+                "environment_box_max",
+                UniformValue::Vec3(bounds.max.into()),
             );
         }
-
-        f(
-            "view",
-            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
-        );
     }
 }
 
-impl SimpleTexturedObjectProgram {
-    pub fn new(display: &Display) -> SimpleTexturedObjectProgram {
-        let vertex_shader_src = r#"
+// shared by the forward programs of the textured objects
+fn get_textured_object_vertex_shader_src() -> String {
+    [
+        r#"
             #version 330 core
     
             in vec3 position;
             in vec3 normal;
             in vec2 tex_coords;
+            in vec4 tangent;
     
             out vec2 v_tex_coords;
             out vec3 v_frag_pos;
-            out vec3 v_normal;
+            out mat3 v_tbn;
+            out vec3 v_tangent_view_direction;
+            out vec4 v_frag_pos_light_space;
     
             uniform mat4 matrix;
             uniform mat4 model;
-    
+            uniform mat4 light_space_matrix;
+            uniform vec3 view_pos;
+            "#,
+        TANGENT_SPACE_SHADER_SRC,
+        r#"
             void main() {
                 gl_Position = matrix * vec4(position, 1.0);
     
                 v_tex_coords = tex_coords;
-                v_normal = normal;
+                v_tbn = get_tbn(model, normal, tangent);
                 v_frag_pos = vec3(model * vec4(position, 1.0));
+                v_tangent_view_direction = transpose(v_tbn) * (view_pos - v_frag_pos);
+                v_frag_pos_light_space = light_space_matrix * vec4(v_frag_pos, 1.0);
             }
-        "#;
-        let fragment_shader_src = r#"
+        "#,
+    ]
+    .concat()
+}
+
+impl SimpleTexturedObjectProgram {
+    pub fn new(display: &Display) -> SimpleTexturedObjectProgram {
+        let vertex_shader_src = get_textured_object_vertex_shader_src();
+        let fragment_shader_src = [
+            r#"
             #version 330 core
     
             in vec2 v_tex_coords;
-            in vec3 v_normal;
+            in mat3 v_tbn;
+            in vec3 v_tangent_view_direction;
             in vec3 v_frag_pos;
+            in vec4 v_frag_pos_light_space;
     
             out vec4 out_color;
+            "#,
+            MATERIAL_SHADER_SRC,
+            ENVIRONMENT_MAPPING_SHADER_SRC,
+            TANGENT_SPACE_SHADER_SRC,
+            LIGHTING_SHADER_SRC,
+            FOG_SHADER_SRC,
+            CLUSTER_SHADER_SRC,
+            r#"
+            // the parallax self shadowing towards a world space direction
+            float get_parallax_light(vec2 tex_coords, vec2 dx, vec2 dy, vec3 light_direction) {
+                if (!material.parallax) {
+                    return 1.0;
+                }
+                return get_parallax_shadow(tex_coords, dx, dy, transpose(v_tbn) * light_direction);
+            }
+    
+            void main() {
+                vec2 dx = dFdx(v_tex_coords);
+                vec2 dy = dFdy(v_tex_coords);
+                ambient_occlusion = get_ambient_occlusion();
 
-            struct Material {
-                sampler2D diffuse;
-                sampler2D specular;
-                float shininess;
-            };
-            uniform Material material;
+                // properties
+                vec2 tex_coords = v_tex_coords;
+                if (material.parallax) {
+                    tex_coords = get_parallax_tex_coords(v_tex_coords, dx, dy, normalize(v_tangent_view_direction));
+                }
+                vec3 norm = get_mapped_normal(v_tbn, material.normal, tex_coords);
+                vec3 view_direction = normalize(view_pos - v_frag_pos);
 
-            struct DirectionalLight {
-                vec3 direction;
-                vec3 ambient;
-                vec3 diffuse;
-                vec3 specular;
-            };
-            uniform DirectionalLight directional_light;
+                vec3 tex_diffuse = vec3(texture(material.diffuse, tex_coords));
+                vec3 tex_specular = vec3(texture(material.specular, tex_coords));
 
-            struct SpotLight {
-                vec3 position;
-                vec3 direction;
-                float cut_off;
-                float outer_cut_off;
+                vec3 result = vec3(0.0);
+                for (int i = 0; i < directional_light_count; i++) {
+                    float shadow = get_parallax_light(tex_coords, dx, dy, -directional_lights[i].direction);
+                    if (directional_lights[i].shadow_index >= 0) {
+                        shadow *= calculate_directional_shadow(v_frag_pos_light_space);
+                    }
+                    result += calculate_directional_light(directional_lights[i], norm, view_direction, tex_diffuse, tex_specular, material.shininess, shadow);
+                }
 
-                vec3 ambient;
-                vec3 diffuse;
-                vec3 specular;
+                // only the point lights that reach this cluster
+                int first_light = 0;
+                int light_count = point_light_count;
+                if (clustered_lighting) {
+                    uvec2 cluster_lights = texelFetch(cluster_grid, get_cluster_index(v_frag_pos)).xy;
+                    first_light = int(cluster_lights.x);
+                    light_count = int(cluster_lights.y);
+                }
+                for (int j = 0; j < light_count; j++) {
+                    int i = j;
+                    if (clustered_lighting) {
+                        i = int(texelFetch(cluster_light_indices, first_light + j).r);
+                    }
+                    float point_shadow = calculate_point_shadow(point_lights[i].shadow_index, point_lights[i].position, v_frag_pos, norm);
+                    point_shadow *= get_parallax_light(tex_coords, dx, dy, point_lights[i].position - v_frag_pos);
+                    result += calculate_point_light(point_lights[i], norm, v_frag_pos, view_direction, tex_diffuse, tex_specular, material.shininess, point_shadow);
+                }
+
+                for (int i = 0; i < spot_light_count; i++) {
+                    vec3 spot_projection = vec3(get_parallax_light(tex_coords, dx, dy, spot_lights[i].position - v_frag_pos));
+                    spot_projection *= calculate_spot_projection(spot_lights[i].shadow_index, v_frag_pos);
+                    result += calculate_spot_light(spot_lights[i], norm, v_frag_pos, view_direction, tex_diffuse, tex_specular, material.shininess, spot_projection);
+                }
+
+                result += calculate_phong_ibl(norm, view_direction, tex_diffuse, tex_specular, material.shininess);
+                result = apply_environment_mapping(result, v_frag_pos, norm, view_direction, tex_specular);
+                result += get_emission(tex_coords);
+                result = apply_fog(result, v_frag_pos, view_pos);
+
+                out_color = vec4(result, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+        SimpleTexturedObjectProgram(
+            Program::from_source(display, &vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j>(
+        matrix: &'a Matrix4<f32>,
+        model: &'b Matrix4<f32>,
+        material: &'c Material,
+        lights: &'d LightBuffer,
+        view: &'e Point3<f32>,
+        shadow_maps: &'f ShadowMaps,
+        ssao: &'g Ssao,
+        ibl: &'h ImageBasedLighting,
+        fog: &'i Fog,
+        environment: EnvironmentMap<'j>,
+    ) -> SimpleTexturedObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j> {
+        SimpleTexturedObjectUniforms {
+            matrix,
+            model,
+            material,
+            lights,
+            view,
+            shadow_maps,
+            ssao,
+            ibl,
+            fog,
+            environment,
+        }
+    }
+}
+
+// the metallic/roughness material of PbrObjectProgram and PbrGBufferProgram
+const PBR_MATERIAL_SHADER_SRC: &str = r#"
+            struct PbrMaterial {
+                sampler2D base_color;
+                sampler2D metallic_roughness;
+                sampler2D normal;
+                sampler2D ambient_occlusion;
+                sampler2D emissive;
+                vec3 base_color_factor;
+                float metallic_factor;
+                float roughness_factor;
+                vec3 emissive_factor;
             };
-            uniform SpotLight spot_light;
+            uniform PbrMaterial material;
+
+            struct PbrSurface {
+                vec3 albedo;
+                float metallic;
+                float roughness;
+                float ambient_occlusion;
+                vec3 emissive;
+            };
+
+            PbrSurface get_pbr_surface(vec2 tex_coords) {
+                vec3 metallic_roughness = texture(material.metallic_roughness, tex_coords).rgb;
+
+                PbrSurface surface;
+                surface.albedo = texture(material.base_color, tex_coords).rgb * material.base_color_factor;
+                surface.metallic = clamp(metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+                // the highlights of perfectly smooth surfaces are too small to see
+                surface.roughness = clamp(metallic_roughness.g * material.roughness_factor, 0.04, 1.0);
+                surface.ambient_occlusion = texture(material.ambient_occlusion, tex_coords).r;
+                surface.emissive = texture(material.emissive, tex_coords).rgb * material.emissive_factor;
+                return surface;
+            }
+"#;
+
+// the microfacet distribution of the pbr lighting and of the image based lighting
+const GGX_SHADER_SRC: &str = r#"
+            const float PI = 3.14159265359;
+
+            // how many microfacets face the halfway vector
+            float distribution_ggx(vec3 normal, vec3 halfway, float roughness) {
+                float a = roughness * roughness;
+                float a2 = a * a;
+                float n_dot_h = max(dot(normal, halfway), 0.0);
+                float denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+                return a2 / (PI * denominator * denominator);
+            }
+"#;
+
+// cook-torrance lighting, uses the lights and shadows of LIGHTING_SHADER_SRC and GGX_SHADER_SRC
+const PBR_LIGHTING_SHADER_SRC: &str = r#"
+            // how much the microfacets hide each other from the view and the light
+            float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+                float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+                float ggx_view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+                float ggx_light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+                return ggx_view * ggx_light;
+            }
+
+            vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+                return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+            }
+
+            // light_direction points towards the light
+            vec3 calculate_pbr_radiance(PbrSurface surface, vec3 normal, vec3 view_direction, vec3 light_direction, vec3 radiance) {
+                vec3 halfway = normalize(view_direction + light_direction);
+                float n_dot_v = max(dot(normal, view_direction), 0.0001);
+                float n_dot_l = max(dot(normal, light_direction), 0.0);
+
+                // dielectrics reflect 4% of the light head on, metals reflect their colour
+                vec3 f0 = mix(vec3(0.04), surface.albedo, surface.metallic);
+                vec3 fresnel = fresnel_schlick(max(dot(halfway, view_direction), 0.0), f0);
+                float distribution = distribution_ggx(normal, halfway, surface.roughness);
+                float geometry = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+                vec3 specular = distribution * geometry * fresnel / (4.0 * n_dot_v * n_dot_l + 0.0001);
+
+                // the light that isn't reflected goes into the surface, metals absorb all of it
+                vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - surface.metallic) * surface.albedo / PI;
+
+                // the lights were set up for phong, where a white surface facing the light
+                // reflects all of it, hence the pi
+                return (diffuse + specular) * radiance * PI * n_dot_l;
+            }
+
+            vec3 calculate_pbr_ambient(PbrSurface surface, vec3 ambient) {
+                return get_light_ambient(ambient) * surface.albedo * surface.ambient_occlusion * ambient_occlusion;
+            }
+
+            vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+                return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+            }
+
+            // split sum approximation, the prefiltered sky times the scale and bias from the lut
+            vec3 calculate_pbr_ibl(PbrSurface surface, vec3 normal, vec3 view_direction) {
+                if (!ibl_enabled) {
+                    return vec3(0.0);
+                }
+                float n_dot_v = max(dot(normal, view_direction), 0.0);
+                vec3 f0 = mix(vec3(0.04), surface.albedo, surface.metallic);
+                vec3 fresnel = fresnel_schlick_roughness(n_dot_v, f0, surface.roughness);
+
+                vec3 irradiance = texture(irradiance_map, normal).rgb;
+                vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - surface.metallic) * surface.albedo * irradiance;
+
+                vec3 prefiltered = textureLod(prefiltered_map, reflect(-view_direction, normal), surface.roughness * prefiltered_max_lod).rgb;
+                vec2 brdf = texture(brdf_lut, vec2(n_dot_v, surface.roughness)).rg;
+                vec3 specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+                return (diffuse + specular) * ibl_intensity * surface.ambient_occlusion * ambient_occlusion;
+            }
+
+            // the specular colour of the lights is only for phong, the brdf decides the highlights
+            vec3 calculate_pbr_directional_light(DirectionalLight light, PbrSurface surface, vec3 normal, vec3 view_direction, float shadow) {
+                vec3 light_direction = normalize(-light.direction);
+                return calculate_pbr_ambient(surface, light.ambient)
+                    + calculate_pbr_radiance(surface, normal, view_direction, light_direction, light.diffuse * shadow);
+            }
+
+            vec3 calculate_pbr_spot_light(SpotLight light, PbrSurface surface, vec3 normal, vec3 frag_pos, vec3 view_direction, vec3 projection) {
+                vec3 light_direction = normalize(light.position - frag_pos);
+
+                float theta = dot(light_direction, normalize(-light.direction));
+                float epsilon = light.cut_off - light.outer_cut_off;
+                float intensity = clamp((theta - light.outer_cut_off) / epsilon, 0.0, 1.0);
+
+                float distance = length(light.position - frag_pos);
+                float attenuation = 1.0 / (light.constant + light.linear * distance +
+                                    light.quadratic * (distance * distance));
+
+                vec3 radiance = light.diffuse * intensity * attenuation * projection;
+                return calculate_pbr_ambient(surface, light.ambient * attenuation)
+                    + calculate_pbr_radiance(surface, normal, view_direction, light_direction, radiance);
+            }
+
+            vec3 calculate_pbr_point_light(PointLight light, PbrSurface surface, vec3 normal, vec3 frag_pos, vec3 view_direction, float shadow) {
+                vec3 light_direction = normalize(light.position - frag_pos);
+
+                float distance = length(light.position - frag_pos);
+                float attenuation = 1.0 / (light.constant + light.linear * distance +
+                                    light.quadratic * (distance * distance));
+
+                vec3 radiance = light.diffuse * attenuation * shadow;
+                return calculate_pbr_ambient(surface, light.ambient * attenuation)
+                    + calculate_pbr_radiance(surface, normal, view_direction, light_direction, radiance);
+            }
+"#;
+
+fn visit_pbr_material_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    material: &'a PbrMaterial,
+    f: &mut F,
+) {
+    f(
+        "material.base_color",
+        UniformValue::SrgbTexture2d(&material.base_color, None),
+    );
+    f(
+        "material.metallic_roughness",
+        UniformValue::Texture2d(&material.metallic_roughness, None),
+    );
+    f(
+        "material.normal",
+        UniformValue::Texture2d(&material.normal, None),
+    );
+    f(
+        "material.ambient_occlusion",
+        UniformValue::Texture2d(&material.ambient_occlusion, None),
+    );
+    f(
+        "material.emissive",
+        UniformValue::SrgbTexture2d(&material.emissive, None),
+    );
+    f(
+        "material.base_color_factor",
+        UniformValue::Vec3(material.base_color_factor.into()),
+    );
+    f(
+        "material.metallic_factor",
+        UniformValue::Float(material.metallic_factor),
+    );
+    f(
+        "material.roughness_factor",
+        UniformValue::Float(material.roughness_factor),
+    );
+    f(
+        "material.emissive_factor",
+        UniformValue::Vec3(material.emissive_factor.into()),
+    );
+}
+
+// physically based alternative to SimpleTexturedObjectProgram for the objects with a PbrMaterial
+pub struct PbrObjectProgram(pub Program);
+
+pub struct PbrObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i> {
+    matrix: &'a Matrix4<f32>,
+    model: &'b Matrix4<f32>,
+    material: &'c PbrMaterial,
+    lights: &'d LightBuffer,
+    view: &'e Point3<f32>,
+    shadow_maps: &'f ShadowMaps,
+    ssao: &'g Ssao,
+    ibl: &'h ImageBasedLighting,
+    fog: &'i Fog,
+}
+
+impl glium::uniforms::Uniforms for PbrObjectUniforms<'_, '_, '_, '_, '_, '_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("model", UniformValue::Mat4(self.model.to_array()));
+
+        visit_pbr_material_uniforms(self.material, &mut f);
+
+        visit_light_uniforms(self.lights, &mut f);
+        f(
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
+        );
+
+        visit_shadow_map_uniforms(self.shadow_maps, &mut f);
+        visit_ssao_uniforms(self.ssao, &mut f);
+        visit_ibl_uniforms(self.ibl, &mut f);
+        visit_fog_uniforms(self.fog, self.ibl, &mut f);
+    }
+}
+
+impl PbrObjectProgram {
+    pub fn new(display: &Display) -> PbrObjectProgram {
+        let vertex_shader_src = get_textured_object_vertex_shader_src();
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
     
-            struct PointLight {
-                vec3 position;
-                vec3 ambient;
-                vec3 diffuse;
-                vec3 specular;
+            in vec2 v_tex_coords;
+            in mat3 v_tbn;
+            in vec3 v_frag_pos;
+            in vec4 v_frag_pos_light_space;
+    
+            out vec4 out_color;
+            "#,
+            PBR_MATERIAL_SHADER_SRC,
+            TANGENT_SPACE_SHADER_SRC,
+            LIGHTING_SHADER_SRC,
+            FOG_SHADER_SRC,
+            GGX_SHADER_SRC,
+            PBR_LIGHTING_SHADER_SRC,
+            CLUSTER_SHADER_SRC,
+            r#"
+            void main() {
+                ambient_occlusion = get_ambient_occlusion();
+
+                PbrSurface surface = get_pbr_surface(v_tex_coords);
+                vec3 norm = get_mapped_normal(v_tbn, material.normal, v_tex_coords);
+                vec3 view_direction = normalize(view_pos - v_frag_pos);
+
+                vec3 result = surface.emissive;
+                for (int i = 0; i < directional_light_count; i++) {
+                    float shadow = 1.0;
+                    if (directional_lights[i].shadow_index >= 0) {
+                        shadow = calculate_directional_shadow(v_frag_pos_light_space);
+                    }
+                    result += calculate_pbr_directional_light(directional_lights[i], surface, norm, view_direction, shadow);
+                }
+
+                // only the point lights that reach this cluster
+                int first_light = 0;
+                int light_count = point_light_count;
+                if (clustered_lighting) {
+                    uvec2 cluster_lights = texelFetch(cluster_grid, get_cluster_index(v_frag_pos)).xy;
+                    first_light = int(cluster_lights.x);
+                    light_count = int(cluster_lights.y);
+                }
+                for (int j = 0; j < light_count; j++) {
+                    int i = j;
+                    if (clustered_lighting) {
+                        i = int(texelFetch(cluster_light_indices, first_light + j).r);
+                    }
+                    float point_shadow = calculate_point_shadow(point_lights[i].shadow_index, point_lights[i].position, v_frag_pos, norm);
+                    result += calculate_pbr_point_light(point_lights[i], surface, norm, v_frag_pos, view_direction, point_shadow);
+                }
+
+                for (int i = 0; i < spot_light_count; i++) {
+                    vec3 spot_projection = calculate_spot_projection(spot_lights[i].shadow_index, v_frag_pos);
+                    result += calculate_pbr_spot_light(spot_lights[i], surface, norm, v_frag_pos, view_direction, spot_projection);
+                }
+
+                result += calculate_pbr_ibl(surface, norm, view_direction);
+                result = apply_fog(result, v_frag_pos, view_pos);
+
+                out_color = vec4(result, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+        PbrObjectProgram(
+            Program::from_source(display, &vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i>(
+        matrix: &'a Matrix4<f32>,
+        model: &'b Matrix4<f32>,
+        material: &'c PbrMaterial,
+        lights: &'d LightBuffer,
+        view: &'e Point3<f32>,
+        shadow_maps: &'f ShadowMaps,
+        ssao: &'g Ssao,
+        ibl: &'h ImageBasedLighting,
+        fog: &'i Fog,
+    ) -> PbrObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i> {
+        PbrObjectUniforms {
+            matrix,
+            model,
+            material,
+            lights,
+            view,
+            shadow_maps,
+            ssao,
+            ibl,
+            fog,
+        }
+    }
+}
+
+// geometry pass of the deferred renderer, writes the surface properties into the gbuffer
+pub struct GBufferProgram(pub Program);
+
+pub struct GBufferUniforms<'a, 'b, 'c, 'd> {
+    matrix: &'a Matrix4<f32>,
+    model: &'b Matrix4<f32>,
+    material: &'c Material,
+    view: &'d Point3<f32>,
+}
+
+impl glium::uniforms::Uniforms for GBufferUniforms<'_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("model", UniformValue::Mat4(self.model.to_array()));
+        f(
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
+        );
+
+        visit_material_uniforms(self.material, &mut f);
+    }
+}
+
+impl GBufferProgram {
+    pub fn new(display: &Display) -> GBufferProgram {
+        let vertex_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec3 position;
+            in vec3 normal;
+            in vec2 tex_coords;
+            in vec4 tangent;
+
+            out vec2 v_tex_coords;
+            out mat3 v_tbn;
+            out vec3 v_tangent_view_direction;
+
+            uniform mat4 matrix;
+            uniform mat4 model;
+            uniform vec3 view_pos;
+            "#,
+            TANGENT_SPACE_SHADER_SRC,
+            r#"
+            void main() {
+                gl_Position = matrix * vec4(position, 1.0);
+
+                v_tex_coords = tex_coords;
+                v_tbn = get_tbn(model, normal, tangent);
+                v_tangent_view_direction = transpose(v_tbn) * (view_pos - vec3(model * vec4(position, 1.0)));
+            }
+        "#,
+        ]
+        .concat();
+
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+            in mat3 v_tbn;
+            in vec3 v_tangent_view_direction;
+
+            out vec4 g_albedo;
+            out vec4 g_specular;
+            out vec4 g_normal;
+            out vec4 g_emissive;
+            "#,
+            MATERIAL_SHADER_SRC,
+            TANGENT_SPACE_SHADER_SRC,
+            r#"
+            // the self shadowing needs the direction of every light, so only the forward
+            // renderer has it
+            void main() {
+                vec2 dx = dFdx(v_tex_coords);
+                vec2 dy = dFdy(v_tex_coords);
+                vec2 tex_coords = v_tex_coords;
+                if (material.parallax) {
+                    tex_coords = get_parallax_tex_coords(v_tex_coords, dx, dy, normalize(v_tangent_view_direction));
+                }
+                g_albedo = vec4(texture(material.diffuse, tex_coords).rgb, 1.0);
+                g_specular = vec4(texture(material.specular, tex_coords).rgb, 1.0);
+                vec3 normal = get_mapped_normal(v_tbn, material.normal, tex_coords);
+                g_normal = vec4(normal, material.shininess);
+                g_emissive = vec4(get_emission(tex_coords), 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        GBufferProgram(
+            Program::from_source(display, &vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c, 'd>(
+        matrix: &'a Matrix4<f32>,
+        model: &'b Matrix4<f32>,
+        material: &'c Material,
+        view: &'d Point3<f32>,
+    ) -> GBufferUniforms<'a, 'b, 'c, 'd> {
+        GBufferUniforms {
+            matrix,
+            model,
+            material,
+            view,
+        }
+    }
+}
+
+// geometry pass for the objects with a PbrMaterial, only the depth and normals are used by the ssao,
+// the deferred lighting is phong so the objects are drawn again with PbrObjectProgram after it
+pub struct PbrGBufferProgram(pub Program);
+
+pub struct PbrGBufferUniforms<'a, 'b, 'c> {
+    matrix: &'a Matrix4<f32>,
+    model: &'b Matrix4<f32>,
+    material: &'c PbrMaterial,
+}
+
+impl glium::uniforms::Uniforms for PbrGBufferUniforms<'_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("model", UniformValue::Mat4(self.model.to_array()));
+
+        visit_pbr_material_uniforms(self.material, &mut f);
+    }
+}
+
+impl PbrGBufferProgram {
+    pub fn new(display: &Display) -> PbrGBufferProgram {
+        let vertex_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec3 position;
+            in vec3 normal;
+            in vec2 tex_coords;
+            in vec4 tangent;
+
+            out vec2 v_tex_coords;
+            out mat3 v_tbn;
+
+            uniform mat4 matrix;
+            uniform mat4 model;
+            "#,
+            TANGENT_SPACE_SHADER_SRC,
+            r#"
+            void main() {
+                gl_Position = matrix * vec4(position, 1.0);
+
+                v_tex_coords = tex_coords;
+                v_tbn = get_tbn(model, normal, tangent);
+            }
+        "#,
+        ]
+        .concat();
+
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+            in mat3 v_tbn;
+
+            out vec4 g_albedo;
+            out vec4 g_specular;
+            out vec4 g_normal;
+            out vec4 g_emissive;
+            "#,
+            PBR_MATERIAL_SHADER_SRC,
+            TANGENT_SPACE_SHADER_SRC,
+            r#"
+            void main() {
+                g_albedo = vec4(0.0);
+                g_specular = vec4(0.0);
+                vec3 normal = get_mapped_normal(v_tbn, material.normal, v_tex_coords);
+                g_normal = vec4(normal, 1.0);
+                g_emissive = vec4(0.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        PbrGBufferProgram(
+            Program::from_source(display, &vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c>(
+        matrix: &'a Matrix4<f32>,
+        model: &'b Matrix4<f32>,
+        material: &'c PbrMaterial,
+    ) -> PbrGBufferUniforms<'a, 'b, 'c> {
+        PbrGBufferUniforms {
+            matrix,
+            model,
+            material,
+        }
+    }
+}
+
+// reads back what GBufferProgram wrote, the position comes from the depth
+const GBUFFER_SHADER_SRC: &str = r#"
+            uniform sampler2D g_albedo;
+            uniform sampler2D g_specular;
+            uniform sampler2D g_normal;
+            uniform sampler2D g_emissive;
+            uniform sampler2D g_depth;
+            uniform mat4 inverse_projection_view;
+
+            struct GBufferSurface {
+                vec3 position;
+                vec3 normal;
+                vec3 diffuse;
+                vec3 specular;
+                float shininess;
+                vec3 emissive;
+            };
+
+            // false where nothing was drawn
+            bool read_gbuffer(out GBufferSurface surface) {
+                vec2 uv = gl_FragCoord.xy / vec2(textureSize(g_depth, 0));
+                float depth = texture(g_depth, uv).r;
+                if (depth >= 1.0) {
+                    return false;
+                }
+
+                vec4 position = inverse_projection_view * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+                vec4 normal = texture(g_normal, uv);
+                surface.position = position.xyz / position.w;
+                surface.normal = normalize(normal.xyz);
+                surface.diffuse = texture(g_albedo, uv).rgb;
+                surface.specular = texture(g_specular, uv).rgb;
+                surface.shininess = normal.w;
+                surface.emissive = texture(g_emissive, uv).rgb;
+                return true;
+            }
+"#;
+
+fn visit_gbuffer_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(
+    gbuffer: &'a GBuffer,
+    inverse_projection_view: &Matrix4<f32>,
+    view: &Point3<f32>,
+    f: &mut F,
+) {
+    let sampler = gbuffer.get_sampler();
+    f("g_albedo", UniformValue::Texture2d(&gbuffer.albedo, Some(sampler)));
+    f(
+        "g_specular",
+        UniformValue::Texture2d(&gbuffer.specular, Some(sampler)),
+    );
+    f("g_normal", UniformValue::Texture2d(&gbuffer.normal, Some(sampler)));
+    f(
+        "g_emissive",
+        UniformValue::Texture2d(&gbuffer.emissive, Some(sampler)),
+    );
+    f(
+        "g_depth",
+        UniformValue::DepthTexture2d(&gbuffer.depth, Some(sampler)),
+    );
+    f(
+        "inverse_projection_view",
+        UniformValue::Mat4(inverse_projection_view.to_array()),
+    );
+    f("view_pos", UniformValue::Vec3([view.x, view.y, view.z]));
+}
+
+// full screen pass of the deferred renderer for the directional and spot lights
+pub struct DeferredLightingProgram(pub Program);
+
+pub struct DeferredLightingUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h> {
+    gbuffer: &'a GBuffer,
+    inverse_projection_view: &'b Matrix4<f32>,
+    view: &'c Point3<f32>,
+    lights: &'d LightBuffer,
+    shadow_maps: &'e ShadowMaps,
+    ssao: &'f Ssao,
+    ibl: &'g ImageBasedLighting,
+    fog: &'h Fog,
+}
+
+impl glium::uniforms::Uniforms for DeferredLightingUniforms<'_, '_, '_, '_, '_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        visit_gbuffer_uniforms(
+            self.gbuffer,
+            self.inverse_projection_view,
+            self.view,
+            &mut f,
+        );
+        f("Lights", self.lights.get_uniform_value());
+        visit_shadow_map_uniforms(self.shadow_maps, &mut f);
+        visit_ssao_uniforms(self.ssao, &mut f);
+        visit_ibl_uniforms(self.ibl, &mut f);
+        visit_fog_uniforms(self.fog, self.ibl, &mut f);
+    }
+}
+
+impl DeferredLightingProgram {
+    pub fn new(display: &Display) -> DeferredLightingProgram {
+        let vertex_shader_src = r#"
+            #version 330 core
+
+            in vec2 position;
+
+            void main() {
+                gl_Position = vec4(position, 0.0, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            out vec4 out_color;
+
+            uniform mat4 light_space_matrix;
+            "#,
+            LIGHTING_SHADER_SRC,
+            FOG_SHADER_SRC,
+            GBUFFER_SHADER_SRC,
+            r#"
+            void main() {
+                GBufferSurface surface;
+                if (!read_gbuffer(surface)) {
+                    discard;
+                }
+                ambient_occlusion = get_ambient_occlusion();
+                vec3 view_direction = normalize(view_pos - surface.position);
+
+                vec3 result = vec3(0.0);
+                for (int i = 0; i < directional_light_count; i++) {
+                    float shadow = 1.0;
+                    if (directional_lights[i].shadow_index >= 0) {
+                        shadow = calculate_directional_shadow(light_space_matrix * vec4(surface.position, 1.0));
+                    }
+                    result += calculate_directional_light(directional_lights[i], surface.normal, view_direction, surface.diffuse, surface.specular, surface.shininess, shadow);
+                }
+
+                for (int i = 0; i < spot_light_count; i++) {
+                    vec3 spot_projection = calculate_spot_projection(spot_lights[i].shadow_index, surface.position);
+                    result += calculate_spot_light(spot_lights[i], surface.normal, surface.position, view_direction, surface.diffuse, surface.specular, surface.shininess, spot_projection);
+                }
+
+                result += calculate_phong_ibl(surface.normal, view_direction, surface.diffuse, surface.specular, surface.shininess);
+                result += surface.emissive;
+                result = apply_fog(result, surface.position, view_pos);
+
+                out_color = vec4(result, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        DeferredLightingProgram(
+            Program::from_source(display, vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h>(
+        gbuffer: &'a GBuffer,
+        inverse_projection_view: &'b Matrix4<f32>,
+        view: &'c Point3<f32>,
+        lights: &'d LightBuffer,
+        shadow_maps: &'e ShadowMaps,
+        ssao: &'f Ssao,
+        ibl: &'g ImageBasedLighting,
+        fog: &'h Fog,
+    ) -> DeferredLightingUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h> {
+        DeferredLightingUniforms {
+            gbuffer,
+            inverse_projection_view,
+            view,
+            lights,
+            shadow_maps,
+            ssao,
+            ibl,
+            fog,
+        }
+    }
+}
+
+// draws a point light of the deferred renderer with a sphere around it, added on top of the
+// full screen pass
+pub struct DeferredPointLightProgram(pub Program);
+
+pub struct DeferredPointLightUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i> {
+    matrix: &'a Matrix4<f32>,
+    light_index: i32,
+    gbuffer: &'b GBuffer,
+    inverse_projection_view: &'c Matrix4<f32>,
+    view: &'d Point3<f32>,
+    lights: &'e LightBuffer,
+    shadow_maps: &'f ShadowMaps,
+    ssao: &'g Ssao,
+    ibl: &'h ImageBasedLighting,
+    fog: &'i Fog,
+}
+
+impl glium::uniforms::Uniforms
+    for DeferredPointLightUniforms<'_, '_, '_, '_, '_, '_, '_, '_, '_>
+{
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("light_index", UniformValue::SignedInt(self.light_index));
+        visit_gbuffer_uniforms(
+            self.gbuffer,
+            self.inverse_projection_view,
+            self.view,
+            &mut f,
+        );
+        f("Lights", self.lights.get_uniform_value());
+        visit_shadow_map_uniforms(self.shadow_maps, &mut f);
+        visit_ssao_uniforms(self.ssao, &mut f);
+        visit_ibl_uniforms(self.ibl, &mut f);
+        visit_fog_uniforms(self.fog, self.ibl, &mut f);
+    }
+}
+
+impl DeferredPointLightProgram {
+    pub fn new(display: &Display) -> DeferredPointLightProgram {
+        let vertex_shader_src = r#"
+            #version 330 core
+
+            in vec3 position;
+
+            uniform mat4 matrix;
+
+            void main() {
+                gl_Position = matrix * vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            out vec4 out_color;
+
+            uniform int light_index;
+            "#,
+            LIGHTING_SHADER_SRC,
+            FOG_SHADER_SRC,
+            GBUFFER_SHADER_SRC,
+            r#"
+            void main() {
+                GBufferSurface surface;
+                if (!read_gbuffer(surface)) {
+                    discard;
+                }
+                ambient_occlusion = get_ambient_occlusion();
+                vec3 view_direction = normalize(view_pos - surface.position);
+
+                PointLight light = point_lights[light_index];
+                float shadow = calculate_point_shadow(light.shadow_index, light.position, surface.position, surface.normal);
+                vec3 result = calculate_point_light(light, surface.normal, surface.position, view_direction, surface.diffuse, surface.specular, surface.shininess, shadow);
+                // added to the fogged full screen pass, so it only fades
+                result *= 1.0 - get_fog_amount(surface.position, view_pos);
+
+                out_color = vec4(result, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        DeferredPointLightProgram(
+            Program::from_source(display, vertex_shader_src, &fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i>(
+        matrix: &'a Matrix4<f32>,
+        light_index: i32,
+        gbuffer: &'b GBuffer,
+        inverse_projection_view: &'c Matrix4<f32>,
+        view: &'d Point3<f32>,
+        lights: &'e LightBuffer,
+        shadow_maps: &'f ShadowMaps,
+        ssao: &'g Ssao,
+        ibl: &'h ImageBasedLighting,
+        fog: &'i Fog,
+    ) -> DeferredPointLightUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i> {
+        DeferredPointLightUniforms {
+            matrix,
+            light_index,
+            gbuffer,
+            inverse_projection_view,
+            view,
+            lights,
+            shadow_maps,
+            ssao,
+            ibl,
+            fog,
+        }
+    }
+}
+
+// view space position of a pixel from the gbuffer depth
+const VIEW_POSITION_SHADER_SRC: &str = r#"
+            uniform sampler2D g_depth;
+            uniform mat4 inverse_projection;
+
+            vec3 get_view_position(vec2 uv) {
+                float depth = texture(g_depth, uv).r;
+                vec4 position = inverse_projection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+                return position.xyz / position.w;
+            }
+"#;
+
+// occlusion of every pixel from the gbuffer depth and normals, 1 is not occluded
+pub struct SsaoProgram(pub Program);
+
+pub struct SsaoUniforms<'a, 'b, 'c, 'd> {
+    ssao: &'a Ssao,
+    gbuffer: &'b GBuffer,
+    view: &'c Matrix4<f32>,
+    projection: &'d Matrix4<f32>,
+    inverse_projection: Matrix4<f32>,
+}
+
+impl glium::uniforms::Uniforms for SsaoUniforms<'_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        let sampler = self.ssao.get_sampler();
+        f(
+            "g_normal",
+            UniformValue::Texture2d(&self.gbuffer.normal, Some(sampler)),
+        );
+        f(
+            "g_depth",
+            UniformValue::DepthTexture2d(&self.gbuffer.depth, Some(sampler)),
+        );
+        f(
+            "noise",
+            UniformValue::Texture2d(&self.ssao.noise, Some(self.ssao.get_noise_sampler())),
+        );
+        for (i, sample) in self.ssao.kernel.iter().enumerate() {
+            f(
+                &format!("samples[{}]", i),
+                UniformValue::Vec3([sample.x, sample.y, sample.z]),
+            );
+        }
+
+        f("view", UniformValue::Mat4(self.view.to_array()));
+        f("projection", UniformValue::Mat4(self.projection.to_array()));
+        f(
+            "inverse_projection",
+            UniformValue::Mat4(self.inverse_projection.to_array()),
+        );
+        f("radius", UniformValue::Float(self.ssao.radius));
+        f("bias", UniformValue::Float(self.ssao.bias));
+        f("power", UniformValue::Float(self.ssao.power));
+    }
+}
+
+impl SsaoProgram {
+    pub fn new(display: &Display) -> SsaoProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out float out_occlusion;
+
+            uniform sampler2D g_normal;
+            uniform sampler2D noise;
+
+            // has to match SSAO_KERNEL_SIZE
+            #define KERNEL_SIZE 32
+            uniform vec3 samples[KERNEL_SIZE];
+
+            uniform mat4 view;
+            uniform mat4 projection;
+            uniform float radius;
+            uniform float bias;
+            uniform float power;
+            "#,
+            VIEW_POSITION_SHADER_SRC,
+            r#"
+            void main() {
+                if (texture(g_depth, v_tex_coords).r >= 1.0) {
+                    out_occlusion = 1.0;
+                    return;
+                }
+
+                vec3 position = get_view_position(v_tex_coords);
+                vec3 normal = normalize(mat3(view) * texture(g_normal, v_tex_coords).xyz);
+
+                // the kernel is rotated randomly around the normal, the blur hides the noise
+                vec2 noise_scale = vec2(textureSize(g_depth, 0)) / vec2(textureSize(noise, 0));
+                vec3 random = vec3(texture(noise, v_tex_coords * noise_scale).xy, 0.0);
+                vec3 tangent = normalize(random - normal * dot(random, normal));
+                vec3 bitangent = cross(normal, tangent);
+                mat3 tbn = mat3(tangent, bitangent, normal);
+
+                float occlusion = 0.0;
+                for (int i = 0; i < KERNEL_SIZE; i++) {
+                    vec3 sample_position = position + tbn * samples[i] * radius;
+
+                    vec4 projected = projection * vec4(sample_position, 1.0);
+                    vec2 sample_uv = projected.xy / projected.w * 0.5 + 0.5;
+                    float surface_depth = get_view_position(sample_uv).z;
+
+                    // surfaces far in front of the fragment don't occlude it
+                    float range = smoothstep(0.0, 1.0, radius / abs(position.z - surface_depth));
+                    occlusion += (surface_depth >= sample_position.z + bias ? 1.0 : 0.0) * range;
+                }
+
+                out_occlusion = pow(1.0 - occlusion / KERNEL_SIZE, power);
+            }
+        "#,
+        ]
+        .concat();
+
+        SsaoProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c, 'd>(
+        ssao: &'a Ssao,
+        gbuffer: &'b GBuffer,
+        view: &'c Matrix4<f32>,
+        projection: &'d Matrix4<f32>,
+    ) -> SsaoUniforms<'a, 'b, 'c, 'd> {
+        SsaoUniforms {
+            ssao,
+            gbuffer,
+            view,
+            projection,
+            inverse_projection: projection.invert().unwrap(),
+        }
+    }
+}
+
+// blurs the occlusion over the noise tile, the pixels with a different depth are skipped so the
+// edges stay sharp
+pub struct SsaoBlurProgram(pub Program);
+
+pub struct SsaoBlurUniforms<'a, 'b> {
+    occlusion: &'a glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+    gbuffer: &'b GBuffer,
+    inverse_projection: Matrix4<f32>,
+}
+
+impl glium::uniforms::Uniforms for SsaoBlurUniforms<'_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "occlusion",
+            UniformValue::Texture2d(self.occlusion, Some(self.sampler)),
+        );
+        f(
+            "g_depth",
+            UniformValue::DepthTexture2d(&self.gbuffer.depth, Some(self.sampler)),
+        );
+        f(
+            "inverse_projection",
+            UniformValue::Mat4(self.inverse_projection.to_array()),
+        );
+    }
+}
+
+impl SsaoBlurProgram {
+    pub fn new(display: &Display) -> SsaoBlurProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out float out_occlusion;
+
+            uniform sampler2D occlusion;
+            "#,
+            VIEW_POSITION_SHADER_SRC,
+            r#"
+            void main() {
+                vec2 texel_size = 1.0 / vec2(textureSize(occlusion, 0));
+                float depth = get_view_position(v_tex_coords).z;
+
+                float result = 0.0;
+                float total_weight = 0.0;
+                for (int x = -2; x < 2; x++) {
+                    for (int y = -2; y < 2; y++) {
+                        vec2 uv = v_tex_coords + vec2(x, y) * texel_size;
+                        float difference = abs(get_view_position(uv).z - depth);
+                        float weight = exp(-difference / (0.02 * abs(depth)));
+                        result += texture(occlusion, uv).r * weight;
+                        total_weight += weight;
+                    }
+                }
+
+                out_occlusion = result / max(total_weight, 0.0001);
+            }
+        "#,
+        ]
+        .concat();
+
+        SsaoBlurProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b>(
+        occlusion: &'a glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+        gbuffer: &'b GBuffer,
+        projection: &Matrix4<f32>,
+    ) -> SsaoBlurUniforms<'a, 'b> {
+        SsaoBlurUniforms {
+            occlusion,
+            sampler,
+            gbuffer,
+            inverse_projection: projection.invert().unwrap(),
+        }
+    }
+}
+
+// world space position of a pixel from the gbuffer depth, used by the volumetric light passes
+const WORLD_POSITION_SHADER_SRC: &str = r#"
+            uniform sampler2D g_depth;
+            uniform mat4 inverse_projection_view;
+
+            vec3 get_world_position(vec2 uv) {
+                float depth = texture(g_depth, uv).r;
+                vec4 position = inverse_projection_view * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+                return position.xyz / position.w;
+            }
+"#;
+
+// light scattered towards the camera by the air, the ray of every pixel is marched through the
+// shadow maps of the directional and spot lights, the alpha has the distance to the surface
+pub struct VolumetricLightProgram(pub Program);
+
+pub struct VolumetricLightUniforms<'a, 'b, 'c, 'd, 'e> {
+    volumetric_light: &'a VolumetricLight,
+    gbuffer: &'b GBuffer,
+    lights: &'c LightBuffer,
+    shadow_maps: &'d ShadowMaps,
+    view: &'e Point3<f32>,
+    inverse_projection_view: Matrix4<f32>,
+}
+
+impl glium::uniforms::Uniforms for VolumetricLightUniforms<'_, '_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "g_depth",
+            UniformValue::DepthTexture2d(&self.gbuffer.depth, Some(self.gbuffer.get_sampler())),
+        );
+        f(
+            "inverse_projection_view",
+            UniformValue::Mat4(self.inverse_projection_view.to_array()),
+        );
+        f(
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
+        );
+        visit_light_uniforms(self.lights, &mut f);
+        visit_shadow_map_uniforms(self.shadow_maps, &mut f);
+
+        let volumetric_light = self.volumetric_light;
+        f("density", UniformValue::Float(volumetric_light.density));
+        f("anisotropy", UniformValue::Float(volumetric_light.anisotropy));
+        f("steps", UniformValue::SignedInt(volumetric_light.steps));
+        f(
+            "max_distance",
+            UniformValue::Float(volumetric_light.max_distance),
+        );
+        f(
+            "frame",
+            UniformValue::SignedInt(volumetric_light.get_frame() as i32),
+        );
+    }
+}
+
+impl VolumetricLightProgram {
+    pub fn new(display: &Display) -> VolumetricLightProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_scattering;
+
+            uniform mat4 light_space_matrix;
+
+            uniform float density;
+            uniform float anisotropy;
+            uniform int steps;
+            uniform float max_distance;
+            uniform int frame;
+
+            const float PI = 3.14159265359;
+            "#,
+            LIGHTING_SHADER_SRC,
+            WORLD_POSITION_SHADER_SRC,
+            r#"
+            // henyey-greenstein, how much of the light is scattered from its direction towards the
+            // camera, a positive anisotropy scatters more of it forwards
+            float get_phase(float cos_theta) {
+                float g2 = anisotropy * anisotropy;
+                return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * anisotropy * cos_theta, 1.5));
+            }
+
+            // interleaved gradient noise, it changes every frame so the temporal pass averages it
+            float get_noise(vec2 pixel) {
+                pixel += float(frame % 16) * 5.588238;
+                return fract(52.9829189 * fract(0.06711056 * pixel.x + 0.00583715 * pixel.y));
+            }
+
+            vec3 get_scattered_light(vec3 position, vec3 ray_direction) {
+                vec3 light = vec3(0.0);
+                for (int i = 0; i < directional_light_count; i++) {
+                    DirectionalLight directional_light = directional_lights[i];
+                    float lit = 1.0;
+                    if (directional_light.shadow_index >= 0) {
+                        // a single tap, the noise of the ray start already softens the edges
+                        vec4 position_light_space = light_space_matrix * vec4(position, 1.0);
+                        vec3 projected = position_light_space.xyz / position_light_space.w * 0.5 + 0.5;
+                        if (!is_outside_shadow_map(projected)) {
+                            lit = texture(shadow_map, projected);
+                        }
+                    }
+                    float phase = get_phase(dot(ray_direction, -normalize(directional_light.direction)));
+                    light += directional_light.diffuse * phase * lit;
+                }
+
+                for (int i = 0; i < spot_light_count; i++) {
+                    SpotLight spot_light = spot_lights[i];
+                    vec3 light_direction = normalize(spot_light.position - position);
+
+                    float theta = dot(light_direction, normalize(-spot_light.direction));
+                    float epsilon = spot_light.cut_off - spot_light.outer_cut_off;
+                    float intensity = clamp((theta - spot_light.outer_cut_off) / epsilon, 0.0, 1.0);
+                    if (intensity <= 0.0) {
+                        continue;
+                    }
+
+                    float distance = length(spot_light.position - position);
+                    float attenuation = 1.0 / (spot_light.constant + spot_light.linear * distance +
+                                        spot_light.quadratic * (distance * distance));
+                    vec3 projection = calculate_spot_projection(spot_light.shadow_index, position);
+                    float phase = get_phase(dot(ray_direction, light_direction));
+                    light += spot_light.diffuse * intensity * attenuation * projection * phase;
+                }
+                return light;
+            }
+
+            void main() {
+                vec3 surface = get_world_position(v_tex_coords);
+                vec3 ray = surface - view_pos;
+                float surface_distance = length(ray);
+                vec3 ray_direction = ray / surface_distance;
+
+                float distance = min(surface_distance, max_distance);
+                float step_size = distance / float(steps);
+                float travelled = step_size * get_noise(gl_FragCoord.xy);
+
+                vec3 scattering = vec3(0.0);
+                for (int i = 0; i < steps; i++) {
+                    vec3 position = view_pos + ray_direction * travelled;
+                    // the light scattered far away is absorbed on its way to the camera
+                    float transmittance = exp(-density * travelled);
+                    scattering += get_scattered_light(position, ray_direction) * density * transmittance * step_size;
+                    travelled += step_size;
+                }
+
+                out_scattering = vec4(scattering, surface_distance);
+            }
+        "#,
+        ]
+        .concat();
+
+        VolumetricLightProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e>(
+        volumetric_light: &'a VolumetricLight,
+        gbuffer: &'b GBuffer,
+        lights: &'c LightBuffer,
+        shadow_maps: &'d ShadowMaps,
+        view: &'e Point3<f32>,
+        projection_view: &Matrix4<f32>,
+    ) -> VolumetricLightUniforms<'a, 'b, 'c, 'd, 'e> {
+        VolumetricLightUniforms {
+            volumetric_light,
+            gbuffer,
+            lights,
+            shadow_maps,
+            view,
+            inverse_projection_view: projection_view.invert().unwrap(),
+        }
+    }
+}
+
+// accumulates the noisy scattering over the frames, the history is reprojected with the camera
+// movement and clamped to the new neighbourhood so it doesn't leave trails behind
+pub struct VolumetricLightTemporalProgram(pub Program);
+
+pub struct VolumetricLightTemporalUniforms<'a, 'b, 'c, 'd> {
+    scattering: &'a glium::texture::Texture2d,
+    history: &'b glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+    gbuffer: &'c GBuffer,
+    previous_projection_view: &'d Matrix4<f32>,
+    inverse_projection_view: Matrix4<f32>,
+    history_valid: bool,
+    blend: f32,
+}
+
+impl glium::uniforms::Uniforms for VolumetricLightTemporalUniforms<'_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "scattering",
+            UniformValue::Texture2d(self.scattering, Some(self.sampler)),
+        );
+        f(
+            "history",
+            UniformValue::Texture2d(self.history, Some(self.sampler)),
+        );
+        f(
+            "g_depth",
+            UniformValue::DepthTexture2d(&self.gbuffer.depth, Some(self.gbuffer.get_sampler())),
+        );
+        f(
+            "inverse_projection_view",
+            UniformValue::Mat4(self.inverse_projection_view.to_array()),
+        );
+        f(
+            "previous_projection_view",
+            UniformValue::Mat4(self.previous_projection_view.to_array()),
+        );
+        f("history_valid", UniformValue::Bool(self.history_valid));
+        f("blend", UniformValue::Float(self.blend));
+    }
+}
+
+impl VolumetricLightTemporalProgram {
+    pub fn new(display: &Display) -> VolumetricLightTemporalProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_scattering;
+
+            uniform sampler2D scattering;
+            uniform sampler2D history;
+            uniform mat4 previous_projection_view;
+            uniform bool history_valid;
+            // how much of the new frame is used
+            uniform float blend;
+            "#,
+            WORLD_POSITION_SHADER_SRC,
+            r#"
+            void main() {
+                ivec2 texel = ivec2(gl_FragCoord.xy);
+                ivec2 max_texel = textureSize(scattering, 0) - 1;
+                vec4 current = texelFetch(scattering, texel, 0);
+
+                vec3 neighbourhood_min = current.rgb;
+                vec3 neighbourhood_max = current.rgb;
+                for (int x = -1; x <= 1; x++) {
+                    for (int y = -1; y <= 1; y++) {
+                        vec3 neighbour = texelFetch(scattering, clamp(texel + ivec2(x, y), ivec2(0), max_texel), 0).rgb;
+                        neighbourhood_min = min(neighbourhood_min, neighbour);
+                        neighbourhood_max = max(neighbourhood_max, neighbour);
+                    }
+                }
+
+                // where the surface of this pixel was on the screen in the last frame
+                vec4 previous_position = previous_projection_view * vec4(get_world_position(v_tex_coords), 1.0);
+                vec2 previous_uv = previous_position.xy / previous_position.w * 0.5 + 0.5;
+
+                float current_blend = blend;
+                if (!history_valid || any(lessThan(previous_uv, vec2(0.0))) || any(greaterThan(previous_uv, vec2(1.0)))) {
+                    current_blend = 1.0;
+                }
+                vec3 previous = clamp(texture(history, previous_uv).rgb, neighbourhood_min, neighbourhood_max);
+
+                out_scattering = vec4(mix(previous, current.rgb, current_blend), current.a);
+            }
+        "#,
+        ]
+        .concat();
+
+        VolumetricLightTemporalProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_uniforms<'a, 'b, 'c, 'd>(
+        scattering: &'a glium::texture::Texture2d,
+        history: &'b glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+        gbuffer: &'c GBuffer,
+        projection_view: &Matrix4<f32>,
+        previous_projection_view: &'d Matrix4<f32>,
+        history_valid: bool,
+        blend: f32,
+    ) -> VolumetricLightTemporalUniforms<'a, 'b, 'c, 'd> {
+        VolumetricLightTemporalUniforms {
+            scattering,
+            history,
+            sampler,
+            gbuffer,
+            previous_projection_view,
+            inverse_projection_view: projection_view.invert().unwrap(),
+            history_valid,
+            blend,
+        }
+    }
+}
+
+// adds the low resolution scattering to the scene, every pixel takes the closest low resolution
+// texels weighted by how similar their distance is, so the shafts don't bleed over the edges
+pub struct VolumetricLightCompositeProgram(pub Program);
+
+pub struct VolumetricLightCompositeUniforms<'a, 'b, 'c> {
+    scattering: &'a glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+    gbuffer: &'b GBuffer,
+    view: &'c Point3<f32>,
+    inverse_projection_view: Matrix4<f32>,
+}
+
+impl glium::uniforms::Uniforms for VolumetricLightCompositeUniforms<'_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "scattering",
+            UniformValue::Texture2d(self.scattering, Some(self.sampler)),
+        );
+        f(
+            "g_depth",
+            UniformValue::DepthTexture2d(&self.gbuffer.depth, Some(self.gbuffer.get_sampler())),
+        );
+        f(
+            "inverse_projection_view",
+            UniformValue::Mat4(self.inverse_projection_view.to_array()),
+        );
+        f(
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
+        );
+    }
+}
+
+impl VolumetricLightCompositeProgram {
+    pub fn new(display: &Display) -> VolumetricLightCompositeProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            uniform sampler2D scattering;
+            uniform vec3 view_pos;
+            "#,
+            WORLD_POSITION_SHADER_SRC,
+            r#"
+            void main() {
+                float distance = length(get_world_position(v_tex_coords) - view_pos);
+
+                vec2 size = vec2(textureSize(scattering, 0));
+                vec2 coords = v_tex_coords * size - 0.5;
+                ivec2 base = ivec2(floor(coords));
+                vec2 bilinear = fract(coords);
+
+                vec3 color = vec3(0.0);
+                float total_weight = 0.0;
+                for (int x = 0; x <= 1; x++) {
+                    for (int y = 0; y <= 1; y++) {
+                        ivec2 texel = clamp(base + ivec2(x, y), ivec2(0), ivec2(size) - 1);
+                        vec4 low_resolution = texelFetch(scattering, texel, 0);
+                        float weight = (x == 0 ? 1.0 - bilinear.x : bilinear.x) * (y == 0 ? 1.0 - bilinear.y : bilinear.y);
+                        weight /= 0.001 + abs(low_resolution.a - distance) / distance;
+                        color += low_resolution.rgb * weight;
+                        total_weight += weight;
+                    }
+                }
+
+                out_color = vec4(color / max(total_weight, 0.0001), 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        VolumetricLightCompositeProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c>(
+        scattering: &'a glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+        gbuffer: &'b GBuffer,
+        view: &'c Point3<f32>,
+        projection_view: &Matrix4<f32>,
+    ) -> VolumetricLightCompositeUniforms<'a, 'b, 'c> {
+        VolumetricLightCompositeUniforms {
+            scattering,
+            sampler,
+            gbuffer,
+            view,
+            inverse_projection_view: projection_view.invert().unwrap(),
+        }
+    }
+}
+
+// only writes depth, used to render the shadow maps
+pub struct ShadowDepthProgram(pub Program);
+
+pub struct ShadowDepthUniforms<'a> {
+    matrix: &'a Matrix4<f32>,
+}
+
+impl glium::uniforms::Uniforms for ShadowDepthUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+    }
+}
+
+impl ShadowDepthProgram {
+    pub fn new(display: &Display) -> ShadowDepthProgram {
+        let vertex_shader_src = r#"
+            #version 330 core
+
+            in vec3 position;
+
+            uniform mat4 matrix;
+
+            void main() {
+                gl_Position = matrix * vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            void main() {
+            }
+        "#;
+
+        ShadowDepthProgram(
+            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms(matrix: &Matrix4<f32>) -> ShadowDepthUniforms<'_> {
+        ShadowDepthUniforms { matrix }
+    }
+}
+
+pub struct SimpleLightObjectProgram(pub Program);
+
+pub struct SimpleLightObjectUniforms<'a, 'b> {
+    matrix: &'a Matrix4<f32>,
+    color: &'b Vector3<f32>,
+}
+
+impl glium::uniforms::Uniforms for SimpleLightObjectUniforms<'_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+
+        f(
+            "color",
+            UniformValue::Vec3([self.color.x, self.color.y, self.color.z]),
+        );
+    }
+}
+
+impl SimpleLightObjectProgram {
+    pub fn new(display: &Display) -> SimpleLightObjectProgram {
+        let vertex_shader_src = r#"
+        #version 140
+
+        in vec3 position;
+
+        uniform mat4 matrix;
+
+        void main() {
+            gl_Position = matrix * vec4(position, 1.0);
+        }
+    "#;
+
+        let fragment_shader_src = r#"
+        #version 140
+
+        out vec4 out_color;
+
+        uniform vec3 color;
+        
+        void main() {
+            out_color = vec4(color, 1.0);
+        }
+    "#;
+
+        SimpleLightObjectProgram(
+            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b>(
+        matrix: &'a Matrix4<f32>,
+        color: &'b Vector3<f32>,
+    ) -> SimpleLightObjectUniforms<'a, 'b> {
+        SimpleLightObjectUniforms { matrix, color }
+    }
+}
+
+pub struct FluidParticleProgram(pub Program);
+
+pub struct FluidParticleUniforms<'a, 'b, 'c> {
+    matrix: &'a Matrix4<f32>,
+    radius: f32,
+    color: &'b Vector3<f32>,
+    light_direction: &'c Vector3<f32>,
+}
+
+impl glium::uniforms::Uniforms for FluidParticleUniforms<'_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("radius", UniformValue::Float(self.radius));
+        f("color", UniformValue::Vec3((*self.color).into()));
+        f(
+            "light_direction",
+            UniformValue::Vec3((*self.light_direction).into()),
+        );
+    }
+}
+
+impl FluidParticleProgram {
+    pub fn new(display: &Display) -> FluidParticleProgram {
+        let vertex_shader_src = r#"
+            #version 330 core
+
+            in vec3 position;
+            in vec3 offset;
+
+            out vec3 v_normal;
+
+            uniform mat4 matrix;
+            uniform float radius;
+
+            void main() {
+                gl_Position = matrix * vec4(position * radius + offset, 1.0);
+                v_normal = position;
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            in vec3 v_normal;
+
+            out vec4 out_color;
+
+            uniform vec3 color;
+            uniform vec3 light_direction;
+
+            void main() {
+                float diff = max(dot(normalize(v_normal), normalize(-light_direction)), 0.0);
+                out_color = vec4(color * (0.3 + 0.7 * diff), 1.0);
+            }
+        "#;
+
+        FluidParticleProgram(
+            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b, 'c>(
+        matrix: &'a Matrix4<f32>,
+        radius: f32,
+        color: &'b Vector3<f32>,
+        light_direction: &'c Vector3<f32>,
+    ) -> FluidParticleUniforms<'a, 'b, 'c> {
+        FluidParticleUniforms {
+            matrix,
+            radius,
+            color,
+            light_direction,
+        }
+    }
+}
+
+pub struct WaterProgram(pub Program);
+
+pub struct WaterUniforms<'a, 'b> {
+    matrix: &'a Matrix4<f32>,
+    color: &'b [f32; 4],
+}
+
+impl glium::uniforms::Uniforms for WaterUniforms<'_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f("color", UniformValue::Vec4(*self.color));
+    }
+}
+
+impl WaterProgram {
+    pub fn new(display: &Display) -> WaterProgram {
+        let vertex_shader_src = r#"
+            #version 330 core
+
+            in vec3 position;
+
+            uniform mat4 matrix;
+
+            void main() {
+                gl_Position = matrix * vec4(position, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            out vec4 out_color;
+
+            uniform vec4 color;
+
+            void main() {
+                out_color = color;
+            }
+        "#;
+
+        WaterProgram(
+            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b>(
+        matrix: &'a Matrix4<f32>,
+        color: &'b [f32; 4],
+    ) -> WaterUniforms<'a, 'b> {
+        WaterUniforms { matrix, color }
+    }
+}
+
+// full screen quad used by the passes that work on a whole texture
+const SCREEN_VERTEX_SHADER_SRC: &str = r#"
+            #version 330 core
+
+            in vec2 tex_coords;
+            in vec2 position;
+
+            out vec2 v_tex_coords;
+
+            void main() {
+                gl_Position = vec4(position.x, position.y, 0.0, 1.0);
+                v_tex_coords = tex_coords;
+            }
+"#;
+
+pub struct ScreenTextureUniforms<'a> {
+    screen_texture: &'a glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+}
+
+impl glium::uniforms::Uniforms for ScreenTextureUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "screen_texture",
+            UniformValue::Texture2d(self.screen_texture, Some(self.sampler)),
+        );
+    }
+}
+
+// log luminance of the scene, its average is used by the auto exposure
+pub struct LuminanceProgram(pub Program);
+
+impl LuminanceProgram {
+    pub fn new(display: &Display) -> LuminanceProgram {
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            uniform sampler2D screen_texture;
+
+            void main() {
+                vec3 color = texture(screen_texture, v_tex_coords).rgb;
+                float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+                out_color = vec4(log(max(luminance, 0.0001)), 0.0, 0.0, 1.0);
+            }
+        "#;
+
+        LuminanceProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms(
+        screen_texture: &glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+    ) -> ScreenTextureUniforms<'_> {
+        ScreenTextureUniforms {
+            screen_texture,
+            sampler,
+        }
+    }
+}
+
+// copies a texture into a smaller one
+pub struct DownsampleProgram(pub Program);
+
+impl DownsampleProgram {
+    pub fn new(display: &Display) -> DownsampleProgram {
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            uniform sampler2D screen_texture;
+
+            void main() {
+                out_color = texture(screen_texture, v_tex_coords);
+            }
+        "#;
+
+        DownsampleProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms(
+        screen_texture: &glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+    ) -> ScreenTextureUniforms<'_> {
+        ScreenTextureUniforms {
+            screen_texture,
+            sampler,
+        }
+    }
+}
+
+// moves the adapted luminance of the last frame towards the average of this one
+pub struct ExposureAdaptationProgram(pub Program);
+
+pub struct ExposureAdaptationUniforms<'a, 'b> {
+    average_log_luminance: &'a glium::texture::Texture2d,
+    previous_luminance: &'b glium::texture::Texture2d,
+    adaptation_rate: f32,
+}
+
+impl glium::uniforms::Uniforms for ExposureAdaptationUniforms<'_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "average_log_luminance",
+            UniformValue::Texture2d(self.average_log_luminance, None),
+        );
+        f(
+            "previous_luminance",
+            UniformValue::Texture2d(self.previous_luminance, None),
+        );
+        f("adaptation_rate", UniformValue::Float(self.adaptation_rate));
+    }
+}
+
+impl ExposureAdaptationProgram {
+    pub fn new(display: &Display) -> ExposureAdaptationProgram {
+        let fragment_shader_src = r#"
+            #version 330 core
+
+            out vec4 out_color;
+
+            uniform sampler2D average_log_luminance;
+            uniform sampler2D previous_luminance;
+            uniform float adaptation_rate;
+
+            void main() {
+                float average = exp(texelFetch(average_log_luminance, ivec2(0), 0).r);
+                float previous = texelFetch(previous_luminance, ivec2(0), 0).r;
+                out_color = vec4(mix(previous, average, adaptation_rate), 0.0, 0.0, 1.0);
+            }
+        "#;
+
+        ExposureAdaptationProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms<'a, 'b>(
+        average_log_luminance: &'a glium::texture::Texture2d,
+        previous_luminance: &'b glium::texture::Texture2d,
+        adaptation_rate: f32,
+    ) -> ExposureAdaptationUniforms<'a, 'b> {
+        ExposureAdaptationUniforms {
+            average_log_luminance,
+            previous_luminance,
+            adaptation_rate,
+        }
+    }
+}
+
+// keeps the parts of the scene brighter than the threshold, the knee makes the cut soft
+pub struct BloomBrightPassProgram(pub Program);
+
+pub struct BloomBrightPassUniforms<'a> {
+    screen_texture: &'a glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+    threshold: f32,
+}
+
+impl glium::uniforms::Uniforms for BloomBrightPassUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "screen_texture",
+            UniformValue::Texture2d(self.screen_texture, Some(self.sampler)),
+        );
+        f("threshold", UniformValue::Float(self.threshold));
+    }
+}
+
+// averages 4x4 texels of the source with four bilinear samples
+const BLOOM_DOWNSAMPLE_SHADER_SRC: &str = r#"
+            vec3 downsample(sampler2D source, vec2 tex_coords) {
+                vec2 texel_size = 1.0 / vec2(textureSize(source, 0));
+                vec3 color = texture(source, tex_coords + texel_size * vec2(-1.0, -1.0)).rgb;
+                color += texture(source, tex_coords + texel_size * vec2(1.0, -1.0)).rgb;
+                color += texture(source, tex_coords + texel_size * vec2(-1.0, 1.0)).rgb;
+                color += texture(source, tex_coords + texel_size * vec2(1.0, 1.0)).rgb;
+                return color * 0.25;
+            }
+"#;
+
+impl BloomBrightPassProgram {
+    pub fn new(display: &Display) -> BloomBrightPassProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            uniform sampler2D screen_texture;
+            uniform float threshold;
+            "#,
+            BLOOM_DOWNSAMPLE_SHADER_SRC,
+            r#"
+            void main() {
+                vec3 color = downsample(screen_texture, v_tex_coords);
+
+                float knee = threshold * 0.5;
+                float brightness = max(color.r, max(color.g, color.b));
+                float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+                soft = soft * soft / (4.0 * knee + 0.0001);
+                float contribution = max(soft, brightness - threshold) / max(brightness, 0.0001);
+
+                out_color = vec4(color * contribution, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        BloomBrightPassProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
+
+    pub fn get_uniforms(
+        screen_texture: &glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+        threshold: f32,
+    ) -> BloomBrightPassUniforms<'_> {
+        BloomBrightPassUniforms {
+            screen_texture,
+            sampler,
+            threshold,
+        }
+    }
+}
 
-                float constant;
-                float linear;
-                float quadratic;
-            };
-            #define NR_POINT_LIGHTS 4
-            uniform PointLight point_lights[NR_POINT_LIGHTS];
-    
-            uniform vec3 view_pos;
+pub struct BloomDownsampleProgram(pub Program);
 
-            vec3 calculate_directional_light(DirectionalLight light, vec3 normal, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular) {
-                vec3 light_dir = normalize(-light.direction);
+impl BloomDownsampleProgram {
+    pub fn new(display: &Display) -> BloomDownsampleProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
 
-                // diffuse shading
-                float diff = max(dot(normal, light_dir), 0.0);
+            in vec2 v_tex_coords;
 
-                // specular shading
-                vec3 reflect_direction = reflect(-light_dir, normal);
-                float spec = pow(max(dot(light.direction, reflect_direction), 0.0),
-                                 material.shininess);
+            out vec4 out_color;
 
-                // combine results
-                vec3 ambient = light.ambient * tex_diffuse;
-                vec3 diffuse = light.diffuse * diff * tex_diffuse;
-                vec3 specular = light.specular * spec * tex_specular; 
-                return (ambient + diffuse + specular);
+            uniform sampler2D screen_texture;
+            "#,
+            BLOOM_DOWNSAMPLE_SHADER_SRC,
+            r#"
+            void main() {
+                out_color = vec4(downsample(screen_texture, v_tex_coords), 1.0);
             }
+        "#,
+        ]
+        .concat();
 
+        BloomDownsampleProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
 
-            vec3 calculate_spot_light(SpotLight light, vec3 normal, vec3 frag_pos, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular) {
-                // diffuse
-                vec3 light_direction = normalize(light.position - frag_pos);
-                float diff = max(dot(normal, light_direction), 0.0);
-    
-                // specular
-                vec3 reflect_direction = reflect(-light_direction, normal);  
-                float spec = pow(max(dot(view_direction, reflect_direction), 0.0), 
-                                 material.shininess);
+    pub fn get_uniforms(
+        screen_texture: &glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+    ) -> ScreenTextureUniforms<'_> {
+        ScreenTextureUniforms {
+            screen_texture,
+            sampler,
+        }
+    }
+}
 
-                vec3 ambient = light.ambient * tex_diffuse;
-                vec3 diffuse = light.diffuse * diff * tex_diffuse;
-                vec3 specular = light.specular * spec * tex_specular; 
+// blurs a bloom texture with a 3x3 tent filter while drawing it on the next bigger one
+pub struct BloomUpsampleProgram(pub Program);
 
-                float theta = dot(light_direction, normalize(-light.direction));
-                float epsilon = light.cut_off - light.outer_cut_off;
-                float intensity = clamp((theta - light.outer_cut_off) / epsilon, 0.0, 1.0);
+pub struct BloomUpsampleUniforms<'a> {
+    screen_texture: &'a glium::texture::Texture2d,
+    sampler: SamplerBehavior,
+    radius: f32,
+}
+
+impl glium::uniforms::Uniforms for BloomUpsampleUniforms<'_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "screen_texture",
+            UniformValue::Texture2d(self.screen_texture, Some(self.sampler)),
+        );
+        f("radius", UniformValue::Float(self.radius));
+    }
+}
 
-                diffuse *= intensity;
-                specular *= intensity;
+impl BloomUpsampleProgram {
+    pub fn new(display: &Display) -> BloomUpsampleProgram {
+        let fragment_shader_src = r#"
+            #version 330 core
 
-                return (ambient + diffuse + specular);
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            uniform sampler2D screen_texture;
+            uniform float radius;
+
+            void main() {
+                vec2 offset = radius / vec2(textureSize(screen_texture, 0));
+
+                vec3 color = texture(screen_texture, v_tex_coords).rgb * 4.0;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(-1.0, 0.0)).rgb * 2.0;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(1.0, 0.0)).rgb * 2.0;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(0.0, -1.0)).rgb * 2.0;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(0.0, 1.0)).rgb * 2.0;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(-1.0, -1.0)).rgb;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(1.0, -1.0)).rgb;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(-1.0, 1.0)).rgb;
+                color += texture(screen_texture, v_tex_coords + offset * vec2(1.0, 1.0)).rgb;
+
+                out_color = vec4(color / 16.0, 1.0);
             }
+        "#;
 
+        BloomUpsampleProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, fragment_shader_src, None)
+                .unwrap(),
+        )
+    }
 
-            vec3 calculate_point_light(PointLight light, vec3 normal, vec3 frag_pos, vec3 view_direction, vec3 tex_diffuse, vec3 tex_specular) {
-                // diffuse
-                vec3 light_direction = normalize(light.position - frag_pos);
-                float diff = max(dot(normal, light_direction), 0.0);
-    
-                // specular
-                vec3 reflect_direction = reflect(-light_direction, normal);  
-                float spec = pow(max(dot(view_direction, reflect_direction), 0.0), 
-                                 material.shininess);
+    pub fn get_uniforms(
+        screen_texture: &glium::texture::Texture2d,
+        sampler: SamplerBehavior,
+        radius: f32,
+    ) -> BloomUpsampleUniforms<'_> {
+        BloomUpsampleUniforms {
+            screen_texture,
+            sampler,
+            radius,
+        }
+    }
+}
 
-                vec3 ambient = light.ambient * tex_diffuse;
-                vec3 diffuse = light.diffuse * diff * tex_diffuse;
-                vec3 specular = light.specular * spec * tex_specular; 
+// the direction of the cubemap texel, for the precomputation programs that draw a screen quad over
+// each face
+const CUBE_FACE_SHADER_SRC: &str = r#"
+            uniform vec3 face_forward;
+            uniform vec3 face_right;
+            uniform vec3 face_up;
 
-                float distance = length(light.position - frag_pos);
-                float attenuation = 1.0 / (light.constant + light.linear * distance +
-                                    light.quadratic * (distance * distance));
-                   
-                ambient *= attenuation;
-                diffuse *= attenuation;
-                specular *= attenuation;
+            vec3 get_face_direction(vec2 tex_coords) {
+                vec2 face_position = tex_coords * 2.0 - 1.0;
+                return normalize(face_forward + face_right * face_position.x + face_up * face_position.y);
+            }
+"#;
 
-                return (ambient + diffuse + specular);
+fn visit_cube_face_uniforms<'a, F: FnMut(&str, UniformValue<'a>)>(face: &CubeFace, f: &mut F) {
+    f("face_forward", UniformValue::Vec3(face.forward.into()));
+    f("face_right", UniformValue::Vec3(face.right.into()));
+    f("face_up", UniformValue::Vec3(face.up.into()));
+}
+
+// picks the directions of the samples the way the ggx lobe spreads the light, needs GGX_SHADER_SRC
+const IMPORTANCE_SAMPLING_SHADER_SRC: &str = r#"
+            // spread more evenly than random points
+            vec2 hammersley(uint i, uint count) {
+                uint bits = i;
+                bits = (bits << 16u) | (bits >> 16u);
+                bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+                bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+                bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+                bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+                return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+            }
+
+            vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+                float a = roughness * roughness;
+                float phi = 2.0 * PI * xi.x;
+                float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+                float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+                vec3 halfway = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+                vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+                vec3 tangent = normalize(cross(up, normal));
+                vec3 bitangent = cross(normal, tangent);
+                return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
             }
+"#;
 
+// convolves the sky into the diffuse light that reaches each normal
+pub struct IblIrradianceProgram(pub Program);
 
-            void main() {
+pub struct IblIrradianceUniforms<'a, 'b> {
+    environment: &'a glium::texture::Cubemap,
+    sampler: SamplerBehavior,
+    face: &'b CubeFace,
+}
 
-                // properties
-                vec3 norm = normalize(v_normal);
-                vec3 view_direction = normalize(view_pos - v_frag_pos);
+impl glium::uniforms::Uniforms for IblIrradianceUniforms<'_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f(
+            "environment",
+            UniformValue::Cubemap(self.environment, Some(self.sampler)),
+        );
+        visit_cube_face_uniforms(self.face, &mut f);
+    }
+}
+
+impl IblIrradianceProgram {
+    pub fn new(display: &Display) -> IblIrradianceProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
 
-                vec3 tex_diffuse = vec3(texture(material.diffuse, v_tex_coords));
-                vec3 tex_specular = vec3(texture(material.specular, v_tex_coords));
+            in vec2 v_tex_coords;
 
-                vec3 result = calculate_directional_light(directional_light, norm, view_direction, tex_diffuse, tex_specular);
+            out vec4 out_color;
 
-                for(int i = 0; i < NR_POINT_LIGHTS; i++) {
-                    result += calculate_point_light(point_lights[i], norm, v_frag_pos, view_direction, tex_diffuse, tex_specular);
+            uniform samplerCube environment;
+            "#,
+            GGX_SHADER_SRC,
+            CUBE_FACE_SHADER_SRC,
+            r#"
+            void main() {
+                vec3 normal = get_face_direction(v_tex_coords);
+                vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+                vec3 right = normalize(cross(up, normal));
+                up = cross(normal, right);
+
+                // sum over the hemisphere, a blurry mipmap hides the steps between the samples
+                vec3 irradiance = vec3(0.0);
+                float sample_count = 0.0;
+                for (float phi = 0.0; phi < 2.0 * PI; phi += 0.05) {
+                    for (float theta = 0.0; theta < 0.5 * PI; theta += 0.05) {
+                        vec3 direction = (right * cos(phi) + up * sin(phi)) * sin(theta) + normal * cos(theta);
+                        irradiance += textureLod(environment, direction, 3.0).rgb * cos(theta) * sin(theta);
+                        sample_count += 1.0;
+                    }
                 }
 
-                result += calculate_spot_light(spot_light, norm, v_frag_pos, view_direction, tex_diffuse, tex_specular);
-                
-                out_color = vec4(result, 1.0);
+                out_color = vec4(PI * irradiance / sample_count, 1.0);
             }
-        "#;
-        SimpleTexturedObjectProgram(
-            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        "#,
+        ]
+        .concat();
+
+        IblIrradianceProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
         )
     }
 
-    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g>(
-        matrix: &'a Matrix4<f32>,
-        model: &'b Matrix4<f32>,
-        material: &'c Material,
-        directional_light: &'d DirectionalLight,
-        spot_light: &'e SpotLight,
-        point_lights: &'f [&'f PointLight; 4],
-        view: &'g Point3<f32>,
-    ) -> SimpleTexturedObjectUniforms<'a, 'b, 'c, 'd, 'e, 'f, 'g> {
-        SimpleTexturedObjectUniforms {
-            matrix,
-            model,
-            material,
-            directional_light,
-            spot_light,
-            point_lights,
-            view,
+    pub fn get_uniforms<'a, 'b>(
+        environment: &'a glium::texture::Cubemap,
+        sampler: SamplerBehavior,
+        face: &'b CubeFace,
+    ) -> IblIrradianceUniforms<'a, 'b> {
+        IblIrradianceUniforms {
+            environment,
+            sampler,
+            face,
         }
     }
 }
 
-pub struct SimpleLightObjectProgram(pub Program);
+// blurs the sky by the ggx lobe of one roughness, for one mipmap of the prefiltered cubemap
+pub struct IblPrefilterProgram(pub Program);
 
-pub struct SimpleLightObjectUniforms<'a, 'b> {
-    matrix: &'a Matrix4<f32>,
-    color: &'b Vector3<f32>,
+pub struct IblPrefilterUniforms<'a, 'b> {
+    environment: &'a glium::texture::Cubemap,
+    sampler: SamplerBehavior,
+    face: &'b CubeFace,
+    roughness: f32,
 }
 
-impl glium::uniforms::Uniforms for SimpleLightObjectUniforms<'_, '_> {
+impl glium::uniforms::Uniforms for IblPrefilterUniforms<'_, '_> {
     fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
-        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
-
         f(
-            "color",
-            UniformValue::Vec3([self.color.x, self.color.y, self.color.z]),
+            "environment",
+            UniformValue::Cubemap(self.environment, Some(self.sampler)),
         );
+        visit_cube_face_uniforms(self.face, &mut f);
+        f("roughness", UniformValue::Float(self.roughness));
     }
 }
 
-impl SimpleLightObjectProgram {
-    pub fn new(display: &Display) -> SimpleLightObjectProgram {
-        let vertex_shader_src = r#"
-        #version 140
-
-        in vec3 position;
+impl IblPrefilterProgram {
+    pub fn new(display: &Display) -> IblPrefilterProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
 
-        uniform mat4 matrix;
+            in vec2 v_tex_coords;
 
-        void main() {
-            gl_Position = matrix * vec4(position, 1.0);
-        }
-    "#;
+            out vec4 out_color;
 
-        let fragment_shader_src = r#"
-        #version 140
+            uniform samplerCube environment;
+            uniform float roughness;
 
-        out vec4 out_color;
+            #define SAMPLE_COUNT 256u
+            "#,
+            GGX_SHADER_SRC,
+            CUBE_FACE_SHADER_SRC,
+            IMPORTANCE_SAMPLING_SHADER_SRC,
+            r#"
+            void main() {
+                // the view is taken to be along the normal, so the reflections don't stretch at
+                // grazing angles
+                vec3 normal = get_face_direction(v_tex_coords);
+                vec3 view_direction = normal;
+
+                float size = float(textureSize(environment, 0).x);
+                float texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+
+                vec3 color = vec3(0.0);
+                float total_weight = 0.0;
+                for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                    vec3 halfway = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+                    vec3 light_direction = normalize(2.0 * dot(view_direction, halfway) * halfway - view_direction);
+                    float n_dot_l = dot(normal, light_direction);
+                    if (n_dot_l > 0.0) {
+                        // the unlikely samples stand for more of the sky, so they read a blurrier mipmap
+                        float pdf = distribution_ggx(normal, halfway, roughness) / 4.0 + 0.0001;
+                        float sample_solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+                        float lod = roughness == 0.0 ? 0.0 : 0.5 * log2(sample_solid_angle / texel_solid_angle);
+
+                        color += textureLod(environment, light_direction, lod).rgb * n_dot_l;
+                        total_weight += n_dot_l;
+                    }
+                }
 
-        uniform vec3 color;
-        
-        void main() {
-            out_color = vec4(color, 1.0);
-        }
-    "#;
+                out_color = vec4(color / total_weight, 1.0);
+            }
+        "#,
+        ]
+        .concat();
 
-        SimpleLightObjectProgram(
-            Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap(),
+        IblPrefilterProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
         )
     }
 
     pub fn get_uniforms<'a, 'b>(
-        matrix: &'a Matrix4<f32>,
-        color: &'b Vector3<f32>,
-    ) -> SimpleLightObjectUniforms<'a, 'b> {
-        SimpleLightObjectUniforms { matrix, color }
+        environment: &'a glium::texture::Cubemap,
+        sampler: SamplerBehavior,
+        face: &'b CubeFace,
+        roughness: f32,
+    ) -> IblPrefilterUniforms<'a, 'b> {
+        IblPrefilterUniforms {
+            environment,
+            sampler,
+            face,
+            roughness,
+        }
+    }
+}
+
+// the fresnel scale and bias of the split sum, x is the cosine of the view angle and y the roughness
+pub struct IblBrdfProgram(pub Program);
+
+impl IblBrdfProgram {
+    pub fn new(display: &Display) -> IblBrdfProgram {
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
+
+            in vec2 v_tex_coords;
+
+            out vec4 out_color;
+
+            #define SAMPLE_COUNT 1024u
+            "#,
+            GGX_SHADER_SRC,
+            IMPORTANCE_SAMPLING_SHADER_SRC,
+            r#"
+            // the image based lighting uses a smaller k than the lights
+            float geometry_schlick_ggx(float n_dot, float roughness) {
+                float k = roughness * roughness / 2.0;
+                return n_dot / (n_dot * (1.0 - k) + k);
+            }
+
+            void main() {
+                float n_dot_v = max(v_tex_coords.x, 0.001);
+                float roughness = v_tex_coords.y;
+                vec3 view_direction = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+                vec3 normal = vec3(0.0, 0.0, 1.0);
+
+                float scale = 0.0;
+                float bias = 0.0;
+                for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                    vec3 halfway = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+                    vec3 light_direction = normalize(2.0 * dot(view_direction, halfway) * halfway - view_direction);
+
+                    float n_dot_l = max(light_direction.z, 0.0);
+                    float n_dot_h = max(halfway.z, 0.0);
+                    float v_dot_h = max(dot(view_direction, halfway), 0.0);
+                    if (n_dot_l > 0.0) {
+                        float geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+                        float visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+                        float fresnel = pow(1.0 - v_dot_h, 5.0);
+                        scale += (1.0 - fresnel) * visibility;
+                        bias += fresnel * visibility;
+                    }
+                }
+
+                out_color = vec4(scale / float(SAMPLE_COUNT), bias / float(SAMPLE_COUNT), 0.0, 1.0);
+            }
+        "#,
+        ]
+        .concat();
+
+        IblBrdfProgram(
+            Program::from_source(display, SCREEN_VERTEX_SHADER_SRC, &fragment_shader_src, None)
+                .unwrap(),
+        )
     }
 }
 
 pub struct MainFramebufferProgram(pub Program);
 
-pub struct MainFramebufferProgramUniforms<'a> {
+pub struct MainFramebufferProgramUniforms<'a, 'b, 'c> {
     screen_texture: &'a glium::texture::Texture2d,
+    tone_mapping: &'b ToneMapping,
+    bloom: &'c Bloom,
 }
 
-impl glium::uniforms::Uniforms for MainFramebufferProgramUniforms<'_> {
+impl glium::uniforms::Uniforms for MainFramebufferProgramUniforms<'_, '_, '_> {
     fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
         f(
             "screen_texture",
             UniformValue::Texture2d(self.screen_texture, None),
         );
+
+        let bloom = self.bloom;
+        f("bloom_enabled", UniformValue::Bool(bloom.settings.enabled));
+        f(
+            "bloom_intensity",
+            UniformValue::Float(bloom.settings.intensity),
+        );
+        f(
+            "bloom_texture",
+            UniformValue::Texture2d(bloom.get_texture(), Some(bloom.get_sampler())),
+        );
+
+        let tone_mapping = self.tone_mapping;
+        f(
+            "tone_mapping_operator",
+            UniformValue::SignedInt(tone_mapping.operator as i32),
+        );
+        f(
+            "auto_exposure",
+            UniformValue::Bool(tone_mapping.auto_exposure),
+        );
+        f("exposure", UniformValue::Float(tone_mapping.exposure));
+        f("key_value", UniformValue::Float(tone_mapping.key_value));
+        f(
+            "min_luminance",
+            UniformValue::Float(tone_mapping.min_luminance),
+        );
+        f(
+            "max_luminance",
+            UniformValue::Float(tone_mapping.max_luminance),
+        );
+        f(
+            "adapted_luminance",
+            UniformValue::Texture2d(tone_mapping.get_adapted_luminance(), None),
+        );
     }
 }
 
@@ -392,6 +3384,17 @@ pub enum PostProcessingEffects {
     Edged,
 }
 
+// the bloom is added to the hdr scene before the tone mapping and the other effects
+pub struct BloomSettings {
+    pub enabled: bool,
+    // how much of the glow is added to the scene
+    pub intensity: f32,
+    // brightness where the scene starts to glow, before the exposure
+    pub threshold: f32,
+    // spread of the blur in texels of every bloom texture
+    pub radius: f32,
+}
+
 impl MainFramebufferProgram {
     pub fn new(
         display: &Display,
@@ -418,21 +3421,21 @@ impl MainFramebufferProgram {
             PostProcessingEffects::NoPostProcessing => {
                 r#"
                 void main() {
-                    out_color = texture(screen_texture, v_tex_coords);
+                    out_color = get_screen_color(v_tex_coords);
                 }
             "#
             }
             PostProcessingEffects::Inversed => {
                 r#"
                 void main() {
-                    out_color = vec4(vec3(1.0 - texture(screen_texture, v_tex_coords)), 1.0);
+                    out_color = vec4(vec3(1.0 - get_screen_color(v_tex_coords)), 1.0);
                 }
             "#
             }
             PostProcessingEffects::GrayScale => {
                 r#"
                 void main() {
-                    out_color = texture(screen_texture, v_tex_coords);
+                    out_color = get_screen_color(v_tex_coords);
                     float average = 0.2126 * out_color.r + 0.7152 * out_color.g + 0.0722 * out_color.b;
                     out_color = vec4(average, average, average, 1.0);
                 }
@@ -471,6 +3474,57 @@ impl MainFramebufferProgram {
 
             uniform sampler2D screen_texture;
 
+            // the scene is in hdr, it gets exposed and tone mapped before the effects
+            uniform int tone_mapping_operator;
+            uniform bool auto_exposure;
+            uniform float exposure;
+            uniform float key_value;
+            uniform float min_luminance;
+            uniform float max_luminance;
+            uniform sampler2D adapted_luminance;
+
+            vec3 tone_map_filmic_curve(vec3 x) {{
+                const float a = 0.15;
+                const float b = 0.50;
+                const float c = 0.10;
+                const float d = 0.20;
+                const float e = 0.02;
+                const float f = 0.30;
+                return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+            }}
+
+            vec3 tone_map(vec3 color) {{
+                switch (tone_mapping_operator) {{
+                    // reinhard
+                    case 0: return color / (color + 1.0);
+                    // aces approximation by Krzysztof Narkowicz
+                    case 1: return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+                    // uncharted 2 filmic curve by John Hable
+                    default: return tone_map_filmic_curve(color * 2.0) / tone_map_filmic_curve(vec3(11.2));
+                }}
+            }}
+
+            float get_exposure() {{
+                if (!auto_exposure) {{
+                    return exposure;
+                }}
+                float luminance = clamp(texelFetch(adapted_luminance, ivec2(0), 0).r, min_luminance, max_luminance);
+                return key_value / luminance;
+            }}
+
+            uniform bool bloom_enabled;
+            uniform float bloom_intensity;
+            uniform sampler2D bloom_texture;
+
+            vec4 get_screen_color(vec2 tex_coords) {{
+                vec3 color = texture(screen_texture, tex_coords).rgb;
+                if (bloom_enabled) {{
+                    color += texture(bloom_texture, tex_coords).rgb * bloom_intensity;
+                }}
+                color *= get_exposure();
+                return vec4(tone_map(color), 1.0);
+            }}
+
             {}
         "#,
             main_code
@@ -481,10 +3535,16 @@ impl MainFramebufferProgram {
         )
     }
 
-    pub fn get_uniforms<'a, 'b>(
+    pub fn get_uniforms<'a, 'b, 'c>(
         screen_texture: &'a glium::texture::Texture2d,
-    ) -> MainFramebufferProgramUniforms<'a> {
-        MainFramebufferProgramUniforms { screen_texture }
+        tone_mapping: &'b ToneMapping,
+        bloom: &'c Bloom,
+    ) -> MainFramebufferProgramUniforms<'a, 'b, 'c> {
+        MainFramebufferProgramUniforms {
+            screen_texture,
+            tone_mapping,
+            bloom,
+        }
     }
 }
 
@@ -529,7 +3589,7 @@ fn from_kernel_template(kernel: [[f32; 3]; 3]) -> String {
 
         vec3 sampleTex[9];
         for(int i = 0; i < 9; i++) {{
-            sampleTex[i] = vec3(texture(screen_texture, v_tex_coords.st +
+            sampleTex[i] = vec3(get_screen_color(v_tex_coords.st +
             offsets[i]));
         }}
         vec3 col = vec3(0.0);
@@ -544,10 +3604,65 @@ fn from_kernel_template(kernel: [[f32; 3]; 3]) -> String {
 
 pub struct SkyBoxProgram(pub Program);
 
+pub struct SkyBoxUniforms<'a, 'b, 'c, 'd, 'e, 'f> {
+    matrix: &'a Matrix4<f32>,
+    cubemap: &'b glium::texture::Cubemap,
+    sky: &'c DayNightCycle,
+    fog: &'d Fog,
+    ibl: &'e ImageBasedLighting,
+    view: &'f Point3<f32>,
+}
+
+impl glium::uniforms::Uniforms for SkyBoxUniforms<'_, '_, '_, '_, '_, '_> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+        f("matrix", UniformValue::Mat4(self.matrix.to_array()));
+        f(
+            "cubetex",
+            UniformValue::Cubemap(
+                self.cubemap,
+                Some(SamplerBehavior {
+                    magnify_filter: MagnifySamplerFilter::Linear,
+                    ..Default::default()
+                }),
+            ),
+        );
+        f("sky_mode", UniformValue::SignedInt(self.sky.mode as i32));
+        f(
+            "sun_position",
+            UniformValue::Vec3(self.sky.get_sun_position().into()),
+        );
+        f(
+            "moon_position",
+            UniformValue::Vec3(self.sky.get_moon_position().into()),
+        );
+        f(
+            "sun_colour",
+            UniformValue::Vec3(self.sky.get_sun_colour().into()),
+        );
+        f("daylight", UniformValue::Float(self.sky.get_daylight()));
+        f(
+            "sky_brightness",
+            UniformValue::Float(self.sky.get_sky_brightness()),
+        );
+        f("turbidity", UniformValue::Float(self.sky.turbidity));
+        f(
+            "star_rotation",
+            UniformValue::Mat3(self.sky.get_star_rotation().into()),
+        );
+
+        visit_fog_uniforms(self.fog, self.ibl, &mut f);
+        f("fog_sky_distance", UniformValue::Float(self.fog.sky_distance));
+        f(
+            "view_pos",
+            UniformValue::Vec3([self.view.x, self.view.y, self.view.z]),
+        );
+    }
+}
+
 impl SkyBoxProgram {
     pub fn new(display: &Display) -> SkyBoxProgram {
         let vertex_shader_src = r#"
-            #version 140
+            #version 330 core
 
             in vec3 position;
 
@@ -562,22 +3677,147 @@ impl SkyBoxProgram {
             }
         "#;
 
-        let fragment_shader_src = r#"
-            #version 140
+        let fragment_shader_src = [
+            r#"
+            #version 330 core
 
             in vec3 texture_coords;
 
             out vec4 out_color;
 
             uniform samplerCube cubetex;
+            uniform vec3 view_pos;
+            uniform float fog_sky_distance;
+
+            // 0 procedural, 1 procedural above the mountains of the skybox, 2 skybox
+            uniform int sky_mode;
+            uniform vec3 sun_position;
+            uniform vec3 moon_position;
+            uniform vec3 sun_colour;
+            uniform float daylight;
+            uniform float sky_brightness;
+            uniform float turbidity;
+            uniform mat3 star_rotation;
+
+            const float PI = 3.14159265359;
+            "#,
+            FOG_SHADER_SRC,
+            r#"
+            // brings the luminance of the sky model (in kcd/m2) close to the skybox
+            const float LUMINANCE_SCALE = 0.06;
+
+            // perez et al. distribution of the luminance for the angle to the zenith and to the sun
+            float perez(float theta, float gamma, float A, float B, float C, float D, float E) {
+                return (1.0 + A * exp(B / max(cos(theta), 0.01)))
+                    * (1.0 + C * exp(D * gamma) + E * cos(gamma) * cos(gamma));
+            }
+
+            // preetham et al. "a practical analytic model for daylight"
+            vec3 preetham_sky(vec3 direction, vec3 sun) {
+                float T = turbidity;
+                // the sun is kept on the horizon during the night, the sky is faded out by daylight
+                float theta_s = acos(clamp(sun.y, 0.0, 1.0));
+                float theta = acos(clamp(direction.y, 0.001, 1.0));
+                float gamma = acos(clamp(dot(direction, normalize(vec3(sun.x, max(sun.y, 0.0), sun.z))), -1.0, 1.0));
+
+                float chi = (4.0 / 9.0 - T / 120.0) * (PI - 2.0 * theta_s);
+                float zenith_Y = (4.0453 * T - 4.9710) * tan(chi) - 0.2155 * T + 2.4192;
+                vec3 theta_s_powers = vec3(theta_s * theta_s * theta_s, theta_s * theta_s, theta_s);
+                float zenith_x = T * T * dot(vec3(0.00166, -0.00375, 0.00209), theta_s_powers)
+                    + T * (dot(vec3(-0.02903, 0.06377, -0.03202), theta_s_powers) + 0.00394)
+                    + dot(vec3(0.11693, -0.21196, 0.06052), theta_s_powers) + 0.25886;
+                float zenith_y = T * T * dot(vec3(0.00275, -0.00610, 0.00317), theta_s_powers)
+                    + T * (dot(vec3(-0.04214, 0.08970, -0.04153), theta_s_powers) + 0.00516)
+                    + dot(vec3(0.15346, -0.26756, 0.06670), theta_s_powers) + 0.26688;
+
+                float Y = zenith_Y
+                    * perez(theta, gamma, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703)
+                    / perez(0.0, theta_s, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703);
+                float x = zenith_x
+                    * perez(theta, gamma, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452)
+                    / perez(0.0, theta_s, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452);
+                float y = zenith_y
+                    * perez(theta, gamma, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529)
+                    / perez(0.0, theta_s, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529);
+
+                // Yxy to XYZ to linear rgb
+                Y *= LUMINANCE_SCALE;
+                vec3 XYZ = vec3(x * Y / y, Y, (1.0 - x - y) * Y / y);
+                vec3 rgb = mat3(
+                    3.2406, -0.9689, 0.0557,
+                    -1.5372, 1.8758, -0.2040,
+                    -0.4986, 0.0415, 1.0570
+                ) * XYZ;
+                return max(rgb, vec3(0.0));
+            }
+
+            float hash(vec3 p) {
+                p = fract(p * 0.3183099 + 0.1);
+                p *= 17.0;
+                return fract(p.x * p.y * p.z * (p.x + p.y + p.z));
+            }
+
+            // a few random cells of a grid around the sky, rotating with the time of day
+            vec3 get_stars(vec3 direction) {
+                vec3 cell = floor(star_rotation * direction * 500.0);
+                float star = hash(cell);
+                float brightness = step(0.998, star) * (star - 0.998) * 500.0;
+                return vec3(0.9, 0.95, 1.0) * brightness * 2.0;
+            }
+
+            vec3 get_procedural_sky(vec3 direction) {
+                float above_horizon = step(0.0, direction.y);
+                vec3 night = vec3(0.004, 0.006, 0.015)
+                    + get_stars(direction) * above_horizon * pow(1.0 - daylight, 4.0);
+                vec3 color = mix(night, preetham_sky(direction, sun_position) * sky_brightness, daylight);
+
+                // the sun and the moon disks, bright enough for the bloom
+                float sun_disk = smoothstep(0.9998, 0.99995, dot(direction, sun_position));
+                color += sun_colour * sun_disk * 40.0 * above_horizon;
+                float moon_disk = smoothstep(0.9994, 0.9996, dot(direction, moon_position));
+                color += vec3(0.8, 0.85, 0.9) * moon_disk * above_horizon;
+                return color;
+            }
 
             void main() {
-                out_color = texture(cubetex, texture_coords);
+                vec3 direction = normalize(texture_coords);
+                vec3 skybox = texture(cubetex, texture_coords).rgb * sky_brightness;
+
+                vec3 color;
+                if (sky_mode == 0) {
+                    color = get_procedural_sky(direction);
+                } else if (sky_mode == 1) {
+                    color = mix(skybox, get_procedural_sky(direction), smoothstep(0.12, 0.3, direction.y));
+                } else {
+                    color = skybox;
+                }
+                color = apply_fog(color, view_pos + direction * fog_sky_distance, view_pos);
+                out_color = vec4(color, 1.0);
             }
-        "#;
+        "#,
+        ]
+        .concat();
 
         SkyBoxProgram(
             Program::from_source(display, vertex_shader_src, &fragment_shader_src, None).unwrap(),
         )
     }
+
+    pub fn get_uniforms<'a, 'b, 'c, 'd, 'e, 'f>(
+        matrix: &'a Matrix4<f32>,
+        cubemap: &'b glium::texture::Cubemap,
+        sky: &'c DayNightCycle,
+        fog: &'d Fog,
+        ibl: &'e ImageBasedLighting,
+        view: &'f Point3<f32>,
+    ) -> SkyBoxUniforms<'a, 'b, 'c, 'd, 'e, 'f> {
+        SkyBoxUniforms {
+            matrix,
+            cubemap,
+            sky,
+            fog,
+            ibl,
+            view,
+        }
+    }
 }
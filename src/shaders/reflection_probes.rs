@@ -0,0 +1,205 @@
+use cgmath::{perspective, Matrix4, MetricSpace, Point3, Rad, Vector3};
+use glium::{
+    framebuffer::{DepthRenderBuffer, SimpleFrameBuffer},
+    texture::{cubemap::Cubemap, CubeLayer, DepthFormat, MipmapsOption, UncompressedFloatFormat},
+    Display,
+};
+
+use crate::physics::scene_query::Aabb;
+
+const NEAR_PLANE: f32 = 0.1;
+// has to reach the skybox
+const FAR_PLANE: f32 = 2500.0;
+
+// the faces in the order they are updated, following the OpenGL cubemap conventions
+pub const FACES: [CubeLayer; 6] = [
+    CubeLayer::PositiveX,
+    CubeLayer::NegativeX,
+    CubeLayer::PositiveY,
+    CubeLayer::NegativeY,
+    CubeLayer::PositiveZ,
+    CubeLayer::NegativeZ,
+];
+
+// projection * view matrix of a cubemap face seen from a point, the same as the point shadow maps
+pub fn get_cube_face_matrix(
+    position: Point3<f32>,
+    layer: CubeLayer,
+    near_plane: f32,
+    far_plane: f32,
+) -> Matrix4<f32> {
+    let (direction, up) = match layer {
+        CubeLayer::PositiveX => (Vector3::unit_x(), -Vector3::unit_y()),
+        CubeLayer::NegativeX => (-Vector3::unit_x(), -Vector3::unit_y()),
+        CubeLayer::PositiveY => (Vector3::unit_y(), Vector3::unit_z()),
+        CubeLayer::NegativeY => (-Vector3::unit_y(), -Vector3::unit_z()),
+        CubeLayer::PositiveZ => (Vector3::unit_z(), -Vector3::unit_y()),
+        CubeLayer::NegativeZ => (-Vector3::unit_z(), -Vector3::unit_y()),
+    };
+    let projection = perspective(Rad(std::f32::consts::FRAC_PI_2), 1.0, near_plane, far_plane);
+    projection * Matrix4::look_at_rh(position, position + direction, up)
+}
+
+// what the environment mapped materials show, the sky is infinitely far away so it isn't
+// projected on a box
+#[derive(Clone, Copy)]
+pub struct EnvironmentMap<'a> {
+    pub cubemap: &'a Cubemap,
+    // position of the probe and the box its reflections are projected on
+    pub box_projection: Option<(Point3<f32>, Aabb)>,
+}
+
+impl<'a> EnvironmentMap<'a> {
+    pub fn new(sky: &'a Cubemap) -> Self {
+        Self {
+            cubemap: sky,
+            box_projection: None,
+        }
+    }
+}
+
+// the scene drawn into a cubemap from a point, for the environment mapped materials around it
+pub struct ReflectionProbe {
+    pub position: Point3<f32>,
+    // the materials inside use this probe, the reflections are projected on it so it should
+    // follow the walls around the probe
+    pub bounds: Aabb,
+    pub cubemap: Cubemap,
+    dirty_faces: [bool; 6],
+    // the probe can't be used until every face has been drawn once
+    rendered_faces: [bool; 6],
+}
+
+impl ReflectionProbe {
+    pub fn is_rendered(&self) -> bool {
+        self.rendered_faces.iter().all(|rendered| *rendered)
+    }
+}
+
+pub struct ReflectionProbes {
+    pub probes: Vec<ReflectionProbe>,
+    // when false the probes are only drawn after request_update
+    pub round_robin: bool,
+    // faces drawn every frame by the round robin, every probe is updated in
+    // probes * 6 / faces_per_frame frames
+    pub faces_per_frame: usize,
+    next_face: usize,
+    resolution: u32,
+    // shared by every face, they are drawn one after the other
+    depth_buffer: DepthRenderBuffer,
+}
+
+impl ReflectionProbes {
+    pub fn new(display: &Display, resolution: u32, faces_per_frame: usize) -> Self {
+        Self {
+            probes: Vec::new(),
+            round_robin: true,
+            faces_per_frame,
+            next_face: 0,
+            resolution,
+            depth_buffer: DepthRenderBuffer::new(display, DepthFormat::I24, resolution, resolution)
+                .unwrap(),
+        }
+    }
+
+    pub fn add_probe(&mut self, display: &Display, position: Point3<f32>, bounds: Aabb) {
+        self.probes.push(ReflectionProbe {
+            position,
+            bounds,
+            // hdr like the scene, the tone mapping happens after the reflections
+            cubemap: Cubemap::empty_with_format(
+                display,
+                UncompressedFloatFormat::F16F16F16F16,
+                MipmapsOption::NoMipmap,
+                self.resolution,
+            )
+            .unwrap(),
+            dirty_faces: [true; 6],
+            rendered_faces: [false; 6],
+        });
+    }
+
+    // every face of every probe is drawn in the next frame
+    pub fn request_update(&mut self) {
+        for probe in self.probes.iter_mut() {
+            probe.dirty_faces = [true; 6];
+        }
+    }
+
+    // the requested faces and the next ones of the round robin, as (probe index, face)
+    pub fn get_faces_to_update(&mut self) -> Vec<(usize, CubeLayer)> {
+        let mut faces = Vec::new();
+        for (probe_index, probe) in self.probes.iter().enumerate() {
+            for (face_index, dirty) in probe.dirty_faces.iter().enumerate() {
+                if *dirty {
+                    faces.push((probe_index, face_index));
+                }
+            }
+        }
+
+        let face_count = self.probes.len() * FACES.len();
+        if self.round_robin && face_count > 0 {
+            for _ in 0..self.faces_per_frame.min(face_count) {
+                let face = (self.next_face / FACES.len(), self.next_face % FACES.len());
+                if !faces.contains(&face) {
+                    faces.push(face);
+                }
+                self.next_face = (self.next_face + 1) % face_count;
+            }
+        }
+
+        for (probe_index, face_index) in faces.iter() {
+            let probe = &mut self.probes[*probe_index];
+            probe.dirty_faces[*face_index] = false;
+            probe.rendered_faces[*face_index] = true;
+        }
+        faces
+            .into_iter()
+            .map(|(probe_index, face_index)| (probe_index, FACES[face_index]))
+            .collect()
+    }
+
+    pub fn get_face_matrix(&self, probe_index: usize, layer: CubeLayer) -> Matrix4<f32> {
+        get_cube_face_matrix(
+            self.probes[probe_index].position,
+            layer,
+            NEAR_PLANE,
+            FAR_PLANE,
+        )
+    }
+
+    pub fn get_framebuffer<'a>(
+        &'a self,
+        display: &Display,
+        probe_index: usize,
+        layer: CubeLayer,
+    ) -> SimpleFrameBuffer<'a> {
+        SimpleFrameBuffer::with_depth_buffer(
+            display,
+            self.probes[probe_index].cubemap.main_level().image(layer),
+            &self.depth_buffer,
+        )
+        .unwrap()
+    }
+
+    // the closest probe whose box has the point, or the sky when there's none
+    pub fn get_environment_map<'a>(
+        &'a self,
+        position: Point3<f32>,
+        sky: &'a Cubemap,
+    ) -> EnvironmentMap<'a> {
+        self.probes
+            .iter()
+            .filter(|probe| probe.is_rendered() && probe.bounds.contains(position))
+            .min_by(|a, b| {
+                let a_distance = a.position.distance2(position);
+                let b_distance = b.position.distance2(position);
+                a_distance.partial_cmp(&b_distance).unwrap()
+            })
+            .map(|probe| EnvironmentMap {
+                cubemap: &probe.cubemap,
+                box_projection: Some((probe.position, probe.bounds)),
+            })
+            .unwrap_or_else(|| EnvironmentMap::new(sky))
+    }
+}
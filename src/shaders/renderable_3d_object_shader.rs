@@ -1,7 +1,11 @@
 use crate::shaders::common::Vertex3d;
+use cgmath::{Point3, Vector3};
 use glium::{Display, VertexBuffer};
 
-use crate::shaders::common::{load_srgb_texture, Material};
+use crate::shaders::common::{
+    create_flat_normal_map, create_white_srgb_texture, create_white_texture, load_srgb_texture,
+    EnvironmentMapping, Material, ObjectMaterial, PbrMaterial,
+};
 use obj::{load_obj, Obj};
 use std::fs::File;
 use std::io::BufReader;
@@ -9,7 +13,11 @@ use std::io::BufReader;
 pub struct Renderable3dObjectShader {
     pub vertex_buffer: VertexBuffer<Vertex3d>,
     pub index_buffer: glium::IndexBuffer<u16>,
-    pub material: Material,
+    pub material: ObjectMaterial,
+    // middle of the bounding box of the mesh, in model space
+    pub center: Point3<f32>,
+    // the mesh is also kept on the cpu for the mesh colliders, in model space
+    pub triangles: Vec<[Point3<f32>; 3]>,
 }
 
 impl Renderable3dObjectShader {
@@ -18,21 +26,77 @@ impl Renderable3dObjectShader {
         model_path: &str,
         texture_bytes: &dyn std::convert::AsRef<[u8]>,
     ) -> Self {
-        let input = BufReader::new(File::open(model_path).unwrap());
-        let obj: Obj<Vertex3d> = load_obj(input).unwrap();
-
-        Self {
-            vertex_buffer: obj.vertex_buffer(display).unwrap(),
-            index_buffer: obj.index_buffer(display).unwrap(),
-            material: Material {
+        Self::from_material(
+            display,
+            model_path,
+            ObjectMaterial::Phong(Material {
                 diffuse: load_srgb_texture(display, texture_bytes, image::ImageFormat::Png),
                 specular: load_srgb_texture(
                     display,
                     &include_bytes!("../../assets/black_picture.png"),
                     image::ImageFormat::Png,
                 ),
+                normal: create_flat_normal_map(display),
+                parallax: None,
+                empty_height: create_white_texture(display),
+                environment: None,
                 shininess: 32.0,
+                emissive: create_white_srgb_texture(display),
+                emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+                emissive_light: None,
+            }),
+        )
+    }
+
+    pub fn new_pbr(display: &Display, model_path: &str, material: PbrMaterial) -> Self {
+        Self::from_material(display, model_path, ObjectMaterial::Pbr(material))
+    }
+
+    // the pbr material already reflects the skybox with the image based lighting, so only the phong
+    // one is environment mapped
+    pub fn set_environment_mapping(&mut self, environment: Option<EnvironmentMapping>) {
+        if let ObjectMaterial::Phong(material) = &mut self.material {
+            material.environment = environment;
+        }
+    }
+
+    fn from_material(display: &Display, model_path: &str, material: ObjectMaterial) -> Self {
+        let input = BufReader::new(File::open(model_path).unwrap());
+        let obj: Obj<Vertex3d> = load_obj(input).unwrap();
+
+        let (min, max) = obj.vertices.iter().fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(min, max), vertex| {
+                let p = vertex.position;
+                (
+                    [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                    [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+                )
             },
+        );
+
+        let triangles = obj
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let mut points = [Point3::new(0.0, 0.0, 0.0); 3];
+                for (point, &i) in points.iter_mut().zip(triangle.iter()) {
+                    *point = Point3::from(obj.vertices[i as usize].position);
+                }
+                points
+            })
+            .collect();
+
+        Self {
+            vertex_buffer: obj.vertex_buffer(display).unwrap(),
+            index_buffer: obj.index_buffer(display).unwrap(),
+            material,
+            center: Point3::new(
+                (min[0] + max[0]) / 2.0,
+                (min[1] + max[1]) / 2.0,
+                (min[2] + max[2]) / 2.0,
+            ),
+            triangles,
         }
     }
 }
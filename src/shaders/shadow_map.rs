@@ -0,0 +1,520 @@
+use cgmath::{
+    ortho, perspective, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Transform, Vector3,
+};
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{
+        CubeLayer, DepthCubemap, DepthFormat, DepthTexture2d, MipmapsOption, RawImage2d,
+        Texture2d,
+    },
+    uniforms::{
+        DepthTextureComparison, MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior,
+        SamplerWrapFunction,
+    },
+    Display,
+};
+
+use std::rc::Rc;
+
+use crate::camera::Camera;
+use crate::shaders::common::{DirectionalLight, PointLight, SpotLight};
+
+// every shadow map sampled by the lit objects
+pub struct ShadowMaps {
+    pub directional: DirectionalShadowMap,
+    pub point: PointShadowMaps,
+    pub spot: SpotShadowMaps,
+}
+
+impl ShadowMaps {
+    // decides which lights get a shadow map, this has to be done before the depth passes
+    pub fn assign_lights(
+        &mut self,
+        directional_lights: &[&DirectionalLight],
+        point_lights: &[&PointLight],
+        spot_lights: &[&SpotLight],
+    ) {
+        // the first light that casts shadows gets the directional map
+        self.directional.light_index = directional_lights
+            .iter()
+            .position(|light| light.casts_shadows);
+        self.point.assign_lights(point_lights);
+        self.spot.assign_lights(spot_lights);
+    }
+}
+
+// shadow map for the sun, it only covers the part of the scene close to the camera
+pub struct DirectionalShadowMap {
+    pub depth_texture: DepthTexture2d,
+    pub resolution: u32,
+    // radius in texels of the percentage-closer filter, 0 gives hard shadows
+    pub filter_size: i32,
+    // how far from the camera the shadows are visible
+    pub distance: f32,
+    // how far behind the visible area objects can still cast shadows into it
+    pub caster_distance: f32,
+    // depth offsets used while rendering the depth pass to avoid shadow acne
+    pub constant_bias: f32,
+    pub slope_bias: f32,
+    pub light_space_matrix: Matrix4<f32>,
+    // only one directional light can have shadows
+    pub light_index: Option<usize>,
+}
+
+impl DirectionalShadowMap {
+    pub fn new(display: &Display, resolution: u32, filter_size: i32) -> Self {
+        Self {
+            depth_texture: Self::create_depth_texture(display, resolution),
+            resolution,
+            filter_size,
+            distance: 40.0,
+            caster_distance: 60.0,
+            constant_bias: 4.0,
+            slope_bias: 2.0,
+            light_space_matrix: Matrix4::from_scale(1.0),
+            light_index: None,
+        }
+    }
+
+    fn create_depth_texture(display: &Display, resolution: u32) -> DepthTexture2d {
+        DepthTexture2d::empty_with_format(
+            display,
+            DepthFormat::I24,
+            MipmapsOption::NoMipmap,
+            resolution,
+            resolution,
+        )
+        .unwrap()
+    }
+
+    pub fn set_resolution(&mut self, display: &Display, resolution: u32) {
+        self.depth_texture = Self::create_depth_texture(display, resolution);
+        self.resolution = resolution;
+    }
+
+    // fits an orthographic projection around the camera frustum, up to the shadow distance
+    pub fn fit_to_camera(
+        &mut self,
+        camera: &Camera,
+        aspect_ratio: f32,
+        light_direction: Vector3<f32>,
+    ) {
+        // bounding sphere of the frustum, its size doesn't change when the camera rotates
+        // so the shadow edges don't shimmer
+        let far_height = (camera.fov / 2.0).tan() * self.distance;
+        let far_width = far_height * aspect_ratio;
+        let far_extent = far_width * far_width + far_height * far_height;
+        let center_distance = ((self.distance * self.distance + far_extent)
+            / (2.0 * self.distance))
+            .min(self.distance);
+        let radius = (center_distance * center_distance)
+            .max((self.distance - center_distance).powi(2) + far_extent)
+            .sqrt();
+        let center = camera.position + camera.front * center_distance;
+
+        let direction = light_direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let light_view = Matrix4::look_at_rh(Point3::origin(), Point3::from_vec(direction), up);
+
+        // move the projection by whole texels only, for the same reason
+        let texel_size = 2.0 * radius / self.resolution as f32;
+        let light_center = light_view.transform_point(center);
+        let x = (light_center.x / texel_size).floor() * texel_size;
+        let y = (light_center.y / texel_size).floor() * texel_size;
+
+        let projection = ortho(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -light_center.z - radius - self.caster_distance,
+            -light_center.z + radius,
+        );
+        self.light_space_matrix = projection * light_view;
+    }
+
+    pub fn get_framebuffer<'a>(&'a self, display: &Display) -> SimpleFrameBuffer<'a> {
+        SimpleFrameBuffer::depth_only(display, &self.depth_texture).unwrap()
+    }
+
+    pub fn get_draw_parameters(&self) -> glium::DrawParameters<'static> {
+        glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            polygon_offset: glium::draw_parameters::PolygonOffset {
+                factor: self.slope_bias,
+                units: self.constant_bias,
+                fill: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    // hardware depth comparison, with linear filtering every tap is already smoothed
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            depth_texture_comparison: Some(DepthTextureComparison::LessOrEqual),
+            ..Default::default()
+        }
+    }
+}
+
+// a few depth cubemaps shared by the point lights that cast shadows, rendered like the skybox
+// cubemap, one face at a time
+pub struct PointShadowMaps {
+    pub cubemaps: Vec<DepthCubemap>,
+    // point light using each cubemap
+    pub light_indices: Vec<Option<usize>>,
+    // maps that have been rendered at least once, the others can't be sampled yet
+    rendered: Vec<bool>,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    // size of the soft shadow penumbra, in world units at one unit from the light
+    pub filter_radius: f32,
+    // how many cubemaps can be rendered every frame, the rest keep the old shadows
+    pub update_budget: usize,
+    next_update: usize,
+    pub constant_bias: f32,
+    pub slope_bias: f32,
+}
+
+impl PointShadowMaps {
+    pub fn new(
+        display: &Display,
+        slot_count: usize,
+        resolution: u32,
+        update_budget: usize,
+    ) -> Self {
+        Self {
+            cubemaps: (0..slot_count)
+                .map(|_| {
+                    DepthCubemap::empty_with_format(
+                        display,
+                        DepthFormat::I24,
+                        MipmapsOption::NoMipmap,
+                        resolution,
+                    )
+                    .unwrap()
+                })
+                .collect(),
+            light_indices: vec![None; slot_count],
+            rendered: vec![false; slot_count],
+            near_plane: 0.1,
+            far_plane: 40.0,
+            filter_radius: 0.02,
+            update_budget,
+            next_update: 0,
+            constant_bias: 4.0,
+            slope_bias: 2.0,
+        }
+    }
+
+    // the first lights that cast shadows get the cubemaps, the others don't have shadows
+    pub fn assign_lights(&mut self, lights: &[&PointLight]) {
+        let mut casters = lights
+            .iter()
+            .enumerate()
+            .filter(|(_, light)| light.casts_shadows)
+            .map(|(light_index, _)| light_index);
+        for slot in 0..self.cubemaps.len() {
+            let light_index = casters.next();
+            if self.light_indices[slot] != light_index {
+                self.light_indices[slot] = light_index;
+                self.rendered[slot] = false;
+            }
+        }
+    }
+
+    // cubemap that can be sampled for the shadows of a light
+    pub fn get_slot(&self, light_index: usize) -> Option<usize> {
+        (0..self.cubemaps.len())
+            .find(|slot| self.light_indices[*slot] == Some(light_index) && self.rendered[*slot])
+    }
+
+    // round robin over the used cubemaps, at most update_budget of them, returns the cubemap
+    // and the light it belongs to
+    pub fn get_lights_to_update(&mut self) -> Vec<(usize, usize)> {
+        let slot_count = self.cubemaps.len();
+        let mut lights = Vec::new();
+        for i in 0..slot_count {
+            if lights.len() >= self.update_budget {
+                break;
+            }
+            let slot = (self.next_update + i) % slot_count;
+            if let Some(light_index) = self.light_indices[slot] {
+                lights.push((slot, light_index));
+            }
+        }
+        if let Some((last, _)) = lights.last() {
+            self.next_update = (last + 1) % slot_count;
+        }
+        for (slot, _) in lights.iter() {
+            self.rendered[*slot] = true;
+        }
+        lights
+    }
+
+    // projection * view matrix of every face, following the OpenGL cubemap conventions
+    pub fn get_face_matrices(&self, light_position: Point3<f32>) -> [(CubeLayer, Matrix4<f32>); 6] {
+        let projection = perspective(
+            Rad(std::f32::consts::FRAC_PI_2),
+            1.0,
+            self.near_plane,
+            self.far_plane,
+        );
+        let face = |layer, direction: Vector3<f32>, up: Vector3<f32>| {
+            let view = Matrix4::look_at_rh(light_position, light_position + direction, up);
+            (layer, projection * view)
+        };
+
+        [
+            face(CubeLayer::PositiveX, Vector3::unit_x(), -Vector3::unit_y()),
+            face(CubeLayer::NegativeX, -Vector3::unit_x(), -Vector3::unit_y()),
+            face(CubeLayer::PositiveY, Vector3::unit_y(), Vector3::unit_z()),
+            face(CubeLayer::NegativeY, -Vector3::unit_y(), -Vector3::unit_z()),
+            face(CubeLayer::PositiveZ, Vector3::unit_z(), -Vector3::unit_y()),
+            face(CubeLayer::NegativeZ, -Vector3::unit_z(), -Vector3::unit_y()),
+        ]
+    }
+
+    pub fn get_framebuffer<'a>(
+        &'a self,
+        display: &Display,
+        slot: usize,
+        layer: CubeLayer,
+    ) -> SimpleFrameBuffer<'a> {
+        SimpleFrameBuffer::depth_only(
+            display,
+            self.cubemaps[slot].main_level().image(layer),
+        )
+        .unwrap()
+    }
+
+    pub fn get_draw_parameters(&self) -> glium::DrawParameters<'static> {
+        glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            polygon_offset: glium::draw_parameters::PolygonOffset {
+                factor: self.slope_bias,
+                units: self.constant_bias,
+                fill: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            depth_texture_comparison: Some(DepthTextureComparison::LessOrEqual),
+            ..Default::default()
+        }
+    }
+}
+
+// perspective shadow maps shared by the spot lights like the point shadow cubemaps, the cookie
+// of a light is projected with the same matrix to give the light a pattern, so the lights with a
+// cookie get a slot even if they don't cast shadows
+pub struct SpotShadowMaps {
+    pub depth_textures: Vec<DepthTexture2d>,
+    // spot light using each slot
+    pub light_indices: Vec<Option<usize>>,
+    pub light_space_matrices: Vec<Matrix4<f32>>,
+    // whether the light of each slot casts shadows, only those maps are rendered
+    pub shadows: Vec<bool>,
+    pub cookies: Vec<Option<Rc<Texture2d>>>,
+    // bound to the slots without a cookie, the other cookies don't show if one of the array is
+    // left unbound
+    pub empty_cookie: Texture2d,
+    // turns the shadows of every spot light off, the cookies stay
+    pub enabled: bool,
+    // radius in texels of the percentage-closer filter
+    pub filter_size: i32,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    pub constant_bias: f32,
+    pub slope_bias: f32,
+}
+
+impl SpotShadowMaps {
+    pub fn new(display: &Display, slot_count: usize, resolution: u32, filter_size: i32) -> Self {
+        Self {
+            depth_textures: (0..slot_count)
+                .map(|_| {
+                    DepthTexture2d::empty_with_format(
+                        display,
+                        DepthFormat::I24,
+                        MipmapsOption::NoMipmap,
+                        resolution,
+                        resolution,
+                    )
+                    .unwrap()
+                })
+                .collect(),
+            light_indices: vec![None; slot_count],
+            light_space_matrices: vec![Matrix4::from_scale(1.0); slot_count],
+            shadows: vec![false; slot_count],
+            cookies: vec![None; slot_count],
+            empty_cookie: Texture2d::new(
+                display,
+                RawImage2d::from_raw_rgba(vec![255u8; 4], (1, 1)),
+            )
+            .unwrap(),
+            enabled: true,
+            filter_size,
+            near_plane: 0.1,
+            far_plane: 50.0,
+            constant_bias: 4.0,
+            slope_bias: 2.0,
+        }
+    }
+
+    // the first lights with shadows or a cookie get the slots, the maps follow them every frame
+    pub fn assign_lights(&mut self, lights: &[&SpotLight]) {
+        let mut lights = lights
+            .iter()
+            .enumerate()
+            .filter(|(_, light)| light.casts_shadows || light.cookie.is_some());
+        for slot in 0..self.depth_textures.len() {
+            match lights.next() {
+                Some((light_index, light)) => {
+                    self.light_indices[slot] = Some(light_index);
+                    self.light_space_matrices[slot] = self.get_light_space_matrix(light);
+                    self.shadows[slot] = light.casts_shadows && self.enabled;
+                    self.cookies[slot] = light.cookie.clone();
+                }
+                None => {
+                    self.light_indices[slot] = None;
+                    self.shadows[slot] = false;
+                    self.cookies[slot] = None;
+                }
+            }
+        }
+    }
+
+    pub fn get_slot(&self, light_index: usize) -> Option<usize> {
+        self.light_indices
+            .iter()
+            .position(|index| *index == Some(light_index))
+    }
+
+    // slots whose depth has to be rendered this frame
+    pub fn get_shadow_slots(&self) -> Vec<usize> {
+        (0..self.depth_textures.len())
+            .filter(|slot| self.shadows[*slot])
+            .collect()
+    }
+
+    // the frustum covers the whole outer cone of the light
+    fn get_light_space_matrix(&self, light: &SpotLight) -> Matrix4<f32> {
+        let fov = 2.0 * light.outer_cut_off.acos();
+        let projection = perspective(Rad(fov), 1.0, self.near_plane, self.far_plane);
+
+        let direction = light.direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let view = Matrix4::look_at_rh(light.position, light.position + direction, up);
+        projection * view
+    }
+
+    pub fn get_framebuffer<'a>(&'a self, display: &Display, slot: usize) -> SimpleFrameBuffer<'a> {
+        SimpleFrameBuffer::depth_only(display, &self.depth_textures[slot]).unwrap()
+    }
+
+    pub fn get_draw_parameters(&self) -> glium::DrawParameters<'static> {
+        glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            polygon_offset: glium::draw_parameters::PolygonOffset {
+                factor: self.slope_bias,
+                units: self.constant_bias,
+                fill: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            depth_texture_comparison: Some(DepthTextureComparison::LessOrEqual),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_cookie_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        }
+    }
+}
+
+// beam pattern of a cheap flashlight: a bright core, a dark gap, a ring and a dim halo
+pub fn create_flashlight_cookie(display: &Display, size: u32) -> Texture2d {
+    let smoothstep = |edge0: f32, edge1: f32, x: f32| {
+        let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let r = (u * u + v * v).sqrt();
+
+            let core = 1.0 - smoothstep(0.3, 0.45, r);
+            let ring = (-((r - 0.62) / 0.06).powi(2)).exp();
+            let halo = 0.35 * (1.0 - smoothstep(0.45, 1.0, r));
+            let value = (core + 0.8 * ring + halo).min(1.0);
+
+            // slightly warm light
+            pixels.push((value * 255.0) as u8);
+            pixels.push((value * 242.0) as u8);
+            pixels.push((value * 217.0) as u8);
+            pixels.push(255);
+        }
+    }
+
+    Texture2d::new(display, RawImage2d::from_raw_rgba(pixels, (size, size))).unwrap()
+}
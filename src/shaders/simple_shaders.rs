@@ -1,6 +1,11 @@
+use cgmath::Vector3;
 use glium::{Display, VertexBuffer};
 
-use crate::shaders::common::{load_srgb_texture, Material, Vertex3d};
+use crate::shaders::common::{
+    create_flat_normal_map, create_white_srgb_texture, create_white_texture, generate_tangents,
+    load_srgb_texture, load_texture, EmissiveLight, EnvironmentMapping, EnvironmentMappingMode,
+    Material, ParallaxMap, Vertex3d,
+};
 
 pub struct CubeShader {
     pub vertex_buffer: VertexBuffer<Vertex3d>,
@@ -24,7 +29,112 @@ impl CubeShader {
                     &include_bytes!("../../assets/container2_specular.png"),
                     image::ImageFormat::Png
                 ),
+                normal: load_texture(
+                    display,
+                    &include_bytes!("../../assets/container2_normal.png"),
+                    image::ImageFormat::Png
+                ),
+                parallax: None,
+                empty_height: create_white_texture(display),
+                environment: None,
                 shininess: 32.0,
+                emissive: create_white_srgb_texture(display),
+                emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+                emissive_light: None,
+            },
+        }
+    }
+
+    // stone tiles with deep gaps between them
+    pub fn new_wall(display: &Display) -> Self {
+        Self {
+            vertex_buffer: Self::create_vertex_buffer(display),
+            index_buffer: glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+            material: Material {
+                diffuse: load_srgb_texture(
+                    display,
+                    &include_bytes!("../../assets/wall.png"),
+                    image::ImageFormat::Png,
+                ),
+                specular: load_srgb_texture(
+                    display,
+                    &include_bytes!("../../assets/black_picture.png"),
+                    image::ImageFormat::Png,
+                ),
+                normal: load_texture(
+                    display,
+                    &include_bytes!("../../assets/wall_normal.png"),
+                    image::ImageFormat::Png,
+                ),
+                parallax: Some(ParallaxMap {
+                    height: load_texture(
+                        display,
+                        &include_bytes!("../../assets/wall_height.png"),
+                        image::ImageFormat::Png,
+                    ),
+                    depth_scale: 0.04,
+                    layers: 32,
+                }),
+                empty_height: create_white_texture(display),
+                environment: None,
+                shininess: 16.0,
+                emissive: create_white_srgb_texture(display),
+                emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+                emissive_light: None,
+            },
+        }
+    }
+
+    // the container with mirror metal, the specular map keeps the wood from reflecting
+    pub fn new_chrome(display: &Display) -> Self {
+        let mut shader = Self::new(display);
+        shader.material.environment = Some(EnvironmentMapping {
+            mode: EnvironmentMappingMode::Reflective,
+            reflectivity: 0.9,
+            reflectivity_from_specular: true,
+            ior: 1.0,
+            tint: Vector3::new(1.0, 1.0, 1.0),
+        });
+        shader
+    }
+
+    // the metal frame of the container glows like a neon tube and lights the cubes around it
+    pub fn new_neon(display: &Display) -> Self {
+        let mut shader = Self::new(display);
+        let emissive = load_srgb_texture(
+            display,
+            &include_bytes!("../../assets/container2_specular.png"),
+            image::ImageFormat::Png,
+        );
+        let emissive_factor = Vector3::new(0.3, 2.0, 4.0);
+        shader.material.emissive_light = Some(EmissiveLight::new(&emissive, emissive_factor, 6.0));
+        shader.material.emissive = emissive;
+        shader.material.emissive_factor = emissive_factor;
+        shader
+    }
+
+    pub fn new_glass(display: &Display) -> Self {
+        Self {
+            vertex_buffer: Self::create_vertex_buffer(display),
+            index_buffer: glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+            material: Material {
+                diffuse: create_white_srgb_texture(display),
+                specular: create_white_srgb_texture(display),
+                normal: create_flat_normal_map(display),
+                parallax: None,
+                empty_height: create_white_texture(display),
+                // a bit of the lit colour is left for the highlights
+                environment: Some(EnvironmentMapping {
+                    mode: EnvironmentMappingMode::Refractive,
+                    reflectivity: 0.9,
+                    reflectivity_from_specular: false,
+                    ior: 1.52,
+                    tint: Vector3::new(0.85, 0.95, 0.9),
+                }),
+                shininess: 128.0,
+                emissive: create_white_srgb_texture(display),
+                emissive_factor: Vector3::new(0.0, 0.0, 0.0),
+                emissive_light: None,
             },
         }
     }
@@ -66,12 +176,16 @@ impl CubeShader {
                 position: [positions[pos_i], positions[pos_i + 1], positions[pos_i + 2]],
                 normal: [normals[pos_i], normals[pos_i + 1], normals[pos_i + 2]],
                 tex_coords: [tex_coords[tex_i], tex_coords[tex_i + 1]],
+                tangent: [0.0, 0.0, 0.0, 1.0],
             });
 
             pos_i += 3;
             tex_i += 2;
         }
 
+        let indices: Vec<usize> = (0..vertices.len()).collect();
+        generate_tangents(&mut vertices, &indices);
+
         vertices
     }
 
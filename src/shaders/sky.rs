@@ -0,0 +1,233 @@
+use cgmath::{InnerSpace, Matrix3, Point3, Rad, Vector3};
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{cubemap::Cubemap, MipmapsOption, UncompressedFloatFormat},
+    Display, Surface,
+};
+
+use std::f32::consts::PI;
+
+use crate::shaders::common::DirectionalLight;
+use crate::shaders::cubemap::CubeMapShader;
+use crate::shaders::fog::Fog;
+use crate::shaders::ibl::ImageBasedLighting;
+use crate::shaders::programs::SkyBoxProgram;
+use crate::shaders::reflection_probes::{get_cube_face_matrix, FACES};
+
+// how far the sun moves before the sky cubemap is drawn again, in radians
+const SKY_UPDATE_ANGLE: f32 = 0.02;
+// the same for a relative change of the brightness of the sky
+const SKY_UPDATE_BRIGHTNESS: f32 = 0.1;
+
+// how the procedural sky and the skybox cubemap are mixed, the order has to match SkyBoxProgram
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SkyMode {
+    Procedural,
+    // the procedural sky above the horizon and the skybox below it
+    Blended,
+    Skybox,
+}
+
+impl SkyMode {
+    // the skybox mode uses the ibl of the skybox, the others the one of the drawn sky
+    pub fn uses_procedural_sky(self) -> bool {
+        self != SkyMode::Skybox
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SkyMode::Procedural => SkyMode::Blended,
+            SkyMode::Blended => SkyMode::Skybox,
+            SkyMode::Skybox => SkyMode::Procedural,
+        }
+    }
+}
+
+// moves the sun and the moon with the time of day, they give the directional light and the
+// procedural sky its colour
+pub struct DayNightCycle {
+    // in hours, 0 is midnight and 12 is noon
+    pub time_of_day: f32,
+    // in hours per second
+    pub speed: f32,
+    pub paused: bool,
+    pub sun_power: f32,
+    pub moon_power: f32,
+    // tilt of the sun path from the zenith, it stays a bit to the south at noon
+    pub tilt: f32,
+    // haze of the atmosphere, 2 is a clear sky and 10 a very hazy one
+    pub turbidity: f32,
+    pub mode: SkyMode,
+}
+
+impl DayNightCycle {
+    pub fn new(time_of_day: f32) -> Self {
+        Self {
+            time_of_day,
+            // a full day in 10 minutes
+            speed: 24.0 / 600.0,
+            paused: false,
+            sun_power: 0.5,
+            moon_power: 0.08,
+            tilt: 0.3,
+            turbidity: 2.5,
+            mode: SkyMode::Blended,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        if !self.paused {
+            self.add_time(self.speed * delta_time);
+        }
+    }
+
+    pub fn add_time(&mut self, hours: f32) {
+        self.time_of_day = (self.time_of_day + hours).rem_euclid(24.0);
+    }
+
+    // from the scene to the sun, it rises in the east (+x) at 6 and sets in the west at 18
+    pub fn get_sun_position(&self) -> Vector3<f32> {
+        let angle = (self.time_of_day - 6.0) / 12.0 * PI;
+        Vector3::new(
+            angle.cos(),
+            angle.sin() * self.tilt.cos(),
+            angle.sin() * self.tilt.sin(),
+        )
+        .normalize()
+    }
+
+    // always opposite to the sun
+    pub fn get_moon_position(&self) -> Vector3<f32> {
+        -self.get_sun_position()
+    }
+
+    // 0 during the night and 1 once the sun is high enough
+    pub fn get_daylight(&self) -> f32 {
+        smoothstep(-0.1, 0.25, self.get_sun_position().y)
+    }
+
+    // brightness of the sky relative to a sunny day, the night never gets completely black
+    pub fn get_sky_brightness(&self) -> f32 {
+        (self.get_daylight() * self.sun_power / 0.5).max(0.03)
+    }
+
+    // reddish at sunrise and sunset because of the longer path through the atmosphere
+    pub fn get_sun_colour(&self) -> Vector3<f32> {
+        let warmth = smoothstep(0.0, 0.4, self.get_sun_position().y);
+        Vector3::new(1.0, 0.45, 0.2) * (1.0 - warmth) + Vector3::new(1.0, 0.97, 0.92) * warmth
+    }
+
+    // the stars turn around the same axis as the sun
+    pub fn get_star_rotation(&self) -> Matrix3<f32> {
+        let axis = Vector3::new(0.0, -self.tilt.sin(), self.tilt.cos());
+        let angle = (self.time_of_day - 6.0) / 12.0 * PI;
+        Matrix3::from_axis_angle(axis, Rad(-angle))
+    }
+
+    // the sun during the day and the moon during the night, the light fades out near the horizon
+    // so the switch between them isn't visible
+    pub fn get_light(&self) -> DirectionalLight {
+        let sun_position = self.get_sun_position();
+        let (position, colour) = if sun_position.y > 0.0 {
+            let power = self.sun_power * smoothstep(0.0, 0.1, sun_position.y);
+            (sun_position, self.get_sun_colour() * power)
+        } else {
+            let power = self.moon_power * smoothstep(0.0, 0.1, -sun_position.y);
+            (-sun_position, Vector3::new(0.6, 0.7, 1.0) * power)
+        };
+
+        let ambient = colour / 3.0;
+        let specular = colour * 0.4 + Vector3::new(0.4, 0.4, 0.4) * colour.magnitude().min(1.0);
+        DirectionalLight {
+            direction: -position,
+            ambient,
+            diffuse: colour,
+            specular,
+            casts_shadows: true,
+        }
+    }
+}
+
+// the sky of the day/night cycle drawn into a cubemap, the ambient light, the reflections and the
+// fog use it instead of the skybox so they change with the time of day
+pub struct SkyCubemap {
+    pub cubemap: Cubemap,
+    // what the cubemap was drawn with, none until it's drawn the first time
+    sun_position: Option<Vector3<f32>>,
+    sky_brightness: f32,
+    mode: SkyMode,
+}
+
+impl SkyCubemap {
+    pub fn new(display: &Display, size: u32) -> Self {
+        Self {
+            // hdr like the procedural sky
+            cubemap: Cubemap::empty_with_format(
+                display,
+                UncompressedFloatFormat::F16F16F16F16,
+                MipmapsOption::NoMipmap,
+                size,
+            )
+            .unwrap(),
+            sun_position: None,
+            sky_brightness: 0.0,
+            mode: SkyMode::Blended,
+        }
+    }
+
+    // the ibl of the procedural sky has to be refiltered after each update, so it only happens
+    // once the sky has changed enough to be noticed, the skybox doesn't move with the sun
+    pub fn needs_update(&self, sky: &DayNightCycle) -> bool {
+        let sun_position = match self.sun_position {
+            Some(sun_position) => sun_position,
+            None => return true,
+        };
+        let brightness_change =
+            (sky.get_sky_brightness() - self.sky_brightness).abs() / self.sky_brightness;
+        sky.mode != self.mode
+            || (sky.mode.uses_procedural_sky()
+                && sun_position.dot(sky.get_sun_position()) < SKY_UPDATE_ANGLE.cos())
+            || brightness_change > SKY_UPDATE_BRIGHTNESS
+    }
+
+    pub fn update(
+        &mut self,
+        display: &Display,
+        program: &SkyBoxProgram,
+        skybox: &CubeMapShader,
+        sky: &DayNightCycle,
+        ibl: &ImageBasedLighting,
+    ) {
+        // the fog reads the ibl, which is made from this cubemap
+        let fog = Fog {
+            enabled: false,
+            ..Fog::default()
+        };
+        let center = Point3::new(0.0, 0.0, 0.0);
+        for layer in FACES.iter() {
+            let matrix = get_cube_face_matrix(center, *layer, 0.1, 10.0);
+            let uniforms =
+                SkyBoxProgram::get_uniforms(&matrix, &skybox.cubemap, sky, &fog, ibl, &center);
+            let mut target =
+                SimpleFrameBuffer::new(display, self.cubemap.main_level().image(*layer)).unwrap();
+            target
+                .draw(
+                    &skybox.vertex_buffer,
+                    &skybox.index_buffer,
+                    &program.0,
+                    &uniforms,
+                    &Default::default(),
+                )
+                .unwrap();
+        }
+
+        self.sun_position = Some(sky.get_sun_position());
+        self.sky_brightness = sky.get_sky_brightness();
+        self.mode = sky.mode;
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
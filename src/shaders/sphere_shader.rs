@@ -0,0 +1,66 @@
+use glium::{Display, IndexBuffer, VertexBuffer};
+
+use crate::shaders::common::PositionalVertex;
+
+pub struct SphereShader {
+    pub vertex_buffer: VertexBuffer<PositionalVertex>,
+    pub index_buffer: IndexBuffer<u16>,
+}
+
+impl SphereShader {
+    // unit sphere, the normal of each vertex is the same as its position
+    pub fn new(display: &Display, sectors: u16, stacks: u16) -> Self {
+        let (vertices, indices) = Self::get_sphere_shape(sectors, stacks);
+        Self {
+            vertex_buffer: VertexBuffer::new(display, &vertices).unwrap(),
+            index_buffer: IndexBuffer::new(
+                display,
+                glium::index::PrimitiveType::TrianglesList,
+                &indices,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn get_sphere_shape(sectors: u16, stacks: u16) -> (Vec<PositionalVertex>, Vec<u16>) {
+        use std::f32::consts::PI;
+
+        let mut vertices = Vec::with_capacity(((sectors + 1) * (stacks + 1)) as usize);
+        for stack in 0..=stacks {
+            let stack_angle = PI / 2.0 - PI * stack as f32 / stacks as f32;
+            let y = stack_angle.sin();
+            let ring_radius = stack_angle.cos();
+
+            for sector in 0..=sectors {
+                let sector_angle = 2.0 * PI * sector as f32 / sectors as f32;
+                vertices.push(PositionalVertex {
+                    position: [
+                        ring_radius * sector_angle.cos(),
+                        y,
+                        ring_radius * sector_angle.sin(),
+                    ],
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((sectors * stacks * 6) as usize);
+        for stack in 0..stacks {
+            let current = stack * (sectors + 1);
+            let next = current + sectors + 1;
+            for sector in 0..sectors {
+                if stack != 0 {
+                    indices.extend_from_slice(&[current + sector, next + sector, current + sector + 1]);
+                }
+                if stack != stacks - 1 {
+                    indices.extend_from_slice(&[
+                        current + sector + 1,
+                        next + sector,
+                        next + sector + 1,
+                    ]);
+                }
+            }
+        }
+
+        (vertices, indices)
+    }
+}
@@ -0,0 +1,195 @@
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction},
+    Display, Surface,
+};
+
+use crate::shaders::deferred::GBuffer;
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{SsaoBlurProgram, SsaoProgram};
+
+// this has to match the define in SsaoProgram
+pub const SSAO_KERNEL_SIZE: usize = 32;
+// the blur in SsaoBlurProgram covers exactly one noise tile
+const SSAO_NOISE_SIZE: u32 = 4;
+
+pub struct SsaoPrograms<'a, 'b> {
+    pub ssao: &'a SsaoProgram,
+    pub blur: &'b SsaoBlurProgram,
+}
+
+// the kernel and the noise only have to look random, so a xorshift is enough
+struct Random(u32);
+
+impl Random {
+    // between 0 and 1
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32
+    }
+}
+
+// screen space ambient occlusion, every pixel checks how much of the hemisphere around its
+// normal is hidden behind the depth buffer, the result darkens the ambient light
+pub struct Ssao {
+    pub enabled: bool,
+    // size of the hemisphere in world units
+    pub radius: f32,
+    // keeps flat surfaces from occluding themselves
+    pub bias: f32,
+    // higher makes the occlusion darker
+    pub power: f32,
+    // view space sample offsets inside of a hemisphere pointing up the z axis
+    pub kernel: Vec<Vector3<f32>>,
+    // random rotations of the kernel around the normal, tiled over the screen
+    pub noise: Texture2d,
+    occlusion: Texture2d,
+    blurred_occlusion: Texture2d,
+    size: (u32, u32),
+    screen_quad: MainFramebufferShader,
+}
+
+impl Ssao {
+    pub fn new(display: &Display, size: (u32, u32)) -> Self {
+        let mut random = Random(0x9e37_79b9);
+
+        let kernel = (0..SSAO_KERNEL_SIZE)
+            .map(|i| {
+                let sample = Vector3::new(
+                    random.next() * 2.0 - 1.0,
+                    random.next() * 2.0 - 1.0,
+                    random.next(),
+                )
+                .normalize()
+                    * random.next();
+                // more samples close to the fragment
+                let scale = i as f32 / SSAO_KERNEL_SIZE as f32;
+                sample * (0.1 + 0.9 * scale * scale)
+            })
+            .collect();
+
+        let noise: Vec<Vec<(f32, f32)>> = (0..SSAO_NOISE_SIZE)
+            .map(|_| {
+                (0..SSAO_NOISE_SIZE)
+                    .map(|_| (random.next() * 2.0 - 1.0, random.next() * 2.0 - 1.0))
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.025,
+            power: 1.5,
+            kernel,
+            noise: Texture2d::with_format(
+                display,
+                noise,
+                UncompressedFloatFormat::F32F32,
+                MipmapsOption::NoMipmap,
+            )
+            .unwrap(),
+            occlusion: Self::create_occlusion_texture(display, size),
+            blurred_occlusion: Self::create_occlusion_texture(display, size),
+            size,
+            screen_quad: MainFramebufferShader::new(display),
+        }
+    }
+
+    fn create_occlusion_texture(display: &Display, size: (u32, u32)) -> Texture2d {
+        Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::U8,
+            MipmapsOption::NoMipmap,
+            size.0,
+            size.1,
+        )
+        .unwrap()
+    }
+
+    // the textures have to follow the window size
+    pub fn resize(&mut self, display: &Display, size: (u32, u32)) {
+        if self.size != size {
+            self.occlusion = Self::create_occlusion_texture(display, size);
+            self.blurred_occlusion = Self::create_occlusion_texture(display, size);
+            self.size = size;
+        }
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_noise_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Repeat,
+                SamplerWrapFunction::Repeat,
+                SamplerWrapFunction::Repeat,
+            ),
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..Default::default()
+        }
+    }
+
+    // read by the lighting programs, one texel per screen pixel
+    pub fn get_texture(&self) -> &Texture2d {
+        &self.blurred_occlusion
+    }
+
+    // the gbuffer has to have the depth and normals of this frame
+    pub fn update(
+        &mut self,
+        display: &Display,
+        programs: SsaoPrograms,
+        gbuffer: &GBuffer,
+        view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let params = glium::DrawParameters::default();
+
+        let mut target = SimpleFrameBuffer::new(display, &self.occlusion).unwrap();
+        let uniforms = SsaoProgram::get_uniforms(self, gbuffer, view, projection);
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.ssao.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+
+        // the noise leaves a pattern, the blur removes it without going over the edges
+        let mut target = SimpleFrameBuffer::new(display, &self.blurred_occlusion).unwrap();
+        let uniforms =
+            SsaoBlurProgram::get_uniforms(&self.occlusion, self.get_sampler(), gbuffer, projection);
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.blur.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+    }
+}
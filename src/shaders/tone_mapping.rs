@@ -0,0 +1,182 @@
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior},
+    Display, Surface,
+};
+
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{DownsampleProgram, ExposureAdaptationProgram, LuminanceProgram};
+
+// the order has to match tone_map in MainFramebufferProgram
+#[derive(Copy, Clone, Debug)]
+pub enum ToneMappingOperator {
+    Reinhard,
+    Aces,
+    Filmic,
+}
+
+impl ToneMappingOperator {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMappingOperator::Reinhard => ToneMappingOperator::Aces,
+            ToneMappingOperator::Aces => ToneMappingOperator::Filmic,
+            ToneMappingOperator::Filmic => ToneMappingOperator::Reinhard,
+        }
+    }
+}
+
+pub struct ToneMappingPrograms<'a, 'b, 'c> {
+    pub luminance: &'a LuminanceProgram,
+    pub downsample: &'b DownsampleProgram,
+    pub adaptation: &'c ExposureAdaptationProgram,
+}
+
+// size of the first luminance texture, it's halved until it gets to a single pixel
+const LUMINANCE_SIZE: u32 = 256;
+
+// brings the hdr scene into the displayable range, the exposure follows the average scene
+// luminance with a delay like an eye adapting to the dark
+pub struct ToneMapping {
+    pub operator: ToneMappingOperator,
+    pub auto_exposure: bool,
+    // used when the auto exposure is off
+    pub exposure: f32,
+    // the average luminance is mapped to this middle grey
+    pub key_value: f32,
+    // limits of the adapted luminance, so dark scenes don't get blown up
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    // how fast the exposure adapts, higher is faster
+    pub adaptation_speed: f32,
+    // log luminance of the scene, each texture is half of the previous one
+    luminance_chain: Vec<Texture2d>,
+    // the adapted luminance of the last two frames, they are swapped every frame
+    adapted_luminance: [Texture2d; 2],
+    current: usize,
+    // the first frame starts already adapted
+    adapted: bool,
+    screen_quad: MainFramebufferShader,
+}
+
+impl ToneMapping {
+    pub fn new(display: &Display) -> Self {
+        let mut luminance_chain = Vec::new();
+        let mut size = LUMINANCE_SIZE;
+        while size >= 1 {
+            luminance_chain.push(Self::create_luminance_texture(display, size));
+            size /= 2;
+        }
+
+        Self {
+            operator: ToneMappingOperator::Aces,
+            auto_exposure: true,
+            exposure: 1.0,
+            key_value: 0.18,
+            min_luminance: 0.1,
+            max_luminance: 5.0,
+            adaptation_speed: 1.5,
+            luminance_chain,
+            adapted_luminance: [
+                Self::create_luminance_texture(display, 1),
+                Self::create_luminance_texture(display, 1),
+            ],
+            current: 0,
+            adapted: false,
+            screen_quad: MainFramebufferShader::new(display),
+        }
+    }
+
+    fn create_luminance_texture(display: &Display, size: u32) -> Texture2d {
+        Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::F32,
+            MipmapsOption::NoMipmap,
+            size,
+            size,
+        )
+        .unwrap()
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_adapted_luminance(&self) -> &Texture2d {
+        &self.adapted_luminance[self.current]
+    }
+
+    // everything stays on the gpu, the scene is averaged down to one pixel and the adapted
+    // luminance moves towards it
+    pub fn update_exposure(
+        &mut self,
+        display: &Display,
+        programs: ToneMappingPrograms,
+        scene: &Texture2d,
+        delta_time: f32,
+    ) {
+        if !self.auto_exposure {
+            return;
+        }
+
+        let params = glium::DrawParameters::default();
+        let sampler = self.get_sampler();
+
+        let mut target = SimpleFrameBuffer::new(display, &self.luminance_chain[0]).unwrap();
+        let uniforms = LuminanceProgram::get_uniforms(scene, sampler);
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.luminance.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+
+        // the linear filter averages four texels with each sample
+        for level in 1..self.luminance_chain.len() {
+            let mut target = SimpleFrameBuffer::new(display, &self.luminance_chain[level]).unwrap();
+            let uniforms =
+                DownsampleProgram::get_uniforms(&self.luminance_chain[level - 1], sampler);
+            target
+                .draw(
+                    &self.screen_quad.vertex_buffer,
+                    self.screen_quad.index_buffer,
+                    &programs.downsample.0,
+                    &uniforms,
+                    &params,
+                )
+                .unwrap();
+        }
+
+        let adaptation_rate = if self.adapted {
+            1.0 - (-delta_time * self.adaptation_speed).exp()
+        } else {
+            1.0
+        };
+        self.adapted = true;
+
+        let next = 1 - self.current;
+        let mut target = SimpleFrameBuffer::new(display, &self.adapted_luminance[next]).unwrap();
+        let uniforms = ExposureAdaptationProgram::get_uniforms(
+            self.luminance_chain.last().unwrap(),
+            &self.adapted_luminance[self.current],
+            adaptation_rate,
+        );
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.adaptation.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+        self.current = next;
+    }
+}
@@ -0,0 +1,230 @@
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use glium::{
+    framebuffer::SimpleFrameBuffer,
+    texture::{MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction},
+    Display, Surface,
+};
+
+use crate::shaders::deferred::GBuffer;
+use crate::shaders::lights::LightBuffer;
+use crate::shaders::main_framebuffer_shader::MainFramebufferShader;
+use crate::shaders::programs::{
+    VolumetricLightCompositeProgram, VolumetricLightProgram, VolumetricLightTemporalProgram,
+};
+use crate::shaders::shadow_map::ShadowMaps;
+
+pub struct VolumetricLightPrograms<'a, 'b, 'c> {
+    pub raymarch: &'a VolumetricLightProgram,
+    pub temporal: &'b VolumetricLightTemporalProgram,
+    pub composite: &'c VolumetricLightCompositeProgram,
+}
+
+// light shafts of the sun and the spot lights, the air in front of every pixel is raymarched
+// through the shadow maps at half of the resolution, the noise of the ray starts is averaged
+// over the frames and the result is upsampled over the scene before the post processing
+pub struct VolumetricLight {
+    pub enabled: bool,
+    // how much light the air scatters per world unit
+    pub density: f32,
+    // between -1 and 1, higher scatters more light forwards so the shafts are brighter when
+    // looking towards the light
+    pub anisotropy: f32,
+    pub steps: i32,
+    // the rays stop here, the directional shadow map doesn't go much further anyway
+    pub max_distance: f32,
+    // how much of each new frame goes into the accumulated scattering
+    pub temporal_blend: f32,
+    scattering: Texture2d,
+    // the accumulated scattering of the last two frames, they are swapped every frame
+    history: [Texture2d; 2],
+    current: usize,
+    // the history is invalid after a resize or while it's disabled
+    history_valid: bool,
+    previous_projection_view: Matrix4<f32>,
+    frame: u32,
+    size: (u32, u32),
+    screen_quad: MainFramebufferShader,
+}
+
+impl VolumetricLight {
+    pub fn new(display: &Display, size: (u32, u32)) -> Self {
+        Self {
+            enabled: true,
+            density: 0.03,
+            anisotropy: 0.7,
+            steps: 32,
+            max_distance: 60.0,
+            temporal_blend: 0.1,
+            scattering: Self::create_scattering_texture(display, size),
+            history: [
+                Self::create_scattering_texture(display, size),
+                Self::create_scattering_texture(display, size),
+            ],
+            current: 0,
+            history_valid: false,
+            previous_projection_view: Matrix4::identity(),
+            frame: 0,
+            size,
+            screen_quad: MainFramebufferShader::new(display),
+        }
+    }
+
+    // the scattering is in rgb and the distance to the surface in alpha, for the upsampling
+    fn create_scattering_texture(display: &Display, size: (u32, u32)) -> Texture2d {
+        Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::F16F16F16F16,
+            MipmapsOption::NoMipmap,
+            (size.0 / 2).max(1),
+            (size.1 / 2).max(1),
+        )
+        .unwrap()
+    }
+
+    // the textures have to follow the window size
+    pub fn resize(&mut self, display: &Display, size: (u32, u32)) {
+        if self.size != size {
+            self.scattering = Self::create_scattering_texture(display, size);
+            self.history = [
+                Self::create_scattering_texture(display, size),
+                Self::create_scattering_texture(display, size),
+            ];
+            self.history_valid = false;
+            self.size = size;
+        }
+    }
+
+    pub fn get_sampler(&self) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+                SamplerWrapFunction::Clamp,
+            ),
+            minify_filter: MinifySamplerFilter::Linear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+            ..Default::default()
+        }
+    }
+
+    // changes the noise of the ray starts
+    pub fn get_frame(&self) -> u32 {
+        self.frame
+    }
+
+    // the gbuffer depth has to have every object of this frame and the shadow maps have to be
+    // updated
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        display: &Display,
+        programs: &VolumetricLightPrograms,
+        gbuffer: &GBuffer,
+        lights: &LightBuffer,
+        shadow_maps: &ShadowMaps,
+        view: &Point3<f32>,
+        projection_view: &Matrix4<f32>,
+    ) {
+        if !self.enabled {
+            self.history_valid = false;
+            return;
+        }
+
+        let params = glium::DrawParameters::default();
+
+        let mut target = SimpleFrameBuffer::new(display, &self.scattering).unwrap();
+        let uniforms = VolumetricLightProgram::get_uniforms(
+            self,
+            gbuffer,
+            lights,
+            shadow_maps,
+            view,
+            projection_view,
+        );
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.raymarch.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+
+        let previous = self.current;
+        self.current = 1 - self.current;
+        let mut target = SimpleFrameBuffer::new(display, &self.history[self.current]).unwrap();
+        let uniforms = VolumetricLightTemporalProgram::get_uniforms(
+            &self.scattering,
+            &self.history[previous],
+            self.get_sampler(),
+            gbuffer,
+            projection_view,
+            &self.previous_projection_view,
+            self.history_valid,
+            self.temporal_blend,
+        );
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.temporal.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+
+        self.history_valid = true;
+        self.previous_projection_view = *projection_view;
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    // adds the scattering to the hdr scene
+    pub fn draw<S: Surface>(
+        &self,
+        target: &mut S,
+        programs: &VolumetricLightPrograms,
+        gbuffer: &GBuffer,
+        view: &Point3<f32>,
+        projection_view: &Matrix4<f32>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        let params = glium::DrawParameters {
+            blend: glium::Blend {
+                color: glium::BlendingFunction::Addition {
+                    source: glium::LinearBlendingFactor::One,
+                    destination: glium::LinearBlendingFactor::One,
+                },
+                alpha: glium::BlendingFunction::AlwaysReplace,
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            ..Default::default()
+        };
+
+        let sampler = SamplerBehavior {
+            minify_filter: MinifySamplerFilter::Nearest,
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            ..self.get_sampler()
+        };
+        let uniforms = VolumetricLightCompositeProgram::get_uniforms(
+            &self.history[self.current],
+            sampler,
+            gbuffer,
+            view,
+            projection_view,
+        );
+        target
+            .draw(
+                &self.screen_quad.vertex_buffer,
+                self.screen_quad.index_buffer,
+                &programs.composite.0,
+                &uniforms,
+                &params,
+            )
+            .unwrap();
+    }
+}